@@ -1,8 +1,52 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::core::domain::storage::entities::{Durability, MetadataPolicy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Store received files under `ferris_base_path`.
+    Fs,
+    /// Write the received stream to stdout.
+    Stdout,
+    /// Store received files by content hash under `ferris_base_path`, once per
+    /// distinct content.
+    Cas,
+    /// Keep received files in memory only; they are gone when the node stops.
+    Memory,
+    /// Upload received files to an S3-compatible bucket. Needs the `s3` feature.
+    S3,
+}
+
 #[derive(Debug)]
 pub struct Config {
     pub ferris_base_path: String,
     pub ferris_port: u16,
     pub ferris_host: String,
+    pub ferris_output: OutputMode,
+    pub ferris_durability: Durability,
+    pub ferris_metadata: MetadataPolicy,
+    pub ferris_encryption_key: Option<PathBuf>,
+    pub ferris_node_name: String,
+    pub ferris_discovery: bool,
+    pub ferris_sync: bool,
+    pub ferris_relay: Option<String>,
+    pub ferris_relay_session: Option<String>,
+    pub ferris_forward_to: Option<String>,
+    pub ferris_forward_delete: bool,
+    pub ferris_forward_max_attempts: u32,
+    pub ferris_forward_queue: PathBuf,
+    pub ferris_hook: Option<PathBuf>,
+    pub ferris_hook_workers: usize,
+    pub ferris_hook_timeout: Duration,
+    pub ferris_quarantine_dir: PathBuf,
+    pub ferris_journal: PathBuf,
+    pub ferris_s3_endpoint: String,
+    pub ferris_s3_region: String,
+    pub ferris_s3_bucket: Option<String>,
+    pub ferris_s3_prefix: String,
+    pub ferris_s3_access_key: Option<String>,
+    pub ferris_s3_secret_key: Option<String>,
 }
 
 impl Config {
@@ -14,10 +58,168 @@ impl Config {
             .parse()
             .expect("FERRIS_PORT must be a valid u16");
         let ferris_host = std::env::var("FERRIS_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+        let ferris_output = match std::env::var("FERRIS_OUTPUT").as_deref() {
+            Ok("stdout") => OutputMode::Stdout,
+            Ok("cas") => OutputMode::Cas,
+            Ok("memory") => OutputMode::Memory,
+            Ok("s3") => OutputMode::S3,
+            Ok("fs") | Err(_) => OutputMode::Fs,
+            Ok(other) => panic!(
+                "FERRIS_OUTPUT must be 'fs', 'stdout', 'cas', 'memory' or 's3', got '{}'",
+                other
+            ),
+        };
+        let ferris_durability = match std::env::var("FERRIS_DURABILITY").as_deref() {
+            Ok("none") => Durability::None,
+            Ok("on-finalize") | Err(_) => Durability::OnFinalize,
+            Ok(other) => match other
+                .strip_prefix("every-")
+                .and_then(|n| n.strip_suffix("-blocks"))
+                .and_then(|n| n.parse().ok())
+            {
+                Some(blocks) if blocks > 0 => Durability::EveryBlocks(blocks),
+                _ => panic!(
+                    "FERRIS_DURABILITY must be 'none', 'on-finalize' or 'every-<N>-blocks', got '{}'",
+                    other
+                ),
+            },
+        };
+        let ferris_metadata = match std::env::var("FERRIS_METADATA").as_deref() {
+            Ok("ignore") => MetadataPolicy::Ignore,
+            Ok("safe") | Err(_) => MetadataPolicy::Safe,
+            Ok("user-xattrs") => MetadataPolicy::UserXattrs,
+            Ok("all") => MetadataPolicy::All,
+            Ok(other) => panic!(
+                "FERRIS_METADATA must be 'ignore', 'safe', 'user-xattrs' or 'all', got '{}'",
+                other
+            ),
+        };
+        let ferris_encryption_key = std::env::var_os("FERRIS_ENCRYPTION_KEY").map(PathBuf::from);
+        let ferris_node_name =
+            std::env::var("FERRIS_NODE_NAME").unwrap_or_else(|_| default_node_name());
+        // Off unless asked for: an announcing daemon tells the whole LAN it's there.
+        let ferris_discovery = std::env::var("FERRIS_DISCOVERY")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .expect("FERRIS_DISCOVERY must be 'true' or 'false'");
+        // Off unless asked for: sync lets peers list and delete stored files.
+        let ferris_sync = std::env::var("FERRIS_SYNC")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .expect("FERRIS_SYNC must be 'true' or 'false'");
+        let ferris_relay = std::env::var("FERRIS_RELAY").ok();
+        let ferris_relay_session = std::env::var("FERRIS_RELAY_SESSION").ok();
+        if ferris_relay.is_some() && ferris_relay_session.is_none() {
+            panic!("FERRIS_RELAY_SESSION must be set when FERRIS_RELAY is");
+        }
+        let ferris_forward_to = std::env::var("FERRIS_FORWARD_TO").ok();
+        if ferris_forward_to.is_some() && ferris_output != OutputMode::Fs {
+            panic!("FERRIS_FORWARD_TO requires FERRIS_OUTPUT=fs");
+        }
+        // The forwarder sends files as they are on disk, which would be ciphertext.
+        if ferris_forward_to.is_some() && ferris_encryption_key.is_some() {
+            panic!("FERRIS_FORWARD_TO can't be combined with FERRIS_ENCRYPTION_KEY");
+        }
+        let ferris_forward_delete = std::env::var("FERRIS_FORWARD_DELETE")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .expect("FERRIS_FORWARD_DELETE must be 'true' or 'false'");
+        let ferris_forward_max_attempts = std::env::var("FERRIS_FORWARD_MAX_ATTEMPTS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()
+            .expect("FERRIS_FORWARD_MAX_ATTEMPTS must be a valid u32");
+        let ferris_forward_queue = std::env::var_os("FERRIS_FORWARD_QUEUE")
+            .map(PathBuf::from)
+            .unwrap_or_else(default_forward_queue);
+        let ferris_hook = std::env::var_os("FERRIS_HOOK").map(PathBuf::from);
+        let ferris_hook_workers = std::env::var("FERRIS_HOOK_WORKERS")
+            .unwrap_or_else(|_| "4".to_string())
+            .parse()
+            .ok()
+            .filter(|workers| *workers > 0)
+            .expect("FERRIS_HOOK_WORKERS must be a positive integer");
+        let ferris_hook_timeout = std::env::var("FERRIS_HOOK_TIMEOUT")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .map(Duration::from_secs)
+            .expect("FERRIS_HOOK_TIMEOUT must be a number of seconds");
+        let ferris_quarantine_dir = std::env::var_os("FERRIS_QUARANTINE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("./quarantine"));
+        let ferris_journal = std::env::var_os("FERRIS_JOURNAL")
+            .map(PathBuf::from)
+            .unwrap_or_else(default_journal);
+        let ferris_s3_region =
+            std::env::var("FERRIS_S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        let ferris_s3_endpoint = std::env::var("FERRIS_S3_ENDPOINT")
+            .unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", ferris_s3_region));
+        let ferris_s3_bucket = std::env::var("FERRIS_S3_BUCKET").ok();
+        if ferris_s3_bucket.is_none() && ferris_output == OutputMode::S3 {
+            panic!("FERRIS_S3_BUCKET must be set when FERRIS_OUTPUT=s3");
+        }
+        let ferris_s3_prefix = std::env::var("FERRIS_S3_PREFIX").unwrap_or_default();
+        let ferris_s3_access_key = std::env::var("FERRIS_S3_ACCESS_KEY")
+            .or_else(|_| std::env::var("AWS_ACCESS_KEY_ID"))
+            .ok();
+        let ferris_s3_secret_key = std::env::var("FERRIS_S3_SECRET_KEY")
+            .or_else(|_| std::env::var("AWS_SECRET_ACCESS_KEY"))
+            .ok();
         Config {
             ferris_base_path,
             ferris_port,
             ferris_host,
+            ferris_output,
+            ferris_durability,
+            ferris_metadata,
+            ferris_encryption_key,
+            ferris_node_name,
+            ferris_discovery,
+            ferris_sync,
+            ferris_relay,
+            ferris_relay_session,
+            ferris_forward_to,
+            ferris_forward_delete,
+            ferris_forward_max_attempts,
+            ferris_forward_queue,
+            ferris_hook,
+            ferris_hook_workers,
+            ferris_hook_timeout,
+            ferris_quarantine_dir,
+            ferris_journal,
+            ferris_s3_endpoint,
+            ferris_s3_region,
+            ferris_s3_bucket,
+            ferris_s3_prefix,
+            ferris_s3_access_key,
+            ferris_s3_secret_key,
         }
     }
 }
+
+/// Transfer journal used when FERRIS_JOURNAL is not set.
+pub fn default_journal() -> PathBuf {
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".ferrisshare").join("journal.db"),
+        None => PathBuf::from("ferrisshare-journal.db"),
+    }
+}
+
+/// Pending forwards file used when FERRIS_FORWARD_QUEUE is not set.
+pub fn default_forward_queue() -> PathBuf {
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home)
+            .join(".ferrisshare")
+            .join("forward.json"),
+        None => PathBuf::from("ferrisshare-forward.json"),
+    }
+}
+
+/// Name announced on the LAN when none is configured: the host name if we can find it.
+pub fn default_node_name() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "ferrisshare".to_string())
+}
@@ -1,8 +1,45 @@
-use std::path::PathBuf;
+mod progress;
+mod queue;
+mod receive;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use clap::{Args, Parser, Subcommand};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use ferrisshare::core::domain::{
+    compression::entities::Codec,
+    discovery::{ports::DiscoveryService as _, services::MulticastDiscoveryService},
+    relay::{ports::RelayClient as _, services::RelayClientImpl},
+    sender::{
+        entities::{Block, OutgoingFile},
+        ports::SenderService as _,
+        services::{BLOCK_QUEUE, TcpSenderService, read_source},
+    },
+    storage::{
+        entities::MAX_BLOCK_SIZE,
+        services::{read_metadata, sha256_file},
+    },
+    sync::{ports::SyncService as _, services::SyncServiceImpl},
+    watch::{ports::WatchService as _, services::WatchServiceImpl},
+    wormhole::{
+        ports::WormholeService as _,
+        services::{DEFAULT_RENDEZVOUS_ADDR, WormholeServiceImpl},
+    },
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+use crate::progress::{Progress, ProgressMode};
+use crate::queue::QueueArgs;
+use crate::receive::ReceiveArgs;
+
+/// How long `send --to` waits for the named node to announce itself.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to try the receiver directly before falling back to the relay.
+const DIRECT_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Parser)]
 #[command(name = "ferris-cli")]
@@ -16,6 +53,50 @@ struct Cli {
 enum Commands {
     /// Send a file to a ferrisshare listener
     Send(SendArgs),
+    /// Receive files, then exit (no separately configured daemon needed)
+    Receive(ReceiveArgs),
+    /// Send files dropped into a folder once they stop changing
+    Watch {
+        /// folder to watch (not recursive)
+        dir: PathBuf,
+        /// remote address (host:port)
+        #[arg(long, value_name = "ADDR")]
+        to: String,
+        /// seconds without writes before a file is considered complete
+        #[arg(long, default_value_t = 5u64)]
+        stable_secs: u64,
+        /// move sent files into a sub-folder instead of leaving them in place
+        #[arg(long)]
+        move_sent: bool,
+        /// where --move-sent puts files (defaults to <DIR>/sent)
+        #[arg(long, requires = "move_sent")]
+        sent_dir: Option<PathBuf>,
+        /// also send the files already in the folder at startup
+        #[arg(long)]
+        include_existing: bool,
+    },
+    /// Make a receiver's directory match a local one (one way)
+    Sync {
+        /// local directory to mirror
+        dir: PathBuf,
+        /// remote address (host:port)
+        #[arg(long, value_name = "ADDR")]
+        to: String,
+        /// delete files the receiver has but the local directory doesn't
+        #[arg(long)]
+        delete: bool,
+        /// only print what would be done
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Persistent outbound queue for unattended senders
+    Queue(QueueArgs),
+    /// List ferrisshare nodes announcing themselves on the local network
+    Peers {
+        /// how long to listen for announcements, in seconds
+        #[arg(short, long, default_value_t = 3u64)]
+        timeout: u64,
+    },
     /// Simple ping (HELLO) for testing
     Hello {
         /// remote address (host:port)
@@ -28,19 +109,107 @@ enum Commands {
     },
 }
 
-#[derive(Args)]
+#[derive(Args, Clone)]
 struct SendArgs {
-    /// remote address
+    /// remote address (repeat to send to several receivers at once)
     #[arg(short, long, default_value = "127.0.0.1:9000")]
-    addr: String,
+    addr: Vec<String>,
+
+    /// send to a node discovered on the LAN by name instead of --addr
+    #[arg(long, value_name = "NODE", conflicts_with = "addr")]
+    to: Option<String>,
+
+    /// print a short code for the receiver instead of dialing an address;
+    /// the transfer is end-to-end encrypted with a key derived from the code
+    #[arg(short, long, conflicts_with_all = ["addr", "to"])]
+    wormhole: bool,
+
+    /// rendezvous server used to pair wormhole peers
+    #[arg(long, default_value = DEFAULT_RENDEZVOUS_ADDR)]
+    rendezvous: String,
+
+    /// relay to go through when the receiver can't be reached directly
+    #[arg(long, value_name = "ADDR")]
+    relay: Option<String>,
+
+    /// relay session shared with the receiver (not needed with --wormhole)
+    #[arg(long, value_name = "ID", requires = "relay")]
+    session: Option<String>,
 
     /// file to send
+    #[arg(
+        short,
+        long,
+        required_unless_present = "stdin",
+        conflicts_with = "stdin"
+    )]
+    file: Option<PathBuf>,
+
+    /// read the data to send from stdin (size unknown, sent as a STREAM)
+    #[arg(long, requires = "name")]
+    stdin: bool,
+
+    /// name announced to the receiver (defaults to the file name)
     #[arg(short, long)]
-    file: PathBuf,
+    name: Option<String>,
 
-    /// block size (default 1024)
-    #[arg(short = 'b', long, default_value_t = 1024u32)]
+    /// block size (default 1024, at most 16 MiB)
+    #[arg(
+        short = 'b',
+        long,
+        default_value_t = 1024u32,
+        value_parser = clap::value_parser!(u32).range(1..=MAX_BLOCK_SIZE as i64)
+    )]
     block_size: u32,
+
+    /// announce the file's hash so a receiver that already stores the same
+    /// content completes the transfer without any data
+    #[arg(long, requires = "file")]
+    dedup: bool,
+
+    /// go on from where an interrupted send of the same file stopped, when
+    /// the receiver kept what it got (hashes the file first)
+    #[arg(long, requires = "file")]
+    resume: bool,
+
+    /// compress blocks with this codec (zstd or lz4) if the receiver agrees;
+    /// blocks that don't shrink are sent as is
+    #[arg(long, value_name = "CODEC", value_parser = parse_codec, default_value = "none")]
+    compress: Codec,
+
+    /// only send what differs from the receiver's existing copy of the file
+    #[arg(long, conflicts_with_all = ["stdin", "wormhole", "relay"])]
+    delta: bool,
+
+    /// send all-zero blocks as HOLE so the receiver keeps the file sparse
+    /// (the receiver must understand HOLE)
+    #[arg(long)]
+    sparse: bool,
+
+    /// also send the file's permissions, mtime and extended attributes
+    /// (the receiver must understand META and decides what to apply)
+    #[arg(short, long, conflicts_with = "stdin")]
+    preserve: bool,
+
+    /// only print errors
+    #[arg(short, long, conflicts_with = "json")]
+    quiet: bool,
+
+    /// emit machine-readable progress events (one JSON object per line)
+    #[arg(long)]
+    json: bool,
+}
+
+impl SendArgs {
+    fn progress_mode(&self) -> ProgressMode {
+        if self.json {
+            ProgressMode::Json
+        } else if self.quiet {
+            ProgressMode::Quiet
+        } else {
+            ProgressMode::Human
+        }
+    }
 }
 
 #[tokio::main]
@@ -67,73 +236,328 @@ async fn main() -> anyhow::Result<()> {
         Commands::Send(args) => {
             send_file(args).await?;
         }
+        Commands::Receive(args) => {
+            receive::receive(args).await?;
+        }
+        Commands::Watch {
+            dir,
+            to,
+            stable_secs,
+            move_sent,
+            sent_dir,
+            include_existing,
+        } => {
+            let mut watch = WatchServiceImpl::new(TcpSenderService::default(), dir.clone(), &to)
+                .with_stable_for(Duration::from_secs(stable_secs))
+                .with_include_existing(include_existing);
+            if move_sent {
+                watch = watch.with_sent_dir(sent_dir.unwrap_or_else(|| dir.join("sent")));
+            }
+            watch
+                .run()
+                .await
+                .map_err(|e| anyhow::anyhow!(String::from(e)))?;
+        }
+        Commands::Sync {
+            dir,
+            to,
+            delete,
+            dry_run,
+        } => {
+            sync_dir(&dir, &to, delete, dry_run).await?;
+        }
+        Commands::Queue(args) => {
+            queue::queue(args).await?;
+        }
+        Commands::Peers { timeout } => {
+            list_peers(Duration::from_secs(timeout)).await?;
+        }
     }
 
     Ok(())
 }
 
 async fn send_file(args: SendArgs) -> anyhow::Result<()> {
-    let file = tokio::fs::File::open(&args.file).await?;
-    let metadata = file.metadata().await?;
-    let filesize = metadata.len();
+    let (source, filesize, default_name): (Box<dyn AsyncRead + Unpin + Send>, _, _) =
+        match &args.file {
+            Some(path) => {
+                let file = tokio::fs::File::open(path).await?;
+                let filesize = file.metadata().await?.len();
+                let name = path.file_name().and_then(|s| s.to_str()).map(String::from);
+                (Box::new(file), Some(filesize), name)
+            }
+            None => (Box::new(tokio::io::stdin()), None, None),
+        };
     let filename = args
-        .file
-        .file_name()
-        .and_then(|s| s.to_str())
-        .ok_or_else(|| anyhow::anyhow!("Invalid filename"))?
-        .to_string();
+        .name
+        .clone()
+        .or(default_name)
+        .ok_or_else(|| anyhow::anyhow!("Invalid filename"))?;
 
-    let mut stream = TcpStream::connect(&args.addr).await?;
+    if args.delta {
+        return send_delta(&args, &filename).await;
+    }
+    let file = OutgoingFile {
+        filename,
+        filesize,
+        sha256: match (&args.file, args.dedup || args.resume) {
+            (Some(path), true) => Some(sha256_file(path).await?),
+            _ => None,
+        },
+        metadata: match (&args.file, args.preserve) {
+            (Some(path), true) => Some(read_metadata(path).await?),
+            _ => None,
+        },
+    };
+    if args.addr.len() > 1 {
+        return fan_out(args, source, file).await;
+    }
 
-    // send HELLO
-    let hello = format!("HELLO {} {}\n", filename, filesize);
-    stream.write_all(hello.as_bytes()).await?;
+    let (tx, rx) = mpsc::channel(BLOCK_QUEUE);
+    let mut progress = Progress::new(args.progress_mode(), &file.filename, file.filesize);
+    let transfer = async {
+        if args.wormhole {
+            let mut wormhole = WormholeServiceImpl::new(&args.rendezvous);
+            if let Some(relay) = &args.relay {
+                wormhole = wormhole.with_relay(relay);
+            }
+            let stream = wormhole
+                .connect_as_sender(|code| progress.wormhole_code(&code.to_string()))
+                .await
+                .map_err(|e| anyhow::anyhow!(String::from(e)))?;
+            return send_over(stream, &file, &args, rx, &mut progress).await;
+        }
 
-    // wait for OK response (simple)
-    let mut resp_buf = vec![0u8; 1024];
-    let n = stream.read(&mut resp_buf).await?;
-    if n > 0 {
-        println!("Server: {}", String::from_utf8_lossy(&resp_buf[..n]).trim());
+        let addr = match &args.to {
+            Some(node) => MulticastDiscoveryService::default()
+                .resolve(node, DISCOVERY_TIMEOUT)
+                .await
+                .map_err(|e| anyhow::anyhow!(String::from(e)))?
+                .addr
+                .to_string(),
+            None => args.addr[0].clone(),
+        };
+        let stream = connect(&args, &addr).await?;
+        send_over(stream, &file, &args, rx, &mut progress).await
+    };
+    let (_, result) = tokio::join!(
+        read_source(source, args.block_size as usize, vec![tx]),
+        transfer
+    );
+    match &result {
+        Ok(()) => progress.finish(Ok(())),
+        Err(e) => progress.finish(Err(&e.to_string())),
     }
+    result
+}
 
-    // stream file and send YEET commands + binary blocks
-    let mut reader = tokio::fs::File::open(&args.file).await?;
-    let mut index: u64 = 0;
-    let mut buf = vec![0u8; args.block_size as usize];
+/// Send `--file` as a delta against the receiver's existing copy.
+async fn send_delta(args: &SendArgs, filename: &str) -> anyhow::Result<()> {
+    let path = args
+        .file
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("--delta needs --file"))?;
+    if args.addr.len() > 1 {
+        anyhow::bail!("--delta only works with a single --addr");
+    }
+    let addr = match &args.to {
+        Some(node) => MulticastDiscoveryService::default()
+            .resolve(node, DISCOVERY_TIMEOUT)
+            .await
+            .map_err(|e| anyhow::anyhow!(String::from(e)))?
+            .addr
+            .to_string(),
+        None => args.addr[0].clone(),
+    };
 
-    loop {
-        let n = reader.read(&mut buf).await?;
-        if n == 0 {
-            break;
-        }
+    let report = TcpSenderService::default()
+        .with_block_size(args.block_size as usize)
+        .with_metadata(args.preserve)
+        .send_delta(&addr, path, filename)
+        .await
+        .map_err(|e| anyhow::anyhow!(String::from(e)))?;
+    progress::delta_report(args.progress_mode(), filename, report.bytes, report.reused);
+    Ok(())
+}
 
-        // send YEET header
-        // checksum placeholder 0 for now
-        let yeet = format!("YEET {} {} {}\n", index, n, 0);
-        stream.write_all(yeet.as_bytes()).await?;
+/// Send the same source to every `--addr` concurrently. The source is read
+/// once; a failing receiver is dropped without affecting the others.
+async fn fan_out(
+    args: SendArgs,
+    source: Box<dyn AsyncRead + Unpin + Send>,
+    file: OutgoingFile,
+) -> anyhow::Result<()> {
+    if args.relay.is_some() {
+        anyhow::bail!("--relay only works with a single --addr");
+    }
+
+    let args = Arc::new(args);
+    let file = Arc::new(file);
+    let mut queues = Vec::new();
+    let mut transfers = JoinSet::new();
+    for (i, addr) in args.addr.iter().enumerate() {
+        let (tx, rx) = mpsc::channel(BLOCK_QUEUE);
+        queues.push(tx);
 
-        // send binary block (raw bytes then newline)
-        stream.write_all(&buf[..n]).await?;
-        stream.write_all(b"\n").await?;
+        let args = args.clone();
+        let addr = addr.clone();
+        let file = file.clone();
+        transfers.spawn(async move {
+            let mut progress =
+                Progress::new(args.progress_mode(), &file.filename, file.filesize).with_peer(&addr);
+            let result = async {
+                let stream = connect(&args, &addr).await?;
+                send_over(stream, &file, &args, rx, &mut progress).await
+            }
+            .await;
+            match &result {
+                Ok(()) => progress.finish(Ok(())),
+                Err(e) => progress.finish(Err(&e.to_string())),
+            }
+            (i, addr, result)
+        });
+    }
 
-        // read ack
-        let n = stream.read(&mut resp_buf).await?;
-        if n > 0 {
-            println!("Server: {}", String::from_utf8_lossy(&resp_buf[..n]).trim());
+    let (_, mut outcomes) = tokio::join!(
+        read_source(source, args.block_size as usize, queues),
+        async {
+            let mut outcomes = Vec::new();
+            while let Some(joined) = transfers.join_next().await {
+                outcomes.push(joined.expect("transfer task panicked"));
+            }
+            outcomes
         }
+    );
+    outcomes.sort_by_key(|(i, _, _)| *i);
 
-        index += 1;
+    let outcomes: Vec<(String, Result<(), String>)> = outcomes
+        .into_iter()
+        .map(|(_, addr, result)| (addr, result.map_err(|e| e.to_string())))
+        .collect();
+    let failed = outcomes.iter().filter(|(_, r)| r.is_err()).count();
+    progress::report(args.progress_mode(), &outcomes);
+    if failed > 0 {
+        anyhow::bail!("{} of {} transfers failed", failed, outcomes.len());
     }
+    Ok(())
+}
 
-    // send MISSION-ACCOMPLISHED
-    stream.write_all(b"MISSION-ACCOMPLISHED\n").await?;
-    let n = stream.read(&mut resp_buf).await?;
-    if n > 0 {
-        println!("Server: {}", String::from_utf8_lossy(&resp_buf[..n]).trim());
+/// Dial the receiver, falling back to the relay session if that fails.
+async fn connect(args: &SendArgs, addr: &str) -> anyhow::Result<TcpStream> {
+    let direct = match tokio::time::timeout(DIRECT_TIMEOUT, TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => return Ok(stream),
+        Ok(Err(e)) => anyhow::anyhow!("{}: {}", addr, e),
+        Err(_) => anyhow::anyhow!("{}: timed out", addr),
+    };
+    let Some(relay) = &args.relay else {
+        return Err(direct);
+    };
+    let session = args
+        .session
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("--relay needs --session unless --wormhole is used"))?;
+
+    eprintln!(
+        "Direct connection failed ({}), using relay {}",
+        direct, relay
+    );
+    RelayClientImpl::new(relay)
+        .dial(session)
+        .await
+        .map_err(|e| anyhow::anyhow!(String::from(e)))
+}
+
+/// Run one transfer over a fresh connection to the receiver, with blocks
+/// compressed or sent as holes according to `--compress` and `--sparse`.
+async fn send_over<S>(
+    stream: S,
+    file: &OutgoingFile,
+    args: &SendArgs,
+    blocks: mpsc::Receiver<Block>,
+    progress: &mut Progress,
+) -> anyhow::Result<()>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    TcpSenderService::default()
+        .with_block_size(args.block_size as usize)
+        .with_compression(args.compress)
+        .with_sparse(args.sparse)
+        .with_resume(args.resume)
+        .send_over(stream, file, blocks, progress)
+        .await
+        .map_err(|e| anyhow::anyhow!(String::from(e)))?;
+    Ok(())
+}
+
+async fn sync_dir(dir: &Path, addr: &str, delete: bool, dry_run: bool) -> anyhow::Result<()> {
+    let sync = SyncServiceImpl::new(TcpSenderService::default());
+
+    if dry_run {
+        let plan = sync
+            .plan(dir, addr, delete)
+            .await
+            .map_err(|e| anyhow::anyhow!(String::from(e)))?;
+        for entry in &plan.upload {
+            println!("upload  {} ({} bytes)", entry.path, entry.size);
+        }
+        for path in &plan.delete {
+            println!("delete  {}", path);
+        }
+        for path in &plan.skipped {
+            println!("skip    {} (whitespace in path)", path);
+        }
+        eprintln!("{} file(s) unchanged", plan.unchanged);
+        return Ok(());
     }
 
-    // send BYE-RIS
-    stream.write_all(b"BYE-RIS\n").await?;
+    let report = sync
+        .sync(dir, addr, delete)
+        .await
+        .map_err(|e| anyhow::anyhow!(String::from(e)))?;
+    for path in &report.uploaded {
+        println!("uploaded  {}", path);
+    }
+    for path in &report.deleted {
+        println!("deleted   {}", path);
+    }
+    for (path, reason) in &report.failed {
+        eprintln!("failed    {}: {}", path, reason);
+    }
+    eprintln!(
+        "{} uploaded, {} deleted, {} unchanged, {} failed",
+        report.uploaded.len(),
+        report.deleted.len(),
+        report.unchanged,
+        report.failed.len()
+    );
+    if !report.failed.is_empty() {
+        anyhow::bail!("sync incomplete");
+    }
+    Ok(())
+}
+
+async fn list_peers(timeout: Duration) -> anyhow::Result<()> {
+    let peers = MulticastDiscoveryService::default()
+        .discover(timeout)
+        .await
+        .map_err(|e| anyhow::anyhow!(String::from(e)))?;
 
+    if peers.is_empty() {
+        eprintln!("No peers found.");
+        return Ok(());
+    }
+    for peer in peers {
+        println!(
+            "{:<24} {:<22} {}",
+            peer.name,
+            peer.addr,
+            peer.capabilities.join(",")
+        );
+    }
     Ok(())
 }
+
+fn parse_codec(s: &str) -> Result<Codec, String> {
+    Codec::try_from(s).map_err(String::from)
+}
@@ -0,0 +1,359 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use ferrisshare::core::domain::sender::ports::TransferObserver;
+use serde_json::json;
+
+/// How transfer progress is reported to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Single live line on stderr, refreshed on every acknowledged block.
+    Human,
+    /// Nothing but errors.
+    Quiet,
+    /// One JSON event per line on stdout, for scripts.
+    Json,
+}
+
+/// Tracks acknowledged bytes for one transfer and renders progress.
+pub struct Progress {
+    mode: ProgressMode,
+    filename: String,
+    // None when streaming data of unknown length
+    total_bytes: Option<u64>,
+    acked_bytes: u64,
+    acked_blocks: u64,
+    // bytes the receiver already had; they count towards the percentage
+    // but were not sent, so not towards the throughput
+    skipped_bytes: u64,
+    started: Instant,
+    // a `\r` progress line is on screen and needs a newline before other output
+    line_open: bool,
+    // receiver this transfer goes to, when sending to several at once
+    peer: Option<String>,
+    // last 10% step reported for a labelled transfer
+    reported_decile: u64,
+}
+
+impl Progress {
+    pub fn new(mode: ProgressMode, filename: &str, total_bytes: Option<u64>) -> Self {
+        Progress {
+            mode,
+            filename: filename.to_string(),
+            total_bytes,
+            acked_bytes: 0,
+            acked_blocks: 0,
+            skipped_bytes: 0,
+            started: Instant::now(),
+            line_open: false,
+            peer: None,
+            reported_decile: 0,
+        }
+    }
+
+    /// Label every event with the receiver, for fan-out transfers. Human output
+    /// then switches to one line per 10% so concurrent transfers don't overwrite
+    /// each other's progress line.
+    pub fn with_peer(mut self, peer: &str) -> Self {
+        self.peer = Some(peer.to_string());
+        self
+    }
+
+    /// Show the wormhole code the receiver has to type. Printed in every mode,
+    /// since the transfer cannot happen without it.
+    pub fn wormhole_code(&mut self, code: &str) {
+        match self.mode {
+            ProgressMode::Json => self.emit(json!({ "event": "code", "code": code })),
+            ProgressMode::Quiet => eprintln!("{}", code),
+            ProgressMode::Human => {
+                eprintln!("Wormhole code is: {}", code);
+                eprintln!(
+                    "On the other computer, run: ferris-cli receive --code {}",
+                    code
+                );
+            }
+        }
+    }
+
+    /// Called when the transfer ended, successfully or not.
+    ///
+    /// Errors themselves are reported by the caller; this only closes the display.
+    pub fn finish(&mut self, outcome: Result<(), &str>) {
+        let elapsed = self.started.elapsed();
+        let throughput = throughput(self.acked_bytes, elapsed);
+
+        match self.mode {
+            ProgressMode::Quiet => {}
+            ProgressMode::Json => self.emit(json!({
+                "event": "done",
+                "file": self.filename,
+                "success": outcome.is_ok(),
+                "error": outcome.err(),
+                "bytes_sent": self.acked_bytes,
+                "bytes_skipped": self.skipped_bytes,
+                "blocks": self.acked_blocks,
+                "elapsed_secs": elapsed.as_secs_f64(),
+                "throughput_bps": throughput as u64,
+            })),
+            ProgressMode::Human => {
+                self.close_line();
+                if outcome.is_ok() {
+                    let to = self
+                        .peer
+                        .as_ref()
+                        .map(|peer| format!(" to {}", peer))
+                        .unwrap_or_default();
+                    eprintln!(
+                        "Sent {}{} ({}) in {} at {}/s",
+                        self.filename,
+                        to,
+                        format_bytes(self.acked_bytes),
+                        format_duration(elapsed),
+                        format_bytes(throughput as u64)
+                    );
+                }
+            }
+        }
+    }
+
+    fn emit(&self, mut event: serde_json::Value) {
+        if let Some(peer) = &self.peer {
+            event["peer"] = json!(peer);
+        }
+        emit(event);
+    }
+
+    fn close_line(&mut self) {
+        if self.line_open {
+            eprintln!();
+            self.line_open = false;
+        }
+    }
+
+    /// Bytes the receiver has: sent or already there.
+    fn done_bytes(&self) -> u64 {
+        self.acked_bytes + self.skipped_bytes
+    }
+
+    fn percent(&self) -> Option<f64> {
+        let total_bytes = self.total_bytes?;
+        if total_bytes == 0 {
+            return Some(100.0);
+        }
+        Some(self.done_bytes() as f64 * 100.0 / total_bytes as f64)
+    }
+
+    fn eta(&self, throughput: f64) -> Option<Duration> {
+        let total_bytes = self.total_bytes?;
+        if throughput <= 0.0 {
+            return None;
+        }
+        let remaining = total_bytes.saturating_sub(self.done_bytes());
+        Some(Duration::from_secs_f64(remaining as f64 / throughput))
+    }
+}
+
+impl TransferObserver for Progress {
+    /// Server replies that are not block acks (OK, SUCCESS, ...).
+    fn server_reply(&mut self, line: &str) {
+        match self.mode {
+            ProgressMode::Quiet => {}
+            ProgressMode::Json => self.emit(json!({ "event": "reply", "line": line })),
+            ProgressMode::Human => {
+                self.close_line();
+                match &self.peer {
+                    Some(peer) => eprintln!("Server {}: {}", peer, line),
+                    None => eprintln!("Server: {}", line),
+                }
+            }
+        }
+    }
+
+    /// Called once the receiver accepted the transfer.
+    fn start(&mut self) {
+        self.started = Instant::now();
+        if self.mode == ProgressMode::Json {
+            self.emit(json!({
+                "event": "start",
+                "file": self.filename,
+                "total_bytes": self.total_bytes,
+            }));
+        }
+    }
+
+    /// Called once the receiver said how much of an interrupted transfer it kept.
+    fn blocks_skipped(&mut self, count: u64, bytes: u64) {
+        self.skipped_bytes += bytes;
+        match self.mode {
+            ProgressMode::Json => self.emit(json!({
+                "event": "skipped",
+                "blocks": count,
+                "bytes": bytes,
+            })),
+            ProgressMode::Quiet => {}
+            ProgressMode::Human => {
+                self.close_line();
+                eprintln!(
+                    "Receiver already has {} of {}, resuming",
+                    format_bytes(bytes),
+                    self.filename
+                );
+            }
+        }
+    }
+
+    /// Called for every `OK-HOUSTEN` received from the server.
+    fn block_acked(&mut self, index: u64, bytes: u64) {
+        self.acked_blocks += 1;
+        self.acked_bytes += bytes;
+
+        let elapsed = self.started.elapsed();
+        let throughput = throughput(self.acked_bytes, elapsed);
+        let eta = self.eta(throughput);
+
+        match self.mode {
+            ProgressMode::Quiet => {}
+            ProgressMode::Json => self.emit(json!({
+                "event": "progress",
+                "block": index,
+                "bytes_sent": self.acked_bytes,
+                "total_bytes": self.total_bytes,
+                "percent": self.percent(),
+                "throughput_bps": throughput as u64,
+                "eta_secs": eta.map(|d| d.as_secs()),
+            })),
+            ProgressMode::Human if self.peer.is_some() => {
+                let decile = self.percent().map_or(0, |p| p as u64 / 10);
+                if decile > self.reported_decile {
+                    self.reported_decile = decile;
+                    eprintln!(
+                        "{} -> {}: {}% {}/s",
+                        self.filename,
+                        self.peer.as_deref().unwrap_or_default(),
+                        decile * 10,
+                        format_bytes(throughput as u64)
+                    );
+                }
+            }
+            ProgressMode::Human => {
+                match (self.percent(), self.total_bytes) {
+                    (Some(percent), Some(total_bytes)) => {
+                        let eta = eta
+                            .map(format_duration)
+                            .unwrap_or_else(|| "--:--".to_string());
+                        eprint!(
+                            "\r{} {:>5.1}% {}/{} {}/s ETA {}   ",
+                            self.filename,
+                            percent,
+                            format_bytes(self.done_bytes()),
+                            format_bytes(total_bytes),
+                            format_bytes(throughput as u64),
+                            eta
+                        );
+                    }
+                    _ => eprint!(
+                        "\r{} {} {}/s   ",
+                        self.filename,
+                        format_bytes(self.acked_bytes),
+                        format_bytes(throughput as u64)
+                    ),
+                }
+                let _ = std::io::stderr().flush();
+                self.line_open = true;
+            }
+        }
+    }
+}
+
+/// Final report of a fan-out transfer: which receivers got the file.
+pub fn report(mode: ProgressMode, outcomes: &[(String, Result<(), String>)]) {
+    match mode {
+        ProgressMode::Json => emit(json!({
+            "event": "summary",
+            "succeeded": outcomes
+                .iter()
+                .filter(|(_, r)| r.is_ok())
+                .map(|(peer, _)| peer)
+                .collect::<Vec<_>>(),
+            "failed": outcomes
+                .iter()
+                .filter_map(|(peer, r)| r.as_ref().err().map(|e| json!({ "peer": peer, "error": e })))
+                .collect::<Vec<_>>(),
+        })),
+        // Failures are worth a line even in quiet mode.
+        ProgressMode::Quiet => {
+            for (peer, result) in outcomes {
+                if let Err(e) = result {
+                    eprintln!("failed  {}: {}", peer, e);
+                }
+            }
+        }
+        ProgressMode::Human => {
+            for (peer, result) in outcomes {
+                match result {
+                    Ok(()) => eprintln!("ok      {}", peer),
+                    Err(e) => eprintln!("failed  {}: {}", peer, e),
+                }
+            }
+        }
+    }
+}
+
+/// Final report of a delta transfer: how much went over the wire and how much
+/// the receiver rebuilt from its existing copy.
+pub fn delta_report(mode: ProgressMode, filename: &str, sent: u64, reused: u64) {
+    match mode {
+        ProgressMode::Json => emit(json!({
+            "event": "done",
+            "file": filename,
+            "success": true,
+            "bytes_sent": sent,
+            "bytes_reused": reused,
+        })),
+        ProgressMode::Quiet => {}
+        ProgressMode::Human => eprintln!(
+            "Sent {} as a delta: {} sent, {} reused",
+            filename,
+            format_bytes(sent),
+            format_bytes(reused)
+        ),
+    }
+}
+
+fn emit(event: serde_json::Value) {
+    let mut out = std::io::stdout().lock();
+    let _ = writeln!(out, "{}", event);
+    let _ = out.flush();
+}
+
+fn throughput(bytes: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs <= 0.0 {
+        return 0.0;
+    }
+    bytes as f64 / secs
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+
+use ferrisshare::{
+    core::domain::{
+        forward::entities::RetryPolicy,
+        queue::{
+            entities::{JobState, unix_now},
+            ports::QueueService as _,
+            services::QueueServiceImpl,
+        },
+        sender::services::TcpSenderService,
+        storage::entities::MAX_BLOCK_SIZE,
+    },
+    infra::repositories::json::json_queue_repository::JsonQueueRepository,
+};
+
+#[derive(Args)]
+pub struct QueueArgs {
+    /// journal file holding the queue (defaults to ~/.ferrisshare/queue.json)
+    #[arg(long, global = true)]
+    journal: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: QueueCommand,
+}
+
+#[derive(Subcommand)]
+enum QueueCommand {
+    /// Queue a file for delivery
+    Add {
+        /// file to send
+        file: PathBuf,
+        /// remote address (host:port)
+        #[arg(long, value_name = "ADDR")]
+        to: String,
+        /// name announced to the receiver (defaults to the file name)
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// Deliver queued files, retrying failures with exponential backoff
+    Run {
+        /// exit once no job is pending instead of waiting for new ones
+        #[arg(long)]
+        until_idle: bool,
+        /// attempts before a job is marked failed (0 = retry forever)
+        #[arg(long, default_value_t = 10u32)]
+        max_attempts: u32,
+        /// block size (at most 16 MiB)
+        #[arg(
+            short = 'b',
+            long,
+            default_value_t = 1024usize,
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new()
+                .range(1..=MAX_BLOCK_SIZE as u64)
+        )]
+        block_size: usize,
+    },
+    /// Show every job and its state
+    Status,
+}
+
+fn default_journal() -> PathBuf {
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".ferrisshare").join("queue.json"),
+        None => PathBuf::from("ferrisshare-queue.json"),
+    }
+}
+
+pub async fn queue(args: QueueArgs) -> anyhow::Result<()> {
+    let journal = args.journal.unwrap_or_else(default_journal);
+    let repository = JsonQueueRepository::new(journal);
+
+    match args.command {
+        QueueCommand::Add { file, to, name } => {
+            let filename = name
+                .or_else(|| file.file_name().and_then(|s| s.to_str()).map(String::from))
+                .ok_or_else(|| anyhow::anyhow!("Invalid filename"))?;
+            let job = QueueServiceImpl::new(repository, TcpSenderService::default())
+                .add(&file, &to, &filename)
+                .await
+                .map_err(|e| anyhow::anyhow!(String::from(e)))?;
+            println!("Queued job {}: {} -> {}", job.id, job.filename, job.addr);
+        }
+        QueueCommand::Run {
+            until_idle,
+            max_attempts,
+            block_size,
+        } => {
+            // A retry goes on from what earlier attempts delivered.
+            let sender = TcpSenderService::default()
+                .with_block_size(block_size)
+                .with_resume(true);
+            QueueServiceImpl::new(repository, sender)
+                .with_policy(RetryPolicy {
+                    max_attempts,
+                    ..RetryPolicy::default()
+                })
+                .run(until_idle)
+                .await
+                .map_err(|e| anyhow::anyhow!(String::from(e)))?;
+        }
+        QueueCommand::Status => {
+            let jobs = QueueServiceImpl::new(repository, TcpSenderService::default())
+                .status()
+                .await
+                .map_err(|e| anyhow::anyhow!(String::from(e)))?;
+            if jobs.is_empty() {
+                eprintln!("Queue is empty.");
+                return Ok(());
+            }
+            let now = unix_now();
+            for job in jobs {
+                let detail = match (&job.state, &job.last_error) {
+                    (JobState::Pending, Some(e)) if job.next_attempt_at > now => {
+                        format!("retry in {}s: {}", job.next_attempt_at - now, e)
+                    }
+                    (JobState::Running { worker, .. }, _) => format!("by worker {}", worker),
+                    (_, Some(e)) => e.clone(),
+                    (_, None) => String::new(),
+                };
+                let state = match job.state {
+                    JobState::Pending => "pending",
+                    JobState::Running { .. } => "running",
+                    JobState::Done => "done",
+                    JobState::Failed => "failed",
+                };
+                let row = format!(
+                    "{:>4}  {:<8} {:>3}  {:<22} {:<24} {}",
+                    job.id, state, job.attempts, job.addr, job.filename, detail
+                );
+                println!("{}", row.trim_end());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use clap::Args;
+use tokio::{net::TcpStream, sync::mpsc};
+
+use ferrisshare::{
+    application::config::default_node_name,
+    core::domain::{
+        command::{
+            ports::TransferGate,
+            services::{AcceptAll, CommandServiceImpl},
+        },
+        discovery::{
+            entities::PeerAnnouncement, ports::DiscoveryService as _,
+            services::MulticastDiscoveryService,
+        },
+        network::{ports::NetworkService as _, services::NetworkServiceImpl},
+        relay::services::RelayClientImpl,
+        storage::ports::StorageRepository,
+        wormhole::{
+            entities::WormholeCode,
+            ports::WormholeService as _,
+            services::{DEFAULT_RENDEZVOUS_ADDR, WormholeServiceImpl},
+        },
+    },
+    infra::{
+        gates::tty_transfer_gate::TtyTransferGate,
+        repositories::{
+            fs::fs_storage_repository::FSStorageRepository,
+            stdout::stdout_storage_repository::StdoutStorageRepository,
+        },
+    },
+};
+
+#[derive(Args)]
+pub struct ReceiveArgs {
+    /// address to listen on
+    #[arg(long, default_value = "0.0.0.0")]
+    host: String,
+
+    /// port to listen on
+    #[arg(short, long, default_value_t = 9000u16)]
+    port: u16,
+
+    /// directory where received files are stored
+    #[arg(short, long, default_value = ".", conflicts_with = "stdout")]
+    dir: PathBuf,
+
+    /// write the received data to stdout instead of a file
+    #[arg(long)]
+    stdout: bool,
+
+    /// number of transfers to accept before exiting
+    #[arg(short = 'n', long, default_value_t = 1u64)]
+    count: u64,
+
+    /// ask on the terminal before accepting each transfer
+    #[arg(short, long)]
+    interactive: bool,
+
+    /// peer IP accepted without prompting (repeatable)
+    #[arg(long = "trust", value_name = "IP", requires = "interactive")]
+    trusted: Vec<IpAddr>,
+
+    /// name announced on the LAN (defaults to the host name)
+    #[arg(long)]
+    node_name: Option<String>,
+
+    /// let `cli sync` list and delete the files in the directory
+    #[arg(long, conflicts_with = "stdout")]
+    sync: bool,
+
+    /// don't announce this receiver on the LAN
+    #[arg(long)]
+    no_announce: bool,
+
+    /// receive a single transfer from a wormhole sender, using the code it printed
+    #[arg(short, long, conflicts_with_all = ["port", "count"])]
+    code: Option<String>,
+
+    /// rendezvous server used to pair wormhole peers
+    #[arg(long, default_value = DEFAULT_RENDEZVOUS_ADDR)]
+    rendezvous: String,
+
+    /// also accept transfers through this relay, for senders that can't reach us
+    #[arg(
+        long,
+        value_name = "ADDR",
+        requires = "session",
+        conflicts_with = "code"
+    )]
+    relay: Option<String>,
+
+    /// relay session shared with the sender
+    #[arg(long, value_name = "ID", requires = "relay")]
+    session: Option<String>,
+}
+
+/// Run the same listener stack as the `ferrisshare` daemon until `count`
+/// transfers have completed.
+pub async fn receive(args: ReceiveArgs) -> anyhow::Result<()> {
+    if args.stdout {
+        with_gate(&args, StdoutStorageRepository::new()).await
+    } else {
+        let dir = args
+            .dir
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid directory"))?
+            .to_string();
+        with_gate(&args, FSStorageRepository::new(dir)).await
+    }
+}
+
+async fn with_gate<S>(args: &ReceiveArgs, storage_repo: S) -> anyhow::Result<()>
+where
+    S: StorageRepository + Clone + Send + Sync + 'static,
+{
+    if args.interactive {
+        let gate = TtyTransferGate::new(args.trusted.clone());
+        serve(args, storage_repo, gate).await
+    } else {
+        serve(args, storage_repo, AcceptAll).await
+    }
+}
+
+async fn serve<S, G>(args: &ReceiveArgs, storage_repo: S, gate: G) -> anyhow::Result<()>
+where
+    S: StorageRepository + Clone + Send + Sync + 'static,
+    G: TransferGate + Clone + 'static,
+{
+    let (tx, rx) = mpsc::channel::<TcpStream>(1);
+
+    let command_service = CommandServiceImpl::new(storage_repo)
+        .with_gate(gate)
+        .with_sync(args.sync);
+    let network_service = NetworkServiceImpl::new(command_service).with_max_transfers(args.count);
+
+    if let Some(code) = &args.code {
+        let code =
+            WormholeCode::try_from(code.as_str()).map_err(|e| anyhow::anyhow!(String::from(e)))?;
+        let (stream, peer) = WormholeServiceImpl::new(&args.rendezvous)
+            .connect_as_receiver(&code)
+            .await
+            .map_err(|e| anyhow::anyhow!(String::from(e)))?;
+        eprintln!("Wormhole established with {}", peer);
+        if !network_service
+            .handle_connection(stream, Some(peer))
+            .await?
+        {
+            anyhow::bail!("wormhole transfer did not complete");
+        }
+        return Ok(());
+    }
+
+    if let (Some(relay), Some(session)) = (&args.relay, &args.session) {
+        let relay = RelayClientImpl::new(relay);
+        let session = session.clone();
+        let tx = tx.clone();
+        // Ends with the process once the handler returns.
+        tokio::spawn(async move {
+            if let Err(e) = relay.listen(&session, tx).await {
+                eprintln!("Relay error: {}", String::from(e));
+            }
+        });
+    }
+
+    let addr = format!("{}:{}", args.host, args.port);
+    let listener_service = network_service.clone();
+    let listener = tokio::spawn(async move { listener_service.listener(&addr, tx).await });
+
+    if !args.no_announce {
+        let name = args.node_name.clone().unwrap_or_else(default_node_name);
+        let announcement = PeerAnnouncement::new(&name, args.port, &PeerAnnouncement::CAPABILITIES);
+        // Ends with the process once the handler returns.
+        tokio::spawn(async move {
+            if let Err(e) = MulticastDiscoveryService::default()
+                .announce(announcement)
+                .await
+            {
+                eprintln!("Discovery error: {}", String::from(e));
+            }
+        });
+    }
+
+    // The handler returns once `count` transfers completed; the listener
+    // only ever returns on error (e.g. the port is already in use).
+    tokio::select! {
+        handled = network_service.handler(rx) => handled?,
+        listened = listener => {
+            listened?.map_err(|e| anyhow::anyhow!(String::from(e)))?;
+        }
+    }
+
+    Ok(())
+}
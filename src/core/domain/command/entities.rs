@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 #[derive(Debug)]
 pub enum CommandError {
     InvalidCommand,
@@ -12,3 +14,33 @@ impl From<CommandError> for String {
         }
     }
 }
+
+/// What a peer asks of the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferKind {
+    /// Store a file: HELLO, STREAM, RESUME or DELTA.
+    Receive,
+    /// Remove a stored file: DELETE.
+    Delete,
+    /// List the stored files: MANIFEST. Has no filename.
+    Manifest,
+}
+
+/// A request announced by a peer, before it is accepted: a transfer (HELLO,
+/// STREAM, ...) or one of the sync commands, DELETE and MANIFEST.
+#[derive(Debug, Clone)]
+pub struct IncomingTransfer {
+    pub kind: TransferKind,
+    pub peer: Option<SocketAddr>,
+    pub filename: String,
+    // None for STREAM transfers
+    pub filesize: Option<u64>,
+    // Content hash from HELLO or RESUME, if the sender announced one
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferDecision {
+    Accept,
+    Reject(String),
+}
@@ -1,14 +1,18 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use crate::core::domain::{
-    command::entities::CommandError,
+    command::entities::{CommandError, IncomingTransfer, TransferDecision},
+    delta::entities::BlockSignature,
     network::entities::{ProtocolMessage, TransferState},
+    storage::entities::ManifestEntry,
 };
 
 pub trait CommandService: Send + Sync {
     fn execute_protocol_command(
         &self,
         state: Arc<tokio::sync::Mutex<TransferState>>,
+        peer: Option<SocketAddr>,
         msg: &ProtocolMessage,
     ) -> impl Future<Output = Result<ProtocolMessage, CommandError>> + Send + Sync;
     fn process_binary_data(
@@ -16,4 +20,37 @@ pub trait CommandService: Send + Sync {
         state: Arc<tokio::sync::Mutex<TransferState>>,
         data: &[u8],
     ) -> impl Future<Output = Result<ProtocolMessage, CommandError>>;
+    /// Record the transfer in progress, if any, as failed for `reason`. Called
+    /// when the connection ends before it completed.
+    fn abandon(
+        &self,
+        state: Arc<tokio::sync::Mutex<TransferState>>,
+        reason: &str,
+    ) -> impl Future<Output = ()> + Send;
+    /// Files currently stored, sent back in reply to MANIFEST.
+    fn manifest(&self) -> impl Future<Output = Result<Vec<ManifestEntry>, CommandError>> + Send;
+    /// Number of full blocks of the stored `filename`, announced in reply
+    /// to DELTA before their signatures. 0 when there is no such file.
+    fn signature_count(
+        &self,
+        filename: &str,
+        block_size: u32,
+    ) -> impl Future<Output = Result<u64, CommandError>> + Send;
+    /// Checksums of full block `index` of the stored `filename`, sent one
+    /// SIG line at a time so no reply is ever held in memory whole.
+    fn signature(
+        &self,
+        filename: &str,
+        block_size: u32,
+        index: u64,
+    ) -> impl Future<Output = Result<BlockSignature, CommandError>> + Send;
+}
+
+/// Hook consulted on every HELLO/STREAM before the transfer is accepted, and
+/// on every DELETE and MANIFEST of a node that has sync enabled.
+pub trait TransferGate: Send + Sync {
+    fn review(
+        &self,
+        transfer: &IncomingTransfer,
+    ) -> impl Future<Output = TransferDecision> + Send + Sync;
 }
@@ -1,17 +1,84 @@
+use std::collections::{HashMap, hash_map::Entry};
+use std::net::SocketAddr;
 use std::sync::Arc;
 
+use tokio::sync::mpsc::Sender;
+
 use crate::core::domain::{
-    command::{entities::CommandError, ports::CommandService},
+    command::{
+        entities::{CommandError, IncomingTransfer, TransferDecision, TransferKind},
+        ports::{CommandService, TransferGate},
+    },
+    compression::{entities::Codec, services::decompress},
+    delta::entities::{BlockSignature, MIN_BLOCK_SIZE},
+    hook::entities::HookJob,
+    journal::{
+        entities::{JournalError, TransferId, TransferOutcome},
+        ports::TransferJournal,
+        services::InMemoryTransferJournal,
+    },
     network::entities::{ProtocolMessage, TransferState},
-    storage::ports::StorageRepository,
+    storage::{
+        entities::{MAX_BLOCK_SIZE, ManifestEntry, MetadataPolicy, StorageError},
+        ports::{ContentLookup, StorageRepository, StorageSession},
+        services::NoContentLookup,
+    },
 };
 
-#[derive(Clone)]
-pub struct CommandServiceImpl<C>
+/// Blocks between two checkpoints of a transfer, see `checkpoint`.
+const JOURNAL_BATCH: u64 = 4096;
+
+/// Gate that accepts every transfer; the default for non-interactive nodes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AcceptAll;
+
+impl TransferGate for AcceptAll {
+    async fn review(&self, _transfer: &IncomingTransfer) -> TransferDecision {
+        TransferDecision::Accept
+    }
+}
+
+pub struct CommandServiceImpl<C, G = AcceptAll, L = NoContentLookup, J = InMemoryTransferJournal>
 where
     C: StorageRepository,
+    G: TransferGate,
+    L: ContentLookup,
+    J: TransferJournal,
 {
     storage: C,
+    gate: G,
+    lookup: L,
+    journal: J,
+    // what of the metadata sent with META is applied to received files
+    metadata_policy: MetadataPolicy,
+    // post-receive hook queue, fed with every finalized file
+    hooks: Option<Sender<HookJob>>,
+    // whether peers may list (MANIFEST) and delete (DELETE) stored files
+    sync: bool,
+    // open storage sessions of the transfers in progress, on any connection
+    sessions: Arc<tokio::sync::Mutex<HashMap<TransferId, C::Session>>>,
+}
+
+// Not derived: that would also require the sessions to be `Clone`.
+impl<C, G, L, J> Clone for CommandServiceImpl<C, G, L, J>
+where
+    C: StorageRepository + Clone,
+    G: TransferGate + Clone,
+    L: ContentLookup + Clone,
+    J: TransferJournal + Clone,
+{
+    fn clone(&self) -> Self {
+        CommandServiceImpl {
+            storage: self.storage.clone(),
+            gate: self.gate.clone(),
+            lookup: self.lookup.clone(),
+            journal: self.journal.clone(),
+            metadata_policy: self.metadata_policy,
+            hooks: self.hooks.clone(),
+            sync: self.sync,
+            sessions: Arc::clone(&self.sessions),
+        }
+    }
 }
 
 impl<C> CommandServiceImpl<C>
@@ -19,97 +86,942 @@ where
     C: StorageRepository + Clone + Send + Sync + 'static,
 {
     pub fn new(storage: C) -> Self {
-        CommandServiceImpl { storage }
+        CommandServiceImpl {
+            storage,
+            gate: AcceptAll,
+            lookup: NoContentLookup,
+            journal: InMemoryTransferJournal::new(),
+            metadata_policy: MetadataPolicy::default(),
+            hooks: None,
+            sync: false,
+            sessions: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
+        }
     }
 }
 
-impl<C> CommandService for CommandServiceImpl<C>
+impl<C, G, L, J> CommandServiceImpl<C, G, L, J>
 where
     C: StorageRepository + Clone + Send + Sync + 'static,
+    G: TransferGate + Clone + 'static,
+    L: ContentLookup + Clone + Send + Sync + 'static,
+    J: TransferJournal + Clone + Send + Sync + 'static,
+{
+    /// Replace the gate consulted before replying OK or NOPE to HELLO/STREAM.
+    pub fn with_gate<H>(self, gate: H) -> CommandServiceImpl<C, H, L, J>
+    where
+        H: TransferGate + Clone + 'static,
+    {
+        CommandServiceImpl {
+            storage: self.storage,
+            gate,
+            lookup: self.lookup,
+            journal: self.journal,
+            metadata_policy: self.metadata_policy,
+            hooks: self.hooks,
+            sync: self.sync,
+            sessions: self.sessions,
+        }
+    }
+
+    /// Complete a HELLO announcing a hash immediately when `lookup` already
+    /// has that content, instead of receiving it again.
+    pub fn with_content_lookup<M>(self, lookup: M) -> CommandServiceImpl<C, G, M, J>
+    where
+        M: ContentLookup + Clone + Send + Sync + 'static,
+    {
+        CommandServiceImpl {
+            storage: self.storage,
+            gate: self.gate,
+            lookup,
+            journal: self.journal,
+            metadata_policy: self.metadata_policy,
+            hooks: self.hooks,
+            sync: self.sync,
+            sessions: self.sessions,
+        }
+    }
+
+    /// Record transfers, and the blocks they received, in `journal` instead of memory.
+    pub fn with_journal<K>(self, journal: K) -> CommandServiceImpl<C, G, L, K>
+    where
+        K: TransferJournal + Clone + Send + Sync + 'static,
+    {
+        CommandServiceImpl {
+            storage: self.storage,
+            gate: self.gate,
+            lookup: self.lookup,
+            journal,
+            metadata_policy: self.metadata_policy,
+            hooks: self.hooks,
+            sync: self.sync,
+            sessions: self.sessions,
+        }
+    }
+
+    /// Decide how much of the metadata sent with META reaches received files.
+    pub fn with_metadata_policy(mut self, metadata_policy: MetadataPolicy) -> Self {
+        self.metadata_policy = metadata_policy;
+        self
+    }
+
+    /// Queue every finalized file on `hooks` for the post-receive hook.
+    /// SUCCESS is only held back while the queue is full.
+    pub fn with_hooks(mut self, hooks: Sender<HookJob>) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Let peers list the stored files and delete them, which is what
+    /// `cli sync` does. Off by default; each request still goes through the gate.
+    pub fn with_sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Review a DELETE or MANIFEST. Returns the NOPE to reply when refused.
+    async fn admit_sync(&self, request: &IncomingTransfer) -> Option<ProtocolMessage> {
+        let command = match request.kind {
+            TransferKind::Delete => format!("DELETE {}", request.filename),
+            TransferKind::Manifest => "MANIFEST".to_string(),
+            TransferKind::Receive => format!("DELTA {}", request.filename),
+        };
+        if !self.sync {
+            eprintln!(
+                "Refused {} from {:?}: sync is not enabled",
+                command, request.peer
+            );
+            return Some(ProtocolMessage::Nope(
+                "sync is not enabled on this node".to_string(),
+            ));
+        }
+        if let TransferDecision::Reject(reason) = self.gate.review(request).await {
+            eprintln!("{} rejected: {}", command, reason);
+            return Some(ProtocolMessage::Nope(reason));
+        }
+        None
+    }
+
+    /// Review an announced file, and complete it at once when its content is
+    /// already stored. Returns the reply when that settles the announcement.
+    async fn admit(
+        &self,
+        state: &Arc<tokio::sync::Mutex<TransferState>>,
+        transfer: &IncomingTransfer,
+    ) -> Option<ProtocolMessage> {
+        if let TransferDecision::Reject(reason) = self.gate.review(transfer).await {
+            eprintln!("Transfer of {} rejected: {}", transfer.filename, reason);
+            self.record_closed(transfer, TransferOutcome::Rejected(reason.clone()))
+                .await;
+            return Some(ProtocolMessage::Nope(reason));
+        }
+
+        // Content already stored: no data needed, the transfer is done.
+        if let (Some(sha256), Some(filesize)) = (&transfer.sha256, transfer.filesize) {
+            match self
+                .lookup
+                .claim(&transfer.filename, sha256, filesize)
+                .await
+            {
+                Ok(true) => {
+                    eprintln!(
+                        "{} already stored as {}, skipping transfer.",
+                        transfer.filename, sha256
+                    );
+                    let final_path = self.storage.location(&transfer.filename);
+                    self.record_closed(transfer, TransferOutcome::Completed { final_path })
+                        .await;
+                    *state.lock().await = TransferState::Finished;
+                    return Some(ProtocolMessage::Success);
+                }
+                Ok(false) => {}
+                Err(e) => eprintln!("Content lookup for {} failed: {:?}", transfer.filename, e),
+            }
+        }
+        None
+    }
+
+    /// Reopen the interrupted transfer of the same content as `transfer`,
+    /// with its partial copy cut back to the blocks the journal holds.
+    /// Returns it and how many blocks it has, or None to start over. Fails
+    /// with the reason for a NOPE when another connection is receiving it.
+    async fn resume(
+        &self,
+        transfer: &IncomingTransfer,
+        block_size: u64,
+    ) -> Result<Option<(TransferId, u64)>, String> {
+        let id = match self.journal.resume(transfer).await {
+            Ok(Some(id)) => id,
+            Ok(None) => return Ok(None),
+            Err(JournalError::InProgress(_)) => {
+                return Err(format!("{} is already being received", transfer.filename));
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to look up earlier transfers of {}: {}",
+                    transfer.filename,
+                    String::from(e)
+                );
+                return Ok(None);
+            }
+        };
+        // The journal hands a transfer out once; a session left for it means
+        // it is still written to, and must not be cut back under its writer.
+        if self.sessions.lock().await.contains_key(&id) {
+            return Err(format!("{} is already being received", transfer.filename));
+        }
+        let reason = match self.resume_point(id, block_size).await {
+            Ok(blocks) => match self
+                .storage
+                .resume_file(&transfer.filename, transfer.filesize, blocks * block_size)
+                .await
+            {
+                Ok(session) => match self.sessions.lock().await.entry(id) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(session);
+                        return Ok(Some((id, blocks)));
+                    }
+                    Entry::Occupied(_) => {
+                        return Err(format!("{} is already being received", transfer.filename));
+                    }
+                },
+                Err(e) => String::from(e),
+            },
+            Err(reason) => reason,
+        };
+        eprintln!("Starting {} over: {}", transfer.filename, reason);
+        self.close_transfer(
+            id,
+            TransferOutcome::Failed(format!("not resumed: {}", reason)),
+        )
+        .await;
+        Ok(None)
+    }
+
+    /// How many blocks of `id` the sender can skip when it goes on with
+    /// blocks of `block_size` bytes.
+    async fn resume_point(&self, id: TransferId, block_size: u64) -> Result<u64, String> {
+        let blocks = self.journal.received_blocks(id).await?;
+        if blocks.is_empty() {
+            return Ok(0);
+        }
+        if self.journal.block_size(id).await? != Some(block_size) {
+            return Err("its blocks were of another size".to_string());
+        }
+        // Blocks are stored one after the other, so only a run from the
+        // first one says where the partial copy ends.
+        if blocks
+            .iter()
+            .enumerate()
+            .any(|(i, &index)| index != i as u64)
+        {
+            return Err("its blocks don't follow each other".to_string());
+        }
+        Ok(blocks.len() as u64)
+    }
+
+    /// Open the storage session `transfer` writes through. On failure the
+    /// transfer is journaled as failed and the reason returned for a NOPE.
+    async fn open_session(
+        &self,
+        transfer: TransferId,
+        filename: &str,
+        size: Option<u64>,
+    ) -> Result<(), String> {
+        match self.storage.open_file(filename, size).await {
+            Ok(session) => {
+                self.sessions.lock().await.insert(transfer, session);
+                Ok(())
+            }
+            Err(e) => {
+                let reason = String::from(e);
+                self.close_transfer(transfer, TransferOutcome::Failed(reason.clone()))
+                    .await;
+                Err(reason)
+            }
+        }
+    }
+
+    /// Take the session of `transfer` out of the shared map, so writing to it
+    /// doesn't hold up other connections.
+    async fn take_session(&self, transfer: TransferId) -> Result<C::Session, CommandError> {
+        self.sessions.lock().await.remove(&transfer).ok_or_else(|| {
+            CommandError::ExecutionFailed(format!("No open file for transfer {}", transfer.0))
+        })
+    }
+
+    /// Flush and publish the file of `transfer`, journal the outcome, then
+    /// queue the file for the post-receive hook.
+    async fn finish_transfer(
+        &self,
+        transfer: TransferId,
+        current_file: &str,
+        size: u64,
+        peer: Option<SocketAddr>,
+    ) -> Result<(), CommandError> {
+        let session = self.take_session(transfer).await?;
+        if let Err(e) = session.finalize().await {
+            let reason = format!("Storage error: {:?}", e);
+            self.close_transfer(transfer, TransferOutcome::Failed(reason.clone()))
+                .await;
+            return Err(CommandError::ExecutionFailed(reason));
+        }
+        let final_path = self.storage.location(current_file);
+        self.close_transfer(
+            transfer,
+            TransferOutcome::Completed {
+                final_path: final_path.clone(),
+            },
+        )
+        .await;
+        if let Some(hooks) = &self.hooks {
+            let job = HookJob {
+                transfer,
+                filename: current_file.to_string(),
+                final_path,
+                size,
+                peer,
+            };
+            if hooks.send(job).await.is_err() {
+                eprintln!("Hook workers stopped, {} is kept unchecked", current_file);
+            }
+        }
+        Ok(())
+    }
+
+    /// Every `JOURNAL_BATCH` blocks, sync the partial copy of `transfer` and
+    /// flush its journaled blocks, in that order: an interrupted transfer can
+    /// pick up from the last checkpoint.
+    async fn checkpoint(&self, transfer: TransferId) -> Result<(), CommandError> {
+        let count = self
+            .journal
+            .block_count(transfer)
+            .await
+            .map_err(journal_error)?;
+        if !count.is_multiple_of(JOURNAL_BATCH) {
+            return Ok(());
+        }
+        let mut session = self.take_session(transfer).await?;
+        let synced = session.sync().await;
+        self.sessions.lock().await.insert(transfer, session);
+        if let Err(e) = synced {
+            let reason = format!("Storage error: {:?}", e);
+            self.close_transfer(transfer, TransferOutcome::Failed(reason.clone()))
+                .await;
+            return Err(CommandError::ExecutionFailed(reason));
+        }
+        self.journal.flush(transfer).await.map_err(journal_error)
+    }
+
+    /// Close a journal entry. The transfer itself is already decided, so a
+    /// journal failure is only logged.
+    async fn close_transfer(&self, id: TransferId, outcome: TransferOutcome) {
+        if let Err(e) = self.journal.finish(id, &outcome).await {
+            eprintln!(
+                "Failed to journal the end of transfer {}: {}",
+                id.0,
+                String::from(e)
+            );
+        }
+    }
+
+    /// Journal a transfer that ends as soon as it is announced.
+    async fn record_closed(&self, transfer: &IncomingTransfer, outcome: TransferOutcome) {
+        match self.journal.begin(transfer, None).await {
+            Ok(id) => self.close_transfer(id, outcome).await,
+            Err(e) => eprintln!(
+                "Failed to journal {}: {}",
+                transfer.filename,
+                String::from(e)
+            ),
+        }
+    }
+}
+
+fn journal_error(err: JournalError) -> CommandError {
+    CommandError::ExecutionFailed(String::from(err))
+}
+
+impl<C, G, L, J> CommandService for CommandServiceImpl<C, G, L, J>
+where
+    C: StorageRepository + Clone + Send + Sync + 'static,
+    G: TransferGate + Clone + 'static,
+    L: ContentLookup + Clone + Send + Sync + 'static,
+    J: TransferJournal + Clone + Send + Sync + 'static,
 {
     async fn execute_protocol_command(
         &self,
         state: Arc<tokio::sync::Mutex<TransferState>>,
+        peer: Option<SocketAddr>,
         msg: &ProtocolMessage,
     ) -> Result<ProtocolMessage, CommandError> {
         match msg {
             ProtocolMessage::Hello {
                 filename: _filename,
                 filesize,
+                sha256,
             } => {
-                println!("Execute HELLO command.");
-                let expected_blocks = (*filesize + 1023).div_ceil(1024);
+                eprintln!("Execute HELLO command.");
+                let transfer = IncomingTransfer {
+                    kind: TransferKind::Receive,
+                    peer,
+                    filename: _filename.clone(),
+                    filesize: Some(*filesize),
+                    sha256: sha256.clone(),
+                };
+                if let Some(reply) = self.admit(&state, &transfer).await {
+                    return Ok(reply);
+                }
+
+                let id = match self.journal.begin(&transfer, None).await {
+                    Ok(id) => id,
+                    Err(e) => return Ok(ProtocolMessage::Nope(String::from(e))),
+                };
+                if let Err(reason) = self
+                    .open_session(id, &transfer.filename, transfer.filesize)
+                    .await
+                {
+                    return Ok(ProtocolMessage::Nope(reason));
+                }
+
                 let mut state_guard = state.lock().await;
-                println!(
-                    "Setting state to Receiving with expected_blocks={}",
-                    expected_blocks
-                );
+                eprintln!("Setting state to Receiving for {} bytes", filesize);
                 *state_guard = TransferState::Receiving {
                     current_file: _filename.clone(),
-                    expected_blocks,
+                    filesize: Some(*filesize),
+                    block_size: None,
+                    ends_with_eos: false,
                     focused_block: None,
-                    received_blocks: Vec::with_capacity(expected_blocks as usize),
+                    transfer: id,
+                    received_bytes: 0,
+                    codec: Codec::None,
                 };
 
                 drop(state_guard);
 
                 Ok(ProtocolMessage::Ok)
             }
+            ProtocolMessage::Resume {
+                filename,
+                filesize,
+                sha256,
+                block_size,
+            } => {
+                eprintln!("Execute RESUME command.");
+                if !(1..=MAX_BLOCK_SIZE).contains(block_size) {
+                    return Ok(ProtocolMessage::Nope(format!(
+                        "block size must be between 1 and {}",
+                        MAX_BLOCK_SIZE
+                    )));
+                }
+                let transfer = IncomingTransfer {
+                    kind: TransferKind::Receive,
+                    peer,
+                    filename: filename.clone(),
+                    filesize: Some(*filesize),
+                    sha256: Some(sha256.clone()),
+                };
+                if let Some(reply) = self.admit(&state, &transfer).await {
+                    return Ok(reply);
+                }
+
+                let resumed = match self.resume(&transfer, *block_size as u64).await {
+                    Ok(resumed) => resumed,
+                    Err(reason) => {
+                        eprintln!("Refused RESUME of {}: {}", filename, reason);
+                        return Ok(ProtocolMessage::Nope(reason));
+                    }
+                };
+                let (id, blocks) = match resumed {
+                    Some(resumed) => resumed,
+                    None => {
+                        let id = match self.journal.begin(&transfer, None).await {
+                            Ok(id) => id,
+                            Err(e) => return Ok(ProtocolMessage::Nope(String::from(e))),
+                        };
+                        if let Err(reason) =
+                            self.open_session(id, filename, transfer.filesize).await
+                        {
+                            return Ok(ProtocolMessage::Nope(reason));
+                        }
+                        (id, 0)
+                    }
+                };
+                if blocks > 0 {
+                    eprintln!("Resuming {} from block {}.", filename, blocks);
+                }
+
+                *state.lock().await = TransferState::Receiving {
+                    current_file: filename.clone(),
+                    filesize: Some(*filesize),
+                    block_size: Some(*block_size as u64),
+                    ends_with_eos: false,
+                    focused_block: None,
+                    transfer: id,
+                    received_bytes: blocks * *block_size as u64,
+                    codec: Codec::None,
+                };
+
+                Ok(ProtocolMessage::OkResume(blocks))
+            }
+            ProtocolMessage::Stream { filename } => {
+                eprintln!("Execute STREAM command.");
+                let transfer = IncomingTransfer {
+                    kind: TransferKind::Receive,
+                    peer,
+                    filename: filename.clone(),
+                    filesize: None,
+                    sha256: None,
+                };
+                if let TransferDecision::Reject(reason) = self.gate.review(&transfer).await {
+                    eprintln!("Transfer of {} rejected: {}", filename, reason);
+                    self.record_closed(&transfer, TransferOutcome::Rejected(reason.clone()))
+                        .await;
+                    return Ok(ProtocolMessage::Nope(reason));
+                }
+
+                let id = match self.journal.begin(&transfer, None).await {
+                    Ok(id) => id,
+                    Err(e) => return Ok(ProtocolMessage::Nope(String::from(e))),
+                };
+                if let Err(reason) = self
+                    .open_session(id, &transfer.filename, transfer.filesize)
+                    .await
+                {
+                    return Ok(ProtocolMessage::Nope(reason));
+                }
+
+                let mut state_guard = state.lock().await;
+                *state_guard = TransferState::Receiving {
+                    current_file: filename.clone(),
+                    filesize: None,
+                    block_size: None,
+                    ends_with_eos: true,
+                    focused_block: None,
+                    transfer: id,
+                    received_bytes: 0,
+                    codec: Codec::None,
+                };
+
+                drop(state_guard);
+
+                Ok(ProtocolMessage::Ok)
+            }
+            ProtocolMessage::Delta {
+                filename,
+                filesize,
+                block_size,
+            } => {
+                eprintln!("Execute DELTA command.");
+                // The block size bounds every COPY and the signatures we send back.
+                if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(block_size) {
+                    return Ok(ProtocolMessage::Nope(format!(
+                        "block size must be between {} and {}",
+                        MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+                    )));
+                }
+                let transfer = IncomingTransfer {
+                    kind: TransferKind::Receive,
+                    peer,
+                    filename: filename.clone(),
+                    filesize: Some(*filesize),
+                    sha256: None,
+                };
+                // A delta reads back stored files, so it is a sync command.
+                if let Some(ProtocolMessage::Nope(reason)) = self.admit_sync(&transfer).await {
+                    self.record_closed(&transfer, TransferOutcome::Rejected(reason.clone()))
+                        .await;
+                    return Ok(ProtocolMessage::Nope(reason));
+                }
+
+                let id = match self
+                    .journal
+                    .begin(&transfer, Some(*block_size as u64))
+                    .await
+                {
+                    Ok(id) => id,
+                    Err(e) => return Ok(ProtocolMessage::Nope(String::from(e))),
+                };
+
+                // The new content is rebuilt from scratch next to the existing copy.
+                if let Err(reason) = self.open_session(id, filename, Some(*filesize)).await {
+                    return Ok(ProtocolMessage::Nope(reason));
+                }
+
+                // Literals and copies arrive in order and end with EOS, like a stream.
+                let mut state_guard = state.lock().await;
+                *state_guard = TransferState::Receiving {
+                    current_file: filename.clone(),
+                    filesize: Some(*filesize),
+                    block_size: Some(*block_size as u64),
+                    ends_with_eos: true,
+                    focused_block: None,
+                    transfer: id,
+                    received_bytes: 0,
+                    codec: Codec::None,
+                };
+
+                drop(state_guard);
+
+                // The signatures themselves are sent by the network layer.
+                Ok(msg.clone())
+            }
+            ProtocolMessage::Copy { offset, len } => {
+                let state_guard = state.lock().await;
+                let (transfer, filesize, dst_offset) = match &*state_guard {
+                    TransferState::Receiving {
+                        transfer,
+                        filesize,
+                        ends_with_eos: true,
+                        focused_block: None,
+                        received_bytes,
+                        ..
+                    } => (*transfer, *filesize, *received_bytes),
+                    _ => {
+                        return Ok(ProtocolMessage::Error(
+                            "COPY is only valid between blocks of a DELTA transfer".to_string(),
+                        ));
+                    }
+                };
+                drop(state_guard);
+
+                // A delta only ever copies whole blocks of the announced size,
+                // which also bounds what a single COPY makes us read.
+                let block_size = match self.journal.block_size(transfer).await {
+                    Ok(block_size) => block_size,
+                    Err(e) => return Ok(ProtocolMessage::Error(String::from(e))),
+                };
+                if block_size.is_none_or(|block_size| *len > block_size) {
+                    return Ok(ProtocolMessage::Nope(format!(
+                        "COPY of {} bytes is larger than a block of this transfer",
+                        len
+                    )));
+                }
+
+                if filesize.is_some_and(|filesize| dst_offset + *len > filesize) {
+                    return Ok(ProtocolMessage::Nope(format!(
+                        "COPY of {} bytes goes past the announced size",
+                        len
+                    )));
+                }
+
+                let mut session = match self.take_session(transfer).await {
+                    Ok(session) => session,
+                    Err(e) => return Ok(ProtocolMessage::Error(String::from(e))),
+                };
+                let copied = session.copy_range(*offset, *len, dst_offset).await;
+                self.sessions.lock().await.insert(transfer, session);
+                if let Err(e) = copied {
+                    return Ok(ProtocolMessage::Nope(String::from(e)));
+                }
+
+                let mut state_guard = state.lock().await;
+                match &mut *state_guard {
+                    TransferState::Receiving { received_bytes, .. } => *received_bytes += len,
+                    _ => {
+                        return Ok(ProtocolMessage::Error(
+                            "Transfer state changed while copying".to_string(),
+                        ));
+                    }
+                }
+
+                Ok(ProtocolMessage::Ok)
+            }
+            ProtocolMessage::Compress(name) => {
+                let Ok(requested) = Codec::try_from(name.as_str()) else {
+                    return Ok(ProtocolMessage::Nope(format!("unsupported codec {}", name)));
+                };
+                let mut state_guard = state.lock().await;
+                if let TransferState::Receiving {
+                    transfer,
+                    focused_block: None,
+                    received_bytes: 0,
+                    codec,
+                    ..
+                } = &mut *state_guard
+                    && self
+                        .journal
+                        .block_count(*transfer)
+                        .await
+                        .map_err(journal_error)?
+                        == 0
+                {
+                    eprintln!("Blocks of this transfer may be {}-compressed.", name);
+                    *codec = requested;
+                    return Ok(ProtocolMessage::Ok);
+                }
+                Ok(ProtocolMessage::Nope(
+                    "COMPRESS must come before the first block".to_string(),
+                ))
+            }
             ProtocolMessage::Yeet(yeet_block) => {
                 let mut state_guard = state.lock().await;
-                let (expected_blocks, focused_block, received_blocks, current_file) =
-                    match &mut *state_guard {
-                        TransferState::Receiving {
-                            expected_blocks,
-                            focused_block,
-                            received_blocks,
-                            current_file,
-                        } => (
-                            expected_blocks,
-                            focused_block,
-                            received_blocks,
-                            current_file,
-                        ),
-                        _ => {
-                            return Err(CommandError::ExecutionFailed(
-                                "Error transfer state is not equal Receiving".to_string(),
-                            ));
-                        }
-                    };
+                let (
+                    filesize,
+                    block_size,
+                    ends_with_eos,
+                    focused_block,
+                    transfer,
+                    received_bytes,
+                    codec,
+                ) = match &mut *state_guard {
+                    TransferState::Receiving {
+                        filesize,
+                        block_size,
+                        ends_with_eos,
+                        focused_block,
+                        transfer,
+                        received_bytes,
+                        codec,
+                        ..
+                    } => (
+                        *filesize,
+                        block_size,
+                        *ends_with_eos,
+                        focused_block,
+                        *transfer,
+                        *received_bytes,
+                        *codec,
+                    ),
+                    _ => {
+                        return Err(CommandError::ExecutionFailed(
+                            "Error transfer state is not equal Receiving".to_string(),
+                        ));
+                    }
+                };
+
+                if yeet_block.raw_size == 0 {
+                    return Err(CommandError::ExecutionFailed(format!(
+                        "Block {} is empty",
+                        yeet_block.index
+                    )));
+                }
+                // HELLO doesn't announce a block size: the first block sets it.
+                let size = block_size.unwrap_or(yeet_block.raw_size as u64);
+                // Checked before the data is read or decoded, which allocate
+                // `size` and `raw_size` bytes.
+                if yeet_block.raw_size as u64 > size.min(MAX_BLOCK_SIZE as u64) {
+                    return Err(CommandError::ExecutionFailed(format!(
+                        "Block {} of {} bytes is larger than the {}-byte blocks of this transfer",
+                        yeet_block.index,
+                        yeet_block.raw_size,
+                        size.min(MAX_BLOCK_SIZE as u64)
+                    )));
+                }
+                // Blocks are only sent compressed when that makes them smaller.
+                if yeet_block.size > yeet_block.raw_size
+                    || (yeet_block.codec == Codec::None && yeet_block.size != yeet_block.raw_size)
+                {
+                    return Err(CommandError::ExecutionFailed(format!(
+                        "Block {} sends {} bytes for {} raw ones",
+                        yeet_block.index, yeet_block.size, yeet_block.raw_size
+                    )));
+                }
 
                 // Ensure we don't exceed the expected number of blocks.
-                if received_blocks.len() >= *expected_blocks as usize {
-                    println!("Received all expected blocks.");
+                if !ends_with_eos
+                    && let Some(filesize) = filesize
+                    && self
+                        .journal
+                        .block_count(transfer)
+                        .await
+                        .map_err(journal_error)?
+                        >= filesize.div_ceil(size)
+                {
+                    eprintln!("Received all expected blocks.");
                     return Err(CommandError::ExecutionFailed(
                         "Received block index exceeds expected blocks".to_string(),
                     ));
                 }
 
                 if let Some(focused_block) = focused_block
-                    && !received_blocks.contains(&focused_block.index)
+                    && !self
+                        .journal
+                        .has_block(transfer, focused_block.index)
+                        .await
+                        .map_err(journal_error)?
                 {
-                    println!("Received expected block index: {}", focused_block.index);
+                    eprintln!("Received expected block index: {}", focused_block.index);
                     return Err(CommandError::ExecutionFailed(
                         "Block not received. Can't proceed with next block.".to_string(),
                     ));
                 }
 
+                // Decoded blocks are exactly `raw_size` long, so this bounds
+                // the data itself. Blocks sent again are ignored later on.
+                if let Some(filesize) = filesize
+                    && received_bytes + yeet_block.raw_size as u64 > filesize
+                    && !self
+                        .journal
+                        .has_block(transfer, yeet_block.index)
+                        .await
+                        .map_err(journal_error)?
+                {
+                    return Err(CommandError::ExecutionFailed(format!(
+                        "Block {} goes past the announced size of {} bytes",
+                        yeet_block.index, filesize
+                    )));
+                }
+
+                if yeet_block.codec != Codec::None && yeet_block.codec != codec {
+                    return Err(CommandError::ExecutionFailed(format!(
+                        "Block {} uses codec {} which was not negotiated",
+                        yeet_block.index,
+                        String::from(yeet_block.codec)
+                    )));
+                }
+
                 // Reuse the mutable guard to update the state without locking again.
-                *state_guard = TransferState::Receiving {
-                    current_file: current_file.clone(),
-                    expected_blocks: *expected_blocks,
-                    focused_block: Some(yeet_block.clone()),
-                    received_blocks: received_blocks.clone(),
-                };
+                *focused_block = Some(yeet_block.clone());
+                *block_size = Some(size);
 
                 drop(state_guard);
 
                 Ok(ProtocolMessage::Yeet(yeet_block.clone()))
             }
+            ProtocolMessage::Hole { index, len } => {
+                let state_guard = state.lock().await;
+                let (transfer, filesize, block_size, ends_with_eos, offset) = match &*state_guard {
+                    TransferState::Receiving {
+                        transfer,
+                        filesize,
+                        block_size,
+                        ends_with_eos,
+                        focused_block: None,
+                        received_bytes,
+                        ..
+                    } => (
+                        *transfer,
+                        *filesize,
+                        *block_size,
+                        *ends_with_eos,
+                        *received_bytes,
+                    ),
+                    _ => {
+                        return Err(CommandError::ExecutionFailed(
+                            "HOLE is only valid between blocks of a transfer".to_string(),
+                        ));
+                    }
+                };
+                drop(state_guard);
+
+                if self
+                    .journal
+                    .has_block(transfer, *index)
+                    .await
+                    .map_err(journal_error)?
+                {
+                    eprintln!("Block {} already received, ignoring.", index);
+                    return Ok(ProtocolMessage::Ok);
+                }
+
+                // Bounded like YEET data: at most one block, within the
+                // announced size and in place of the next block.
+                let len = u64::from(*len);
+                let size = block_size.unwrap_or(len);
+                if len == 0 || len > size.min(MAX_BLOCK_SIZE as u64) {
+                    return Err(CommandError::ExecutionFailed(format!(
+                        "Hole {} of {} bytes doesn't fit the {}-byte blocks of this transfer",
+                        index,
+                        len,
+                        size.min(MAX_BLOCK_SIZE as u64)
+                    )));
+                }
+                if let Some(filesize) = filesize
+                    && offset + len > filesize
+                {
+                    return Err(CommandError::ExecutionFailed(format!(
+                        "Hole {} goes past the announced size of {} bytes",
+                        index, filesize
+                    )));
+                }
+                let received = self
+                    .journal
+                    .block_count(transfer)
+                    .await
+                    .map_err(journal_error)?;
+                if *index != received {
+                    return Err(CommandError::ExecutionFailed(format!(
+                        "Hole {} is out of order, block {} is next",
+                        index, received
+                    )));
+                }
+                if !ends_with_eos
+                    && let Some(filesize) = filesize
+                    && received >= filesize.div_ceil(size)
+                {
+                    return Err(CommandError::ExecutionFailed(
+                        "Received block index exceeds expected blocks".to_string(),
+                    ));
+                }
+
+                let mut session = self.take_session(transfer).await?;
+                let written = session.write_hole(offset, len).await;
+                self.sessions.lock().await.insert(transfer, session);
+                if let Err(e) = written {
+                    let reason = format!("Storage error: {:?}", e);
+                    self.close_transfer(transfer, TransferOutcome::Failed(reason.clone()))
+                        .await;
+                    return Ok(ProtocolMessage::Error(reason));
+                }
+                self.journal
+                    .record_block(transfer, *index, len)
+                    .await
+                    .map_err(journal_error)?;
+                self.checkpoint(transfer).await?;
+
+                let mut state_guard = state.lock().await;
+                match &mut *state_guard {
+                    TransferState::Receiving {
+                        received_bytes,
+                        block_size,
+                        ..
+                    } => {
+                        *received_bytes += len;
+                        *block_size = Some(size);
+                    }
+                    _ => {
+                        return Err(CommandError::ExecutionFailed(
+                            "Transfer state changed while writing hole".to_string(),
+                        ));
+                    }
+                }
+
+                Ok(ProtocolMessage::OkHousten(*index))
+            }
+            ProtocolMessage::Meta(metadata) => {
+                let transfer = match &*state.lock().await {
+                    TransferState::Receiving { transfer, .. } => *transfer,
+                    _ => {
+                        return Err(CommandError::ExecutionFailed(
+                            "Error transfer state is not equal Receiving".to_string(),
+                        ));
+                    }
+                };
+                // Acknowledged either way: the sender has nothing to change.
+                let Some(metadata) = self.metadata_policy.filter(metadata.clone()) else {
+                    eprintln!("Ignoring file metadata.");
+                    return Ok(ProtocolMessage::Ok);
+                };
+                let mut session = self.take_session(transfer).await?;
+                session.set_metadata(metadata);
+                self.sessions.lock().await.insert(transfer, session);
+                Ok(ProtocolMessage::Ok)
+            }
             ProtocolMessage::MissionAccomplished => {
                 let mut state_guard = state.lock().await;
-                let current_file = match &*state_guard {
-                    TransferState::Receiving { current_file, .. } => current_file,
+                let (current_file, transfer, size) = match &*state_guard {
+                    TransferState::Receiving {
+                        current_file,
+                        ends_with_eos: false,
+                        transfer,
+                        received_bytes,
+                        ..
+                    } => (current_file.clone(), *transfer, *received_bytes),
+                    TransferState::Receiving { .. } => {
+                        return Err(CommandError::ExecutionFailed(
+                            "Stream transfers must be terminated with EOS".to_string(),
+                        ));
+                    }
                     _ => {
                         return Err(CommandError::ExecutionFailed(
                             "Error transfer state is not equal Receiving".to_string(),
@@ -117,9 +1029,54 @@ where
                     }
                 };
 
-                self.storage.finalize(current_file).await.map_err(|e| {
-                    CommandError::ExecutionFailed(format!("Storage error: {:?}", e))
-                })?;
+                self.finish_transfer(transfer, &current_file, size, peer)
+                    .await?;
+                *state_guard = TransferState::Finished;
+                drop(state_guard);
+                Ok(ProtocolMessage::Success)
+            }
+            ProtocolMessage::EndOfStream(total_bytes) => {
+                let mut state_guard = state.lock().await;
+                let (current_file, transfer, size) = match &*state_guard {
+                    TransferState::Receiving {
+                        current_file,
+                        filesize,
+                        ends_with_eos: true,
+                        received_bytes,
+                        transfer,
+                        ..
+                    } => {
+                        // A delta rebuilds a file of the size it announced.
+                        if let Some(filesize) = filesize
+                            && filesize != received_bytes
+                        {
+                            return Err(CommandError::ExecutionFailed(format!(
+                                "DELTA announced {} bytes but {} were received",
+                                filesize, received_bytes
+                            )));
+                        }
+                        if received_bytes != total_bytes {
+                            return Err(CommandError::ExecutionFailed(format!(
+                                "EOS announced {} bytes but {} were received",
+                                total_bytes, received_bytes
+                            )));
+                        }
+                        (current_file.clone(), *transfer, *received_bytes)
+                    }
+                    TransferState::Receiving { .. } => {
+                        return Err(CommandError::ExecutionFailed(
+                            "EOS is only valid for STREAM transfers".to_string(),
+                        ));
+                    }
+                    _ => {
+                        return Err(CommandError::ExecutionFailed(
+                            "Error transfer state is not equal Receiving".to_string(),
+                        ));
+                    }
+                };
+
+                self.finish_transfer(transfer, &current_file, size, peer)
+                    .await?;
                 *state_guard = TransferState::Finished;
                 drop(state_guard);
                 Ok(ProtocolMessage::Success)
@@ -128,10 +1085,96 @@ where
                 *state.lock().await = TransferState::Closed;
                 Ok(ProtocolMessage::ByeRis)
             }
+            // The listing itself is sent by the network layer through `manifest`.
+            ProtocolMessage::ManifestRequest => {
+                let request = IncomingTransfer {
+                    kind: TransferKind::Manifest,
+                    peer,
+                    filename: String::new(),
+                    filesize: None,
+                    sha256: None,
+                };
+                if let Some(reply) = self.admit_sync(&request).await {
+                    return Ok(reply);
+                }
+                Ok(ProtocolMessage::ManifestRequest)
+            }
+            ProtocolMessage::Delete(path) => {
+                eprintln!("Execute DELETE command.");
+                let request = IncomingTransfer {
+                    kind: TransferKind::Delete,
+                    peer,
+                    filename: path.clone(),
+                    filesize: None,
+                    sha256: None,
+                };
+                if let Some(reply) = self.admit_sync(&request).await {
+                    return Ok(reply);
+                }
+                match self.storage.delete_file(path).await {
+                    Ok(()) => Ok(ProtocolMessage::Ok),
+                    Err(e) => Ok(ProtocolMessage::Nope(String::from(e))),
+                }
+            }
             _ => Err(CommandError::InvalidCommand),
         }
     }
 
+    async fn abandon(&self, state: Arc<tokio::sync::Mutex<TransferState>>, reason: &str) {
+        let transfer = match &*state.lock().await {
+            TransferState::Receiving { transfer, .. } => *transfer,
+            _ => return,
+        };
+        // Leave the partial copy in step with the blocks the journal recorded,
+        // without the space reserved for the rest.
+        if let Some(session) = self.sessions.lock().await.remove(&transfer)
+            && let Err(e) = session.abandon().await
+        {
+            eprintln!("Failed to abandon transfer {}: {:?}", transfer.0, e);
+        }
+        self.close_transfer(transfer, TransferOutcome::Failed(reason.to_string()))
+            .await;
+    }
+
+    async fn manifest(&self) -> Result<Vec<ManifestEntry>, CommandError> {
+        self.storage
+            .list_files()
+            .await
+            .map_err(|e| CommandError::ExecutionFailed(format!("Storage error: {:?}", e)))
+    }
+
+    async fn signature_count(&self, filename: &str, block_size: u32) -> Result<u64, CommandError> {
+        match self.storage.file_size(filename).await {
+            // A short tail block can't be matched by the sender anyway.
+            Ok(size) => Ok(size / block_size as u64),
+            Err(StorageError::FileNotFound) => Ok(0),
+            Err(e) => Err(CommandError::ExecutionFailed(format!(
+                "Storage error: {:?}",
+                e
+            ))),
+        }
+    }
+
+    async fn signature(
+        &self,
+        filename: &str,
+        block_size: u32,
+        index: u64,
+    ) -> Result<BlockSignature, CommandError> {
+        let block = self
+            .storage
+            .read_range(filename, index * block_size as u64, block_size as u64)
+            .await
+            .map_err(|e| CommandError::ExecutionFailed(format!("Storage error: {:?}", e)))?;
+        if block.len() < block_size as usize {
+            return Err(CommandError::ExecutionFailed(format!(
+                "{} changed while its signatures were sent",
+                filename
+            )));
+        }
+        Ok(BlockSignature::of(&block))
+    }
+
     async fn process_binary_data(
         &self,
         state: Arc<tokio::sync::Mutex<TransferState>>,
@@ -140,41 +1183,40 @@ where
         // Lock once and extract what we need.
         let mut state_guard = state.lock().await;
 
-        let (_, maybe_focused_block, received_blocks_clone, current_file_clone) =
-            match &mut *state_guard {
-                TransferState::Receiving {
-                    expected_blocks,
-                    focused_block,
-                    received_blocks,
-                    current_file,
-                } => {
-                    // take the focused block out (leaves None in the guard)
-                    let taken_block = focused_block.take();
-                    (
-                        *expected_blocks,
-                        taken_block,
-                        received_blocks.clone(),
-                        current_file.clone(),
-                    )
-                }
-                _ => {
-                    return Err(CommandError::ExecutionFailed(
-                        "Error transfer state is not equal Receiving".to_string(),
-                    ));
-                }
-            };
+        let (maybe_focused_block, transfer, offset) = match &mut *state_guard {
+            TransferState::Receiving {
+                focused_block,
+                transfer,
+                received_bytes,
+                ..
+            } => {
+                // take the focused block out (leaves None in the guard)
+                let taken_block = focused_block.take();
+                (taken_block, *transfer, *received_bytes)
+            }
+            _ => {
+                return Err(CommandError::ExecutionFailed(
+                    "Error transfer state is not equal Receiving".to_string(),
+                ));
+            }
+        };
 
         // If there was no focused block, nothing to do.
         let focused_block = match maybe_focused_block {
             Some(b) => b,
             None => {
-                println!("No focused block to store data for.");
+                eprintln!("No focused block to store data for.");
                 return Ok(ProtocolMessage::Ok);
             }
         };
 
         // If block already received, restore focused_block into the state and return.
-        if received_blocks_clone.contains(&focused_block.index) {
+        if self
+            .journal
+            .has_block(transfer, focused_block.index)
+            .await
+            .map_err(journal_error)?
+        {
             // restore focused_block back into the guard before returning
             if let TransferState::Receiving {
                 focused_block: guard_focused_block,
@@ -183,32 +1225,60 @@ where
             {
                 *guard_focused_block = Some(focused_block.clone());
             }
-            println!("Block {} already received, ignoring.", focused_block.index);
+            eprintln!("Block {} already received, ignoring.", focused_block.index);
             return Ok(ProtocolMessage::Ok);
         }
 
-        println!("Stored binary data block: {:?}", focused_block);
+        eprintln!("Stored binary data block: {:?}", focused_block);
 
         // Clone what we need for the async storage write, then drop the guard before awaiting.
-        let file_for_write = current_file_clone.clone();
         let block_for_write = focused_block.clone();
         drop(state_guard);
 
+        // Storage only ever sees raw bytes.
+        let raw;
+        let data = if block_for_write.codec == Codec::None {
+            data
+        } else {
+            match decompress(block_for_write.codec, data, block_for_write.raw_size) {
+                Ok(decoded) => {
+                    raw = decoded;
+                    &raw[..]
+                }
+                Err(e) => {
+                    let reason = String::from(e);
+                    self.close_transfer(transfer, TransferOutcome::Failed(reason.clone()))
+                        .await;
+                    return Err(CommandError::ExecutionFailed(reason));
+                }
+            }
+        };
+
         // Perform the async write while not holding the mutex.
-        self.storage
-            .write_block(&file_for_write, &block_for_write, data)
+        let mut session = self.take_session(transfer).await?;
+        let written = session.write_block(offset, &block_for_write, data).await;
+        self.sessions.lock().await.insert(transfer, session);
+        if let Err(e) = written {
+            let reason = format!("Storage error: {:?}", e);
+            self.close_transfer(transfer, TransferOutcome::Failed(reason.clone()))
+                .await;
+            return Err(CommandError::ExecutionFailed(reason));
+        }
+        self.journal
+            .record_block(transfer, block_for_write.index, data.len() as u64)
             .await
-            .map_err(|e| CommandError::ExecutionFailed(format!("Storage error: {:?}", e)))?;
+            .map_err(journal_error)?;
+        self.checkpoint(transfer).await?;
 
         // Re-lock and update received_blocks + clear focused_block.
         let mut state_guard = state.lock().await;
         match &mut *state_guard {
             TransferState::Receiving {
-                received_blocks,
                 focused_block,
+                received_bytes,
                 ..
             } => {
-                received_blocks.push(block_for_write.index);
+                *received_bytes += data.len() as u64;
                 *focused_block = None;
             }
             _ => {
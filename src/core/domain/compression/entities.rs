@@ -0,0 +1,53 @@
+use std::convert::TryFrom;
+
+/// How the payload of one YEET block is encoded on the wire.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Codec {
+    /// Raw bytes.
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl TryFrom<&str> for Codec {
+    type Error = CompressionError;
+
+    fn try_from(value: &str) -> Result<Self, CompressionError> {
+        match value {
+            "none" => Ok(Codec::None),
+            "zstd" => Ok(Codec::Zstd),
+            "lz4" => Ok(Codec::Lz4),
+            other => Err(CompressionError::UnsupportedCodec(other.to_string())),
+        }
+    }
+}
+
+impl From<Codec> for String {
+    fn from(codec: Codec) -> Self {
+        match codec {
+            Codec::None => "none",
+            Codec::Zstd => "zstd",
+            Codec::Lz4 => "lz4",
+        }
+        .to_string()
+    }
+}
+
+#[derive(Debug)]
+pub enum CompressionError {
+    UnsupportedCodec(String),
+    /// The payload is corrupt or doesn't expand to the announced size.
+    InvalidPayload(String),
+}
+
+impl From<CompressionError> for String {
+    fn from(err: CompressionError) -> Self {
+        match err {
+            CompressionError::UnsupportedCodec(codec) => format!("Unsupported codec: {}", codec),
+            CompressionError::InvalidPayload(reason) => {
+                format!("Invalid compressed block: {}", reason)
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use crate::core::domain::compression::entities::{Codec, CompressionError};
+
+/// zstd level used for blocks: fast, still a large gain on text.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Encode one block with `codec`.
+pub fn compress(codec: Codec, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    match codec {
+        Codec::None => Ok(data.to_vec()),
+        Codec::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)
+            .map_err(|e| CompressionError::InvalidPayload(e.to_string())),
+        Codec::Lz4 => Ok(lz4_flex::block::compress(data)),
+    }
+}
+
+/// Decode one block that must expand to exactly `raw_size` bytes.
+pub fn decompress(codec: Codec, data: &[u8], raw_size: u32) -> Result<Vec<u8>, CompressionError> {
+    let raw = match codec {
+        Codec::None => data.to_vec(),
+        Codec::Zstd => zstd::bulk::decompress(data, raw_size as usize)
+            .map_err(|e| CompressionError::InvalidPayload(e.to_string()))?,
+        Codec::Lz4 => lz4_flex::block::decompress(data, raw_size as usize)
+            .map_err(|e| CompressionError::InvalidPayload(e.to_string()))?,
+    };
+    if raw.len() != raw_size as usize {
+        return Err(CompressionError::InvalidPayload(format!(
+            "expected {} bytes, got {}",
+            raw_size,
+            raw.len()
+        )));
+    }
+    Ok(raw)
+}
+
+/// The YEET payload for `data`: compressed when that makes it smaller,
+/// otherwise the raw bytes with `Codec::None`.
+pub fn encode_block(codec: Codec, data: &[u8]) -> (Codec, Vec<u8>) {
+    if codec == Codec::None {
+        return (Codec::None, data.to_vec());
+    }
+    match compress(codec, data) {
+        Ok(compressed) if compressed.len() < data.len() => (codec, compressed),
+        _ => (Codec::None, data.to_vec()),
+    }
+}
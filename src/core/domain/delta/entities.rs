@@ -0,0 +1,94 @@
+use sha2::{Digest, Sha256};
+
+/// Smallest block size a DELTA may use; smaller blocks make more signatures
+/// than they save.
+pub const MIN_BLOCK_SIZE: u32 = 1024;
+
+/// Checksums of one full block of the receiver's existing copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSignature {
+    /// Rolling checksum, cheap to slide one byte at a time.
+    pub weak: u32,
+    /// Hex of the first 16 bytes of the block's SHA-256; confirms a weak match.
+    pub strong: String,
+}
+
+impl BlockSignature {
+    pub fn of(block: &[u8]) -> Self {
+        BlockSignature {
+            weak: Rolling::new(block).value(),
+            strong: strong_checksum(block),
+        }
+    }
+}
+
+/// One instruction rebuilding the new file on the receiver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// Reuse `len` bytes of the receiver's existing copy starting at `offset`.
+    Copy { offset: u64, len: u64 },
+    /// Bytes the receiver doesn't have.
+    Literal(Vec<u8>),
+}
+
+/// rsync's rolling checksum over a fixed-size window.
+#[derive(Debug, Clone)]
+pub struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    pub fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+        for (i, byte) in window.iter().enumerate() {
+            a = a.wrapping_add(*byte as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(*byte as u32));
+        }
+        Rolling { a, b, len }
+    }
+
+    /// Slide the window one byte: drop `out` at the front, append `incoming`.
+    pub fn roll(&mut self, out: u8, incoming: u8) {
+        self.a = self
+            .a
+            .wrapping_sub(out as u32)
+            .wrapping_add(incoming as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    pub fn value(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+pub fn strong_checksum(block: &[u8]) -> String {
+    hex::encode(&Sha256::digest(block)[..16])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_matches_a_fresh_checksum_of_the_window() {
+        let data: Vec<u8> = (0..300u32).map(|i| (i * 7 % 256) as u8).collect();
+        let window = 64;
+        let mut rolling = Rolling::new(&data[..window]);
+        for start in 1..=data.len() - window {
+            rolling.roll(data[start - 1], data[start + window - 1]);
+            assert_eq!(
+                rolling.value(),
+                Rolling::new(&data[start..start + window]).value(),
+                "window at {}",
+                start
+            );
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{HashMap, VecDeque};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::core::domain::delta::entities::{BlockSignature, DeltaOp, Rolling, strong_checksum};
+
+/// Bytes pulled from the source per read.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Turns a source file into copy and literal ops against the receiver's
+/// signatures, reading the source once.
+///
+/// Only full blocks are matched, at any byte offset; whatever doesn't match
+/// goes out as literals of at most `block_size` bytes.
+pub struct DeltaEncoder {
+    block_size: usize,
+    // weak checksum -> (block index, strong checksum)
+    blocks: HashMap<u32, Vec<(u64, String)>>,
+    buf: Vec<u8>,
+    // start of the window being matched, into `buf`
+    pos: usize,
+    // start of the bytes no op covers yet, into `buf`
+    literal_start: usize,
+    rolling: Option<Rolling>,
+    eof: bool,
+    pending: VecDeque<DeltaOp>,
+}
+
+impl DeltaEncoder {
+    pub fn new(signatures: &[BlockSignature], block_size: u32) -> Self {
+        let mut blocks: HashMap<u32, Vec<(u64, String)>> = HashMap::new();
+        for (index, signature) in signatures.iter().enumerate() {
+            blocks
+                .entry(signature.weak)
+                .or_default()
+                .push((index as u64, signature.strong.clone()));
+        }
+        DeltaEncoder {
+            block_size: block_size.max(1) as usize,
+            blocks,
+            buf: Vec::new(),
+            pos: 0,
+            literal_start: 0,
+            rolling: None,
+            eof: false,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// The next op, or None once the whole source is covered.
+    pub async fn next_op<R: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+    ) -> std::io::Result<Option<DeltaOp>> {
+        loop {
+            if let Some(op) = self.pending.pop_front() {
+                return Ok(Some(op));
+            }
+
+            // One byte past the window, so the checksum can roll forward.
+            while !self.eof && self.buf.len() <= self.pos + self.block_size {
+                let mut chunk = vec![0u8; READ_CHUNK];
+                let n = reader.read(&mut chunk).await?;
+                if n == 0 {
+                    self.eof = true;
+                } else {
+                    self.buf.extend_from_slice(&chunk[..n]);
+                }
+            }
+
+            let window_end = self.pos + self.block_size;
+            if window_end > self.buf.len() {
+                // Less than a block left: no match possible.
+                let rest = self.buf.split_off(self.literal_start);
+                self.push_literals(&rest);
+                self.buf.clear();
+                self.pos = 0;
+                self.literal_start = 0;
+                if self.pending.is_empty() {
+                    return Ok(None);
+                }
+                continue;
+            }
+
+            let rolling = self
+                .rolling
+                .get_or_insert_with(|| Rolling::new(&self.buf[self.pos..window_end]));
+            let weak = rolling.value();
+
+            if let Some(index) = self.lookup(weak, self.pos, window_end) {
+                let literal = self.buf[self.literal_start..self.pos].to_vec();
+                self.push_literals(&literal);
+                self.pending.push_back(DeltaOp::Copy {
+                    offset: index * self.block_size as u64,
+                    len: self.block_size as u64,
+                });
+                self.pos = window_end;
+                self.literal_start = window_end;
+                self.rolling = None;
+                self.compact();
+                continue;
+            }
+
+            let out = self.buf[self.pos];
+            match (self.rolling.as_mut(), self.buf.get(window_end)) {
+                (Some(rolling), Some(incoming)) => rolling.roll(out, *incoming),
+                _ => self.rolling = None,
+            }
+            self.pos += 1;
+            if self.pos - self.literal_start == self.block_size {
+                let literal = self.buf[self.literal_start..self.pos].to_vec();
+                self.pending.push_back(DeltaOp::Literal(literal));
+                self.literal_start = self.pos;
+                self.compact();
+            }
+        }
+    }
+
+    fn lookup(&self, weak: u32, start: usize, end: usize) -> Option<u64> {
+        let candidates = self.blocks.get(&weak)?;
+        let strong = strong_checksum(&self.buf[start..end]);
+        candidates
+            .iter()
+            .find(|(_, s)| *s == strong)
+            .map(|(index, _)| *index)
+    }
+
+    fn push_literals(&mut self, data: &[u8]) {
+        for chunk in data.chunks(self.block_size) {
+            self.pending.push_back(DeltaOp::Literal(chunk.to_vec()));
+        }
+    }
+
+    /// Drop bytes every op already covers, once there are enough to be worth the move.
+    fn compact(&mut self) {
+        if self.literal_start < READ_CHUNK {
+            return;
+        }
+        self.buf.drain(..self.literal_start);
+        self.pos -= self.literal_start;
+        self.literal_start = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::domain::delta::entities::MIN_BLOCK_SIZE;
+
+    const BLOCK: usize = MIN_BLOCK_SIZE as usize;
+
+    fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn signatures(old: &[u8]) -> Vec<BlockSignature> {
+        old.chunks_exact(BLOCK).map(BlockSignature::of).collect()
+    }
+
+    async fn encode(old: &[u8], new: &[u8]) -> Vec<DeltaOp> {
+        let mut encoder = DeltaEncoder::new(&signatures(old), BLOCK as u32);
+        let mut reader = new;
+        let mut ops = Vec::new();
+        while let Some(op) = encoder.next_op(&mut reader).await.unwrap() {
+            ops.push(op);
+        }
+        ops
+    }
+
+    /// What the receiver rebuilds from `old` and the ops.
+    fn apply(old: &[u8], ops: &[DeltaOp]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for op in ops {
+            match op {
+                DeltaOp::Copy { offset, len } => {
+                    out.extend_from_slice(&old[*offset as usize..(*offset + *len) as usize])
+                }
+                DeltaOp::Literal(data) => out.extend_from_slice(data),
+            }
+        }
+        out
+    }
+
+    fn copied(ops: &[DeltaOp]) -> usize {
+        ops.iter()
+            .filter(|op| matches!(op, DeltaOp::Copy { .. }))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn blocks_are_found_at_any_offset() {
+        // Longer than a read, so the buffer gets compacted on the way.
+        let old = pseudo_random(80 * BLOCK + 100, 1);
+        let mut new = b"inserted".to_vec();
+        new.extend_from_slice(&old[..3 * BLOCK]);
+        new.extend_from_slice(&pseudo_random(50, 2));
+        new.extend_from_slice(&old[3 * BLOCK..]);
+
+        let ops = encode(&old, &new).await;
+        assert_eq!(apply(&old, &ops), new);
+        assert_eq!(copied(&ops), 80);
+        assert!(ops.iter().all(|op| match op {
+            DeltaOp::Literal(data) => !data.is_empty() && data.len() <= BLOCK,
+            DeltaOp::Copy { len, .. } => *len == BLOCK as u64,
+        }));
+    }
+
+    #[tokio::test]
+    async fn unrelated_sources_go_out_as_literals() {
+        let old = pseudo_random(4 * BLOCK, 3);
+        let new = pseudo_random(5 * BLOCK + 7, 4);
+
+        let ops = encode(&old, &new).await;
+        assert_eq!(apply(&old, &ops), new);
+        assert_eq!(copied(&ops), 0);
+        assert_eq!(ops.len(), 6);
+    }
+
+    #[tokio::test]
+    async fn an_empty_source_has_no_ops() {
+        assert!(encode(&pseudo_random(BLOCK, 5), &[]).await.is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+
+/// What a listener broadcasts on the LAN so senders can find it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerAnnouncement {
+    // "FERRIS-PEER <name> <port> <capability,...>"
+    pub name: String,
+    pub port: u16,
+    pub capabilities: Vec<String>,
+}
+
+impl PeerAnnouncement {
+    /// Protocol features this build supports, advertised to senders.
+    pub const CAPABILITIES: [&'static str; 6] =
+        ["stream", "delta", "compress", "hole", "meta", "resume"];
+
+    pub fn new(name: &str, port: u16, capabilities: &[&str]) -> Self {
+        PeerAnnouncement {
+            // Announcements are whitespace separated, so names can't contain any.
+            name: name.split_whitespace().collect::<Vec<_>>().join("-"),
+            port,
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+/// An announcement heard on the network, with the address it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredPeer {
+    pub name: String,
+    pub addr: SocketAddr,
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum DiscoveryError {
+    SocketFailed(std::io::Error),
+    InvalidAnnouncement,
+    PeerNotFound(String),
+}
+
+impl TryFrom<&str> for PeerAnnouncement {
+    type Error = DiscoveryError;
+
+    fn try_from(value: &str) -> Result<Self, DiscoveryError> {
+        let tokens: Vec<&str> = value.split_whitespace().collect();
+        if tokens.first().copied() != Some("FERRIS-PEER") {
+            return Err(DiscoveryError::InvalidAnnouncement);
+        }
+        let name = tokens
+            .get(1)
+            .ok_or(DiscoveryError::InvalidAnnouncement)?
+            .to_string();
+        let port = tokens
+            .get(2)
+            .ok_or(DiscoveryError::InvalidAnnouncement)?
+            .parse::<u16>()
+            .map_err(|_| DiscoveryError::InvalidAnnouncement)?;
+        let capabilities = tokens
+            .get(3)
+            .map(|caps| {
+                caps.split(',')
+                    .filter(|c| !c.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(PeerAnnouncement {
+            name,
+            port,
+            capabilities,
+        })
+    }
+}
+
+impl From<PeerAnnouncement> for String {
+    fn from(announcement: PeerAnnouncement) -> Self {
+        format!(
+            "FERRIS-PEER {} {} {}",
+            announcement.name,
+            announcement.port,
+            announcement.capabilities.join(",")
+        )
+        .trim_end()
+        .to_string()
+    }
+}
+
+impl From<DiscoveryError> for String {
+    fn from(err: DiscoveryError) -> Self {
+        match err {
+            DiscoveryError::SocketFailed(e) => format!("Discovery socket error: {}", e),
+            DiscoveryError::InvalidAnnouncement => "Invalid peer announcement".to_string(),
+            DiscoveryError::PeerNotFound(name) => format!("No peer named '{}' found", name),
+        }
+    }
+}
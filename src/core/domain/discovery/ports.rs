@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+use crate::core::domain::discovery::entities::{DiscoveredPeer, DiscoveryError, PeerAnnouncement};
+
+pub trait DiscoveryService {
+    /// Periodically broadcast `announcement`; only returns on error.
+    fn announce(
+        &self,
+        announcement: PeerAnnouncement,
+    ) -> impl Future<Output = Result<(), DiscoveryError>> + Send;
+    /// Listen for announcements during `timeout` and return the distinct peers heard.
+    fn discover(
+        &self,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Vec<DiscoveredPeer>, DiscoveryError>> + Send;
+    /// Wait up to `timeout` for a peer called `name` to announce itself.
+    fn resolve(
+        &self,
+        name: &str,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<DiscoveredPeer, DiscoveryError>> + Send;
+}
@@ -0,0 +1,146 @@
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+use crate::core::domain::discovery::entities::{DiscoveredPeer, DiscoveryError, PeerAnnouncement};
+use crate::core::domain::discovery::ports::DiscoveryService;
+
+pub const DEFAULT_MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 70, 83);
+pub const DEFAULT_DISCOVERY_PORT: u16 = 9099;
+
+/// Announces and discovers peers with UDP multicast on the local network.
+#[derive(Debug, Clone)]
+pub struct MulticastDiscoveryService {
+    group: Ipv4Addr,
+    port: u16,
+    interval: Duration,
+}
+
+impl MulticastDiscoveryService {
+    pub fn new(group: Ipv4Addr, port: u16) -> Self {
+        MulticastDiscoveryService {
+            group,
+            port,
+            interval: Duration::from_secs(2),
+        }
+    }
+
+    /// Bind the group port with SO_REUSEADDR so several discoverers can run on one host.
+    fn bind_listener(&self) -> Result<UdpSocket, DiscoveryError> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
+            .map_err(DiscoveryError::SocketFailed)?;
+        socket
+            .set_reuse_address(true)
+            .map_err(DiscoveryError::SocketFailed)?;
+        socket
+            .bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, self.port).into())
+            .map_err(DiscoveryError::SocketFailed)?;
+        socket
+            .join_multicast_v4(&self.group, &Ipv4Addr::UNSPECIFIED)
+            .map_err(DiscoveryError::SocketFailed)?;
+        socket
+            .set_nonblocking(true)
+            .map_err(DiscoveryError::SocketFailed)?;
+        UdpSocket::from_std(socket.into()).map_err(DiscoveryError::SocketFailed)
+    }
+
+    /// Receive announcements until `deadline`, calling `on_peer` for each one.
+    /// Stops early when `on_peer` returns true.
+    async fn listen_until<F>(&self, deadline: Instant, mut on_peer: F) -> Result<(), DiscoveryError>
+    where
+        F: FnMut(DiscoveredPeer) -> bool + Send,
+    {
+        let socket = self.bind_listener()?;
+        let mut buf = [0u8; 512];
+
+        loop {
+            let received = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await;
+            let (n, from) = match received {
+                Err(_) => return Ok(()),
+                Ok(res) => res.map_err(DiscoveryError::SocketFailed)?,
+            };
+
+            let Ok(line) = std::str::from_utf8(&buf[..n]) else {
+                continue;
+            };
+            let Ok(announcement) = PeerAnnouncement::try_from(line) else {
+                continue;
+            };
+
+            let peer = DiscoveredPeer {
+                name: announcement.name,
+                addr: SocketAddr::new(from.ip(), announcement.port),
+                capabilities: announcement.capabilities,
+            };
+            if on_peer(peer) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Default for MulticastDiscoveryService {
+    fn default() -> Self {
+        Self::new(DEFAULT_MULTICAST_GROUP, DEFAULT_DISCOVERY_PORT)
+    }
+}
+
+impl DiscoveryService for MulticastDiscoveryService {
+    async fn announce(&self, announcement: PeerAnnouncement) -> Result<(), DiscoveryError> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .await
+            .map_err(DiscoveryError::SocketFailed)?;
+        // Keep announcements on the local network, and let peers on this host hear them.
+        socket
+            .set_multicast_ttl_v4(1)
+            .map_err(DiscoveryError::SocketFailed)?;
+        socket
+            .set_multicast_loop_v4(true)
+            .map_err(DiscoveryError::SocketFailed)?;
+
+        let target = SocketAddrV4::new(self.group, self.port);
+        let payload = String::from(announcement);
+        eprintln!("Announcing '{}' on {}", payload, target);
+
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+            socket
+                .send_to(payload.as_bytes(), target)
+                .await
+                .map_err(DiscoveryError::SocketFailed)?;
+        }
+    }
+
+    async fn discover(&self, timeout: Duration) -> Result<Vec<DiscoveredPeer>, DiscoveryError> {
+        let mut peers: Vec<DiscoveredPeer> = Vec::new();
+        self.listen_until(Instant::now() + timeout, |peer| {
+            if !peers.contains(&peer) {
+                peers.push(peer);
+            }
+            false
+        })
+        .await?;
+        Ok(peers)
+    }
+
+    async fn resolve(
+        &self,
+        name: &str,
+        timeout: Duration,
+    ) -> Result<DiscoveredPeer, DiscoveryError> {
+        let mut found = None;
+        self.listen_until(Instant::now() + timeout, |peer| {
+            if peer.name == name {
+                found = Some(peer);
+                return true;
+            }
+            false
+        })
+        .await?;
+        found.ok_or_else(|| DiscoveryError::PeerNotFound(name.to_string()))
+    }
+}
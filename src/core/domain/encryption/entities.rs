@@ -0,0 +1,86 @@
+use std::fmt;
+
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+
+/// Plaintext bytes sealed together; every chunk but the last is this long.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+/// AEAD tag appended to every chunk.
+pub const TAG_LEN: usize = 16;
+/// Identifies files encrypted at rest, followed by the file's nonce prefix.
+pub const MAGIC: &[u8; 8] = b"FERRENC1";
+/// Random per-file part of every nonce; the chunk index makes up the rest.
+pub const PREFIX_LEN: usize = 16;
+/// Bytes before the first chunk.
+pub const HEADER_LEN: usize = MAGIC.len() + PREFIX_LEN;
+
+/// Key the node encrypts received files with. Stored hex-encoded in the file
+/// named by FERRIS_ENCRYPTION_KEY; without it the files can't be recovered.
+#[derive(Clone)]
+pub struct NodeKey([u8; 32]);
+
+impl NodeKey {
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        NodeKey(key)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl TryFrom<&str> for NodeKey {
+    type Error = EncryptionError;
+
+    fn try_from(value: &str) -> Result<Self, EncryptionError> {
+        let bytes = hex::decode(value.trim())
+            .map_err(|_| EncryptionError::InvalidKey("not hex".to_string()))?;
+        let key = <[u8; 32]>::try_from(bytes.as_slice()).map_err(|_| {
+            EncryptionError::InvalidKey(format!("{} bytes instead of 32", bytes.len()))
+        })?;
+        Ok(NodeKey(key))
+    }
+}
+
+// Keeps the key out of logs.
+impl fmt::Debug for NodeKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NodeKey(..)")
+    }
+}
+
+#[derive(Debug)]
+pub enum EncryptionError {
+    InvalidKey(String),
+    NotEncrypted,
+    /// Chunk `n` doesn't authenticate: wrong key, or the file was altered.
+    Corrupted(u64),
+    Truncated,
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for EncryptionError {
+    fn from(err: std::io::Error) -> Self {
+        EncryptionError::Io(err)
+    }
+}
+
+impl From<EncryptionError> for String {
+    fn from(err: EncryptionError) -> Self {
+        match err {
+            EncryptionError::InvalidKey(msg) => format!("Invalid encryption key: {}", msg),
+            EncryptionError::NotEncrypted => "Not an encrypted ferrisshare file".to_string(),
+            EncryptionError::Corrupted(chunk) => format!(
+                "Chunk {} failed authentication (wrong key or altered file)",
+                chunk
+            ),
+            EncryptionError::Truncated => "Encrypted file is truncated".to_string(),
+            EncryptionError::Io(e) => format!("I/O error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,653 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305, XNonce,
+    aead::{Aead, OsRng, Payload, rand_core::RngCore},
+};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+use crate::core::domain::encryption::entities::{
+    CHUNK_SIZE, EncryptionError, HEADER_LEN, MAGIC, NodeKey, PREFIX_LEN, TAG_LEN,
+};
+use crate::core::domain::storage::{
+    entities::{FileMetadata, ManifestEntry, StorageError, YeetBlock},
+    ports::{StorageRepository, StorageSession},
+    services::ZEROS,
+};
+
+/// One encrypted chunk on disk: a full chunk of plaintext and its tag.
+const RECORD_LEN: usize = CHUNK_SIZE + TAG_LEN;
+
+/// Where chunk `index` starts in an encrypted file.
+fn record_offset(index: u64) -> u64 {
+    HEADER_LEN as u64 + index * RECORD_LEN as u64
+}
+
+/// Size of `size` bytes once encrypted. Even an empty file has a chunk, so
+/// that cutting a file short is always detected.
+pub fn encrypted_len(size: u64) -> u64 {
+    let chunks = size.div_ceil(CHUNK_SIZE as u64).max(1);
+    HEADER_LEN as u64 + size + chunks * TAG_LEN as u64
+}
+
+/// Size of the plaintext of an encrypted file of `len` bytes, the inverse of
+/// `encrypted_len`. Only the layout is checked; reading the file authenticates it.
+fn plaintext_len(len: u64) -> Result<u64, EncryptionError> {
+    let sealed = len
+        .checked_sub(HEADER_LEN as u64)
+        .filter(|sealed| *sealed >= TAG_LEN as u64)
+        .ok_or(EncryptionError::Truncated)?;
+    let chunks = sealed.div_ceil(RECORD_LEN as u64);
+    sealed
+        .checked_sub(chunks * TAG_LEN as u64)
+        .ok_or(EncryptionError::Truncated)
+}
+
+/// Nonce of chunk `index`: the file's random prefix, then the index.
+fn nonce(prefix: &[u8; PREFIX_LEN], index: u64) -> XNonce {
+    let mut nonce = XNonce::default();
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..].copy_from_slice(&index.to_be_bytes());
+    nonce
+}
+
+/// The last chunk is sealed differently, so dropping trailing chunks or
+/// appending some makes the file fail authentication.
+fn seal(
+    cipher: &XChaCha20Poly1305,
+    prefix: &[u8; PREFIX_LEN],
+    index: u64,
+    last: bool,
+    plaintext: &[u8],
+) -> Vec<u8> {
+    let payload = Payload {
+        msg: plaintext,
+        aad: &[last as u8],
+    };
+    cipher
+        .encrypt(&nonce(prefix, index), payload)
+        .expect("chunks are far below the AEAD size limit")
+}
+
+fn open(
+    cipher: &XChaCha20Poly1305,
+    prefix: &[u8; PREFIX_LEN],
+    index: u64,
+    last: bool,
+    record: &[u8],
+) -> Result<Vec<u8>, EncryptionError> {
+    let payload = Payload {
+        msg: record,
+        aad: &[last as u8],
+    };
+    cipher
+        .decrypt(&nonce(prefix, index), payload)
+        .map_err(|_| EncryptionError::Corrupted(index))
+}
+
+/// Open chunk `index` without knowing whether it is the last one. Only a full
+/// record can be followed by more. Returns the plaintext and whether it was last.
+fn open_any(
+    cipher: &XChaCha20Poly1305,
+    prefix: &[u8; PREFIX_LEN],
+    index: u64,
+    record: &[u8],
+) -> Result<(Vec<u8>, bool), EncryptionError> {
+    if record.len() < TAG_LEN {
+        return Err(EncryptionError::Truncated);
+    }
+    if record.len() == RECORD_LEN
+        && let Ok(plaintext) = open(cipher, prefix, index, false, record)
+    {
+        return Ok((plaintext, false));
+    }
+    Ok((open(cipher, prefix, index, true, record)?, true))
+}
+
+/// Nonce prefix of a file from its header.
+fn parse_header(header: &[u8]) -> Result<[u8; PREFIX_LEN], EncryptionError> {
+    match header.split_at_checked(MAGIC.len()) {
+        Some((magic, prefix)) if magic == MAGIC && prefix.len() == PREFIX_LEN => {
+            Ok(prefix.try_into().expect("length checked above"))
+        }
+        _ => Err(EncryptionError::NotEncrypted),
+    }
+}
+
+fn storage_error(err: EncryptionError) -> StorageError {
+    StorageError::Unknown(String::from(err))
+}
+
+/// Read the key in `path`, or generate one there if the file doesn't exist.
+pub fn load_or_create_key(path: &Path) -> Result<NodeKey, EncryptionError> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => NodeKey::try_from(text.as_str()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if let Some(parent) = path.parent()
+                && !parent.as_os_str().is_empty()
+            {
+                std::fs::create_dir_all(parent)?;
+            }
+            let key = NodeKey::generate();
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            std::io::Write::write_all(&mut options.open(path)?, (key.to_hex() + "\n").as_bytes())?;
+            eprintln!(
+                "Generated a new encryption key in {}. Back it up: stored files can't be decrypted without it.",
+                path.display()
+            );
+            Ok(key)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Fill `buf` as far as possible. Returns less than its length only at end of input.
+async fn read_full<R>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// Decrypt a file stored by `EncryptingStorageRepository`, streaming it from
+/// `reader` to `writer`. Returns the plaintext size. Fails on a wrong key and
+/// on files that were altered, reordered or cut short; what was written to
+/// `writer` by then must not be trusted.
+pub async fn decrypt<R, W>(
+    key: &NodeKey,
+    mut reader: R,
+    mut writer: W,
+) -> Result<u64, EncryptionError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let cipher = XChaCha20Poly1305::new(key.as_bytes().into());
+    let mut header = [0u8; HEADER_LEN];
+    if read_full(&mut reader, &mut header).await? < HEADER_LEN {
+        return Err(EncryptionError::NotEncrypted);
+    }
+    let prefix = parse_header(&header)?;
+
+    let mut record = vec![0u8; RECORD_LEN];
+    let mut next = vec![0u8; RECORD_LEN];
+    let mut len = read_full(&mut reader, &mut record).await?;
+    let mut index = 0;
+    let mut total = 0;
+    loop {
+        if len < TAG_LEN {
+            return Err(EncryptionError::Truncated);
+        }
+        // Read ahead: a chunk is the last one when nothing follows it.
+        let next_len = if len == RECORD_LEN {
+            read_full(&mut reader, &mut next).await?
+        } else {
+            0
+        };
+        let last = next_len == 0;
+        let plaintext = open(&cipher, &prefix, index, last, &record[..len])?;
+        writer.write_all(&plaintext).await?;
+        total += plaintext.len() as u64;
+        if last {
+            break;
+        }
+        std::mem::swap(&mut record, &mut next);
+        len = next_len;
+        index += 1;
+    }
+    writer.flush().await?;
+    Ok(total)
+}
+
+/// A decrypted chunk kept for the next `read_range`.
+struct PlainChunk {
+    filename: String,
+    index: u64,
+    data: Vec<u8>,
+}
+
+/// Storage decorator encrypting files before they reach the wrapped storage.
+///
+/// Files are sealed in chunks of `CHUNK_SIZE` bytes with XChaCha20-Poly1305
+/// under the node key. Each file starts with a header holding a random nonce
+/// prefix, and the chunk index completes the nonce of each chunk. Reads,
+/// manifests and delta copies see the plaintext; `decrypt` recovers a file
+/// outside the node.
+#[derive(Clone)]
+pub struct EncryptingStorageRepository<S> {
+    inner: S,
+    cipher: XChaCha20Poly1305,
+    // delta signatures read one block at a time, mostly from the same chunk
+    last_chunk: Arc<Mutex<Option<PlainChunk>>>,
+}
+
+impl<S> EncryptingStorageRepository<S>
+where
+    S: StorageRepository,
+{
+    pub fn new(inner: S, key: &NodeKey) -> Self {
+        EncryptingStorageRepository {
+            inner,
+            cipher: XChaCha20Poly1305::new(key.as_bytes().into()),
+            last_chunk: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Drop the cached chunk of `filename` once the file changed.
+    async fn forget(&self, filename: &str) {
+        let mut last_chunk = self.last_chunk.lock().await;
+        if last_chunk.as_ref().is_some_and(|c| c.filename == filename) {
+            *last_chunk = None;
+        }
+    }
+
+    async fn read_prefix(&self, filename: &str) -> Result<[u8; PREFIX_LEN], StorageError> {
+        let header = self
+            .inner
+            .read_range(filename, 0, HEADER_LEN as u64)
+            .await?;
+        parse_header(&header).map_err(storage_error)
+    }
+
+    /// Plaintext of chunk `index` of `filename`, and whether it is the last.
+    /// None past the end of the file.
+    async fn read_chunk(
+        &self,
+        filename: &str,
+        prefix: &[u8; PREFIX_LEN],
+        index: u64,
+    ) -> Result<Option<(Vec<u8>, bool)>, StorageError> {
+        let record = self
+            .inner
+            .read_range(filename, record_offset(index), RECORD_LEN as u64)
+            .await?;
+        if record.is_empty() {
+            return Ok(None);
+        }
+        open_any(&self.cipher, prefix, index, &record)
+            .map(Some)
+            .map_err(storage_error)
+    }
+
+    /// Size and hash of the plaintext of a stored file.
+    async fn plaintext_entry(&self, entry: ManifestEntry) -> Result<ManifestEntry, StorageError> {
+        let prefix = self.read_prefix(&entry.path).await?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let mut index = 0;
+        loop {
+            let Some((data, last)) = self.read_chunk(&entry.path, &prefix, index).await? else {
+                return Err(storage_error(EncryptionError::Truncated));
+            };
+            hasher.update(&data);
+            size += data.len() as u64;
+            if last {
+                break;
+            }
+            index += 1;
+        }
+        Ok(ManifestEntry {
+            size,
+            sha256: hex::encode(hasher.finalize()),
+            ..entry
+        })
+    }
+}
+
+impl<S> StorageRepository for EncryptingStorageRepository<S>
+where
+    S: StorageRepository + Clone + Send + Sync + 'static,
+{
+    type Session = EncryptingSession<S>;
+
+    async fn open_file(
+        &self,
+        filename: &str,
+        size: Option<u64>,
+    ) -> Result<Self::Session, StorageError> {
+        let inner = self
+            .inner
+            .open_file(filename, size.map(encrypted_len))
+            .await?;
+        let mut prefix = [0u8; PREFIX_LEN];
+        OsRng.fill_bytes(&mut prefix);
+        Ok(EncryptingSession {
+            repository: self.clone(),
+            inner,
+            filename: filename.to_string(),
+            prefix,
+            chunk: 0,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            written: 0,
+        })
+    }
+
+    /// The nonce prefix and the chunk being filled were lost with the session.
+    async fn resume_file(
+        &self,
+        _filename: &str,
+        _size: Option<u64>,
+        _offset: u64,
+    ) -> Result<Self::Session, StorageError> {
+        Err(StorageError::Unknown(
+            "encrypted transfers can't go on from an earlier one".to_string(),
+        ))
+    }
+
+    async fn list_files(&self) -> Result<Vec<ManifestEntry>, StorageError> {
+        let mut entries = Vec::new();
+        for entry in self.inner.list_files().await? {
+            let path = entry.path.clone();
+            match self.plaintext_entry(entry).await {
+                Ok(entry) => entries.push(entry),
+                // e.g. files stored before encryption was turned on
+                Err(e) => eprintln!("Leaving {} out of the manifest: {:?}", path, e),
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn delete_file(&self, filename: &str) -> Result<(), StorageError> {
+        self.forget(filename).await;
+        self.inner.delete_file(filename).await
+    }
+
+    async fn read_range(
+        &self,
+        filename: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, StorageError> {
+        // `len` comes from the caller, possibly a peer; the buffer only grows
+        // with the plaintext actually read.
+        let mut data = Vec::with_capacity(len.min(CHUNK_SIZE as u64) as usize);
+        let mut index = offset / CHUNK_SIZE as u64;
+        let mut start = (offset % CHUNK_SIZE as u64) as usize;
+        let mut prefix = None;
+        let mut last_chunk = self.last_chunk.lock().await;
+        while (data.len() as u64) < len {
+            let cached = last_chunk
+                .as_ref()
+                .filter(|c| c.filename == filename && c.index == index);
+            if cached.is_none() {
+                let prefix = match prefix {
+                    Some(prefix) => prefix,
+                    None => *prefix.insert(self.read_prefix(filename).await?),
+                };
+                let Some((chunk, _)) = self.read_chunk(filename, &prefix, index).await? else {
+                    break;
+                };
+                *last_chunk = Some(PlainChunk {
+                    filename: filename.to_string(),
+                    index,
+                    data: chunk,
+                });
+            }
+            let chunk = &last_chunk.as_ref().expect("cached above").data;
+            if start >= chunk.len() {
+                break;
+            }
+            let take = (len as usize - data.len()).min(chunk.len() - start);
+            data.extend_from_slice(&chunk[start..start + take]);
+            // Only the last chunk is short.
+            if chunk.len() < CHUNK_SIZE {
+                break;
+            }
+            index += 1;
+            start = 0;
+        }
+        Ok(data)
+    }
+
+    async fn file_size(&self, filename: &str) -> Result<u64, StorageError> {
+        plaintext_len(self.inner.file_size(filename).await?).map_err(storage_error)
+    }
+
+    fn location(&self, filename: &str) -> String {
+        self.inner.location(filename)
+    }
+}
+
+/// Session of the wrapped storage that receives sealed chunks. Writes must
+/// arrive in order, and the chunk being filled stays in memory until it is
+/// full or the file is finalized.
+pub struct EncryptingSession<S>
+where
+    S: StorageRepository,
+{
+    repository: EncryptingStorageRepository<S>,
+    inner: S::Session,
+    filename: String,
+    prefix: [u8; PREFIX_LEN],
+    // index of the chunk being filled
+    chunk: u64,
+    buf: Vec<u8>,
+    // plaintext bytes received so far
+    written: u64,
+}
+
+impl<S> EncryptingSession<S>
+where
+    S: StorageRepository + Clone + Send + Sync + 'static,
+{
+    /// Append plaintext, sealing each chunk once it is full and more follows.
+    async fn push(&mut self, offset: u64, mut data: &[u8]) -> Result<(), StorageError> {
+        if offset != self.written {
+            return Err(StorageError::Unknown(format!(
+                "Out of order write at offset {} (expected {})",
+                offset, self.written
+            )));
+        }
+        while !data.is_empty() {
+            if self.buf.len() == CHUNK_SIZE {
+                self.seal_chunk(false).await?;
+            }
+            let n = (CHUNK_SIZE - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..n]);
+            self.written += n as u64;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    /// Hand the buffered chunk to the wrapped session, behind the header for the first one.
+    async fn seal_chunk(&mut self, last: bool) -> Result<(), StorageError> {
+        let sealed = seal(
+            &self.repository.cipher,
+            &self.prefix,
+            self.chunk,
+            last,
+            &self.buf,
+        );
+        let record = if self.chunk == 0 {
+            [MAGIC.as_slice(), &self.prefix, &sealed].concat()
+        } else {
+            sealed
+        };
+        let offset = match self.chunk {
+            0 => 0,
+            index => record_offset(index),
+        };
+        let block = YeetBlock::new(self.chunk, record.len() as u32, 0);
+        self.inner.write_block(offset, &block, &record).await?;
+        self.buf.clear();
+        self.chunk += 1;
+        Ok(())
+    }
+}
+
+impl<S> StorageSession for EncryptingSession<S>
+where
+    S: StorageRepository + Clone + Send + Sync + 'static,
+{
+    async fn write_block(
+        &mut self,
+        offset: u64,
+        _block: &YeetBlock,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        self.push(offset, data).await
+    }
+
+    /// Encrypted zeros aren't zeros, so holes are written out.
+    async fn write_hole(&mut self, offset: u64, len: u64) -> Result<(), StorageError> {
+        let mut done = 0;
+        while done < len {
+            let n = (len - done).min(ZEROS.len() as u64);
+            self.push(offset + done, &ZEROS[..n as usize]).await?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    async fn copy_range(
+        &mut self,
+        offset: u64,
+        len: u64,
+        dst_offset: u64,
+    ) -> Result<(), StorageError> {
+        let data = self
+            .repository
+            .read_range(&self.filename, offset, len)
+            .await?;
+        if (data.len() as u64) < len {
+            return Err(StorageError::Unknown(format!(
+                "Range {}+{} is past the end of the file",
+                offset, len
+            )));
+        }
+        self.push(dst_offset, &data).await
+    }
+
+    fn set_metadata(&mut self, metadata: FileMetadata) {
+        self.inner.set_metadata(metadata);
+    }
+
+    /// Only full chunks have been handed on; the one being filled can't be
+    /// sealed before the file ends.
+    async fn sync(&mut self) -> Result<(), StorageError> {
+        self.inner.sync().await
+    }
+
+    async fn finalize(mut self) -> Result<(), StorageError> {
+        self.seal_chunk(true).await?;
+        self.inner.finalize().await?;
+        self.repository.forget(&self.filename).await;
+        Ok(())
+    }
+
+    /// The chunk being filled is lost, like on `sync`.
+    async fn abandon(self) -> Result<(), StorageError> {
+        self.inner.abandon().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::repositories::memory::in_memory_storage_repository::InMemoryStorageRepository;
+
+    /// Two full chunks and a short one.
+    fn sample() -> Vec<u8> {
+        (0..2 * CHUNK_SIZE + 123).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Store `data` through an encrypting repository over `inner`, in blocks
+    /// that don't line up with the chunks.
+    async fn store(inner: &InMemoryStorageRepository, key: &NodeKey, data: &[u8]) {
+        let repository = EncryptingStorageRepository::new(inner.clone(), key);
+        let mut session = repository
+            .open_file("a.bin", Some(data.len() as u64))
+            .await
+            .unwrap();
+        for (index, block) in data.chunks(10_000).enumerate() {
+            let offset = index as u64 * 10_000;
+            let yeet = YeetBlock::new(index as u64, block.len() as u32, 0);
+            session.write_block(offset, &yeet, block).await.unwrap();
+        }
+        session.finalize().await.unwrap();
+    }
+
+    async fn decrypt_bytes(key: &NodeKey, sealed: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let mut plaintext = Vec::new();
+        decrypt(key, sealed, &mut plaintext).await?;
+        Ok(plaintext)
+    }
+
+    #[tokio::test]
+    async fn stored_files_decrypt_to_what_was_written() {
+        let inner = InMemoryStorageRepository::new();
+        let key = NodeKey::generate();
+        let data = sample();
+        store(&inner, &key, &data).await;
+
+        let sealed = inner.file("a.bin").unwrap();
+        assert_eq!(sealed.len() as u64, encrypted_len(data.len() as u64));
+        assert_eq!(decrypt_bytes(&key, &sealed).await.unwrap(), data);
+
+        let repository = EncryptingStorageRepository::new(inner.clone(), &key);
+        assert_eq!(
+            repository.file_size("a.bin").await.unwrap(),
+            data.len() as u64
+        );
+        let start = CHUNK_SIZE as u64 - 10;
+        let range = repository.read_range("a.bin", start, 20).await.unwrap();
+        assert_eq!(range, data[start as usize..start as usize + 20]);
+    }
+
+    #[tokio::test]
+    async fn empty_files_round_trip() {
+        let inner = InMemoryStorageRepository::new();
+        let key = NodeKey::generate();
+        store(&inner, &key, &[]).await;
+
+        let sealed = inner.file("a.bin").unwrap();
+        assert_eq!(sealed.len(), HEADER_LEN + TAG_LEN);
+        assert!(decrypt_bytes(&key, &sealed).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn altered_files_fail_authentication() {
+        let inner = InMemoryStorageRepository::new();
+        let key = NodeKey::generate();
+        store(&inner, &key, &sample()).await;
+        let sealed = inner.file("a.bin").unwrap();
+
+        let mut flipped = sealed.clone();
+        flipped[record_offset(1) as usize + 5] ^= 1;
+        assert!(matches!(
+            decrypt_bytes(&key, &flipped).await,
+            Err(EncryptionError::Corrupted(1))
+        ));
+
+        // Dropping the last chunk leaves one that wasn't sealed as the last.
+        let cut = &sealed[..record_offset(2) as usize];
+        assert!(matches!(
+            decrypt_bytes(&key, cut).await,
+            Err(EncryptionError::Corrupted(1))
+        ));
+
+        assert!(matches!(
+            decrypt_bytes(&NodeKey::generate(), &sealed).await,
+            Err(EncryptionError::Corrupted(0))
+        ));
+        assert!(matches!(
+            decrypt_bytes(&key, &sealed[HEADER_LEN..]).await,
+            Err(EncryptionError::NotEncrypted)
+        ));
+    }
+}
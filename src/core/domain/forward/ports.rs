@@ -0,0 +1,7 @@
+use tokio::sync::mpsc::Receiver;
+
+pub trait ForwardService {
+    /// Deliver every filename received on `jobs` downstream, along with the
+    /// jobs still pending from earlier runs, until the channel closes.
+    fn run(&self, jobs: Receiver<String>) -> impl Future<Output = ()> + Send;
+}
@@ -0,0 +1,323 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+use crate::core::domain::forward::entities::RetryPolicy;
+use crate::core::domain::forward::ports::ForwardService;
+use crate::core::domain::queue::entities::{JobState, QueueError, QueueJob, unix_now};
+use crate::core::domain::queue::ports::QueueRepository;
+use crate::core::domain::sender::ports::SenderService;
+use crate::core::domain::storage::entities::{
+    FileMetadata, ManifestEntry, StorageError, YeetBlock,
+};
+use crate::core::domain::storage::ports::{StorageRepository, StorageSession};
+
+/// Delivers finalized files to a downstream node, one at a time and in the
+/// order they were received. A failing downstream holds up the queue behind
+/// it, since every job goes to the same place anyway.
+///
+/// Jobs are written to `journal` as soon as they arrive and leave it once
+/// delivered, so files still waiting when the node stops are forwarded after
+/// a restart. Jobs that ran out of attempts stay in it as failed.
+#[derive(Debug, Clone)]
+pub struct ForwardServiceImpl<T, R>
+where
+    T: SenderService,
+    R: QueueRepository,
+{
+    sender: T,
+    journal: R,
+    downstream: String,
+    base_path: String,
+    policy: RetryPolicy,
+    delete_after: bool,
+}
+
+impl<T, R> ForwardServiceImpl<T, R>
+where
+    T: SenderService + Send + Sync,
+    R: QueueRepository + Send + Sync,
+{
+    /// `base_path` is where the local storage keeps finalized files.
+    pub fn new(sender: T, journal: R, downstream: &str, base_path: &str) -> Self {
+        ForwardServiceImpl {
+            sender,
+            journal,
+            downstream: downstream.to_string(),
+            base_path: base_path.to_string(),
+            policy: RetryPolicy::default(),
+            delete_after: false,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Remove the local copy once the downstream node acknowledged it.
+    pub fn with_delete_after(mut self, delete_after: bool) -> Self {
+        self.delete_after = delete_after;
+        self
+    }
+
+    /// Record every filename from `jobs` as a pending job, waking the worker.
+    async fn intake(&self, mut jobs: Receiver<String>, wake: &Notify) {
+        while let Some(filename) = jobs.recv().await {
+            let path = PathBuf::from(&self.base_path).join(&filename);
+            let path = tokio::fs::canonicalize(&path).await.unwrap_or(path);
+            let addr = self.downstream.clone();
+            let queued = self
+                .journal
+                .update(move |jobs| {
+                    let now = unix_now();
+                    jobs.push(QueueJob {
+                        id: jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1,
+                        path,
+                        filename,
+                        addr,
+                        state: JobState::Pending,
+                        attempts: 0,
+                        added_at: now,
+                        next_attempt_at: now,
+                        last_error: None,
+                    });
+                })
+                .await;
+            if let Err(e) = queued {
+                eprintln!("Failed to record a forward job: {}", String::from(e));
+            }
+            wake.notify_one();
+        }
+    }
+
+    /// Deliver pending jobs oldest first, waiting for `wake` when there are none.
+    async fn work(&self, wake: &Notify) -> Result<(), QueueError> {
+        loop {
+            let next = self
+                .journal
+                .load()
+                .await?
+                .into_iter()
+                .filter(|job| job.state == JobState::Pending && job.addr == self.downstream)
+                .min_by_key(|job| job.id);
+            match next {
+                Some(job) => self.deliver(job).await?,
+                None => wake.notified().await,
+            }
+        }
+    }
+
+    async fn deliver(&self, mut job: QueueJob) -> Result<(), QueueError> {
+        loop {
+            // Still backing off from before a restart.
+            let now = unix_now();
+            if job.next_attempt_at > now {
+                tokio::time::sleep(Duration::from_secs(job.next_attempt_at - now)).await;
+            }
+            job.attempts += 1;
+            match self
+                .sender
+                .send_file(&self.downstream, &job.path, &job.filename)
+                .await
+            {
+                Ok(report) => {
+                    eprintln!(
+                        "Forwarded {} ({} bytes) to {}",
+                        report.filename, report.bytes, self.downstream
+                    );
+                    if self.delete_after
+                        && let Err(e) = tokio::fs::remove_file(&job.path).await
+                    {
+                        eprintln!("Failed to delete forwarded {}: {}", job.filename, e);
+                    }
+                    let id = job.id;
+                    return self
+                        .journal
+                        .update(move |jobs| jobs.retain(|job| job.id != id))
+                        .await;
+                }
+                Err(e) => {
+                    let reason = String::from(e);
+                    job.last_error = Some(reason.clone());
+                    if self.policy.exhausted(job.attempts) {
+                        eprintln!(
+                            "Giving up forwarding {} after {} attempts: {}",
+                            job.filename, job.attempts, reason
+                        );
+                        job.state = JobState::Failed;
+                        return self.save(job).await;
+                    }
+                    let delay = self.policy.backoff(job.attempts);
+                    eprintln!(
+                        "Forwarding {} failed (attempt {}): {}; retrying in {:?}",
+                        job.filename, job.attempts, reason, delay
+                    );
+                    job.next_attempt_at = unix_now() + delay.as_secs();
+                    self.save(job.clone()).await?;
+                }
+            }
+        }
+    }
+
+    async fn save(&self, job: QueueJob) -> Result<(), QueueError> {
+        self.journal
+            .update(move |jobs| {
+                if let Some(stored) = jobs.iter_mut().find(|stored| stored.id == job.id) {
+                    *stored = job;
+                }
+            })
+            .await
+    }
+}
+
+impl<T, R> ForwardService for ForwardServiceImpl<T, R>
+where
+    T: SenderService + Send + Sync,
+    R: QueueRepository + Send + Sync,
+{
+    async fn run(&self, jobs: Receiver<String>) {
+        let wake = Notify::new();
+        // Picks up whatever was left over from before a restart first.
+        wake.notify_one();
+        tokio::select! {
+            () = self.intake(jobs, &wake) => {}
+            Err(e) = self.work(&wake) => {
+                eprintln!("Forwarder stopped: {}", String::from(e));
+            }
+        }
+    }
+}
+
+/// Storage decorator that queues every successfully finalized file for
+/// forwarding. Everything else is passed through unchanged.
+#[derive(Debug, Clone)]
+pub struct ForwardingStorageRepository<S>
+where
+    S: StorageRepository,
+{
+    inner: S,
+    jobs: Sender<String>,
+}
+
+impl<S> ForwardingStorageRepository<S>
+where
+    S: StorageRepository,
+{
+    pub fn new(inner: S, jobs: Sender<String>) -> Self {
+        ForwardingStorageRepository { inner, jobs }
+    }
+}
+
+impl<S> StorageRepository for ForwardingStorageRepository<S>
+where
+    S: StorageRepository + Send + Sync,
+{
+    type Session = ForwardingSession<S::Session>;
+
+    async fn open_file(
+        &self,
+        filename: &str,
+        size: Option<u64>,
+    ) -> Result<Self::Session, StorageError> {
+        Ok(ForwardingSession {
+            inner: self.inner.open_file(filename, size).await?,
+            filename: filename.to_string(),
+            jobs: self.jobs.clone(),
+        })
+    }
+
+    async fn resume_file(
+        &self,
+        filename: &str,
+        size: Option<u64>,
+        offset: u64,
+    ) -> Result<Self::Session, StorageError> {
+        Ok(ForwardingSession {
+            inner: self.inner.resume_file(filename, size, offset).await?,
+            filename: filename.to_string(),
+            jobs: self.jobs.clone(),
+        })
+    }
+
+    async fn list_files(&self) -> Result<Vec<ManifestEntry>, StorageError> {
+        self.inner.list_files().await
+    }
+
+    async fn delete_file(&self, filename: &str) -> Result<(), StorageError> {
+        self.inner.delete_file(filename).await
+    }
+
+    async fn read_range(
+        &self,
+        filename: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, StorageError> {
+        self.inner.read_range(filename, offset, len).await
+    }
+
+    async fn file_size(&self, filename: &str) -> Result<u64, StorageError> {
+        self.inner.file_size(filename).await
+    }
+
+    fn location(&self, filename: &str) -> String {
+        self.inner.location(filename)
+    }
+}
+
+/// Session of the wrapped storage that queues its file once finalized.
+pub struct ForwardingSession<T> {
+    inner: T,
+    filename: String,
+    jobs: Sender<String>,
+}
+
+impl<T> StorageSession for ForwardingSession<T>
+where
+    T: StorageSession + Send + Sync,
+{
+    async fn write_block(
+        &mut self,
+        offset: u64,
+        block: &YeetBlock,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        self.inner.write_block(offset, block, data).await
+    }
+
+    async fn write_hole(&mut self, offset: u64, len: u64) -> Result<(), StorageError> {
+        self.inner.write_hole(offset, len).await
+    }
+
+    async fn copy_range(
+        &mut self,
+        offset: u64,
+        len: u64,
+        dst_offset: u64,
+    ) -> Result<(), StorageError> {
+        self.inner.copy_range(offset, len, dst_offset).await
+    }
+
+    fn set_metadata(&mut self, metadata: FileMetadata) {
+        self.inner.set_metadata(metadata);
+    }
+
+    async fn sync(&mut self) -> Result<(), StorageError> {
+        self.inner.sync().await
+    }
+
+    async fn finalize(self) -> Result<(), StorageError> {
+        self.inner.finalize().await?;
+        if self.jobs.send(self.filename.clone()).await.is_err() {
+            eprintln!("Forwarder stopped, {} stays local", self.filename);
+        }
+        Ok(())
+    }
+
+    async fn abandon(self) -> Result<(), StorageError> {
+        self.inner.abandon().await
+    }
+}
@@ -0,0 +1,54 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::core::domain::journal::entities::TransferId;
+
+/// A finalized file waiting for the post-receive hook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookJob {
+    pub transfer: TransferId,
+    pub filename: String,
+    /// Where the storage put the file, as journaled.
+    pub final_path: String,
+    pub size: u64,
+    pub peer: Option<SocketAddr>,
+}
+
+/// What becomes of a file once its hook exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookVerdict {
+    Keep,
+    Quarantine,
+    Delete,
+}
+
+impl HookVerdict {
+    /// 0 keeps the file, 1 quarantines it and 2 deletes it. Anything else,
+    /// including a hook killed by a signal, quarantines it: a scanner that
+    /// crashed hasn't cleared the file.
+    pub fn from_exit_code(code: Option<i32>) -> Self {
+        match code {
+            Some(0) => HookVerdict::Keep,
+            Some(2) => HookVerdict::Delete,
+            _ => HookVerdict::Quarantine,
+        }
+    }
+}
+
+/// How the post-receive hook is run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookSettings {
+    /// Executable run once per finalized file.
+    pub command: PathBuf,
+    /// Hooks running at the same time; further files wait their turn.
+    pub workers: usize,
+    /// A hook still running after this long is killed and the file quarantined.
+    pub timeout: Duration,
+    /// Where quarantined files are moved, outside the storage.
+    pub quarantine_dir: PathBuf,
+    /// Where the hook gets a plaintext copy of each file when the storage
+    /// doesn't keep one on disk (CAS, encryption, memory, S3). None hands it
+    /// the stored file itself.
+    pub staging_dir: Option<PathBuf>,
+}
@@ -0,0 +1,8 @@
+use tokio::sync::mpsc::Receiver;
+
+use crate::core::domain::hook::entities::HookJob;
+
+pub trait HookService {
+    /// Run the hook for every file received on `jobs`, until the channel closes.
+    fn run(&self, jobs: Receiver<HookJob>) -> impl Future<Output = ()> + Send;
+}
@@ -0,0 +1,265 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+use crate::core::domain::hook::entities::{HookJob, HookSettings, HookVerdict};
+use crate::core::domain::hook::ports::HookService;
+use crate::core::domain::storage::ports::StorageRepository;
+
+/// Bytes read from the storage at a time when hashing or copying out a file.
+const READ_WINDOW: u64 = 1024 * 1024;
+
+/// Runs the post-receive hook on finalized files, at most `workers` at a time,
+/// and keeps, quarantines or deletes each file according to its exit code.
+#[derive(Debug, Clone)]
+pub struct HookServiceImpl<S>
+where
+    S: StorageRepository,
+{
+    storage: S,
+    settings: HookSettings,
+    kept: Option<Sender<String>>,
+}
+
+impl<S> HookServiceImpl<S>
+where
+    S: StorageRepository + Clone + Send + Sync + 'static,
+{
+    /// `storage` is the one the files were received into.
+    pub fn new(storage: S, settings: HookSettings) -> Self {
+        HookServiceImpl {
+            storage,
+            settings,
+            kept: None,
+        }
+    }
+
+    /// Pass the name of every file the hook kept on to `kept`, e.g. the
+    /// forwarder's queue, so nothing leaves the node before its hook cleared it.
+    pub fn with_kept(mut self, kept: Sender<String>) -> Self {
+        self.kept = Some(kept);
+        self
+    }
+
+    async fn handle(&self, job: HookJob) {
+        // Each transfer stages into its own directory, removed with the copy.
+        let staging = self
+            .settings
+            .staging_dir
+            .as_ref()
+            .map(|dir| dir.join(job.transfer.0.to_string()));
+        let staged = staging.as_ref().map(|dir| dir.join(&job.filename));
+        // Read through the storage, so the digest and the staged copy are of
+        // the received content whatever the backend keeps on disk.
+        let read = self.read_out(&job.filename, staged.as_deref()).await;
+        let verdict = match &read {
+            Ok(sha256) => {
+                let path = match &staged {
+                    Some(staged) => staged.display().to_string(),
+                    None => job.final_path.clone(),
+                };
+                Some(self.run_hook(&job, &path, sha256).await)
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to read {} for its hook, leaving it in place: {}",
+                    job.filename, e
+                );
+                None
+            }
+        };
+        if let Some(staging) = &staging {
+            let _ = tokio::fs::remove_dir_all(staging).await;
+        }
+        let (Ok(sha256), Some(verdict)) = (read, verdict) else {
+            return;
+        };
+
+        match verdict {
+            HookVerdict::Keep => {
+                if let Some(kept) = &self.kept
+                    && kept.send(job.filename.clone()).await.is_err()
+                {
+                    eprintln!("Forwarder stopped, {} stays local", job.filename);
+                }
+            }
+            HookVerdict::Delete => match self.delete(&job, &sha256).await {
+                Ok(()) => eprintln!("Hook deleted {}", job.filename),
+                Err(e) => eprintln!(
+                    "Failed to delete {} after its hook, it stays in place: {}",
+                    job.filename, e
+                ),
+            },
+            HookVerdict::Quarantine => match self.quarantine(&job, &sha256).await {
+                Ok(path) => eprintln!("Hook quarantined {} to {}", job.filename, path.display()),
+                Err(e) => eprintln!(
+                    "Failed to quarantine {}, it stays in place: {}",
+                    job.filename, e
+                ),
+            },
+        }
+    }
+
+    async fn run_hook(&self, job: &HookJob, path: &str, sha256: &str) -> HookVerdict {
+        let mut command = Command::new(&self.settings.command);
+        command
+            .env("FERRIS_HOOK_PATH", path)
+            .env("FERRIS_HOOK_FILENAME", &job.filename)
+            .env("FERRIS_HOOK_SIZE", job.size.to_string())
+            .env(
+                "FERRIS_HOOK_PEER",
+                job.peer.map(|peer| peer.to_string()).unwrap_or_default(),
+            )
+            .env("FERRIS_HOOK_SHA256", sha256)
+            .env("FERRIS_HOOK_TRANSFER", job.transfer.0.to_string())
+            .stdin(Stdio::null())
+            // Stdout may be carrying received data (FERRIS_OUTPUT=stdout).
+            .stdout(std::io::stderr())
+            .kill_on_drop(true);
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!(
+                    "Failed to run hook {} for {}: {}",
+                    self.settings.command.display(),
+                    job.filename,
+                    e
+                );
+                return HookVerdict::Quarantine;
+            }
+        };
+        match tokio::time::timeout(self.settings.timeout, child.wait()).await {
+            Ok(Ok(status)) => {
+                if !matches!(status.code(), Some(0..=2)) {
+                    eprintln!("Hook for {} ended with {}", job.filename, status);
+                }
+                HookVerdict::from_exit_code(status.code())
+            }
+            Ok(Err(e)) => {
+                eprintln!("Failed to wait for the hook of {}: {}", job.filename, e);
+                HookVerdict::Quarantine
+            }
+            Err(_) => {
+                eprintln!(
+                    "Hook for {} still running after {:?}, killing it",
+                    job.filename, self.settings.timeout
+                );
+                if let Err(e) = child.kill().await {
+                    eprintln!("Failed to kill the hook of {}: {}", job.filename, e);
+                }
+                HookVerdict::Quarantine
+            }
+        }
+    }
+
+    /// Hash the stored `filename` through the storage, writing it to `copy`
+    /// on the way when given. The copy is removed again if anything fails.
+    async fn read_out(&self, filename: &str, copy: Option<&Path>) -> Result<String, String> {
+        let mut file = match copy {
+            Some(path) => Some(create_private(path).await.map_err(|e| e.to_string())?),
+            None => None,
+        };
+        let result = async {
+            let mut hasher = Sha256::new();
+            let mut offset = 0;
+            // Up to the end of what is stored now, which is what a verdict applies to.
+            loop {
+                let data = self
+                    .storage
+                    .read_range(filename, offset, READ_WINDOW)
+                    .await
+                    .map_err(String::from)?;
+                hasher.update(&data);
+                if let Some(file) = &mut file {
+                    file.write_all(&data).await.map_err(|e| e.to_string())?;
+                }
+                if (data.len() as u64) < READ_WINDOW {
+                    break;
+                }
+                offset += READ_WINDOW;
+            }
+            if let Some(file) = &mut file {
+                file.sync_all().await.map_err(|e| e.to_string())?;
+            }
+            Ok(hex::encode(hasher.finalize()))
+        }
+        .await;
+        if result.is_err()
+            && let Some(path) = copy
+        {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        result
+    }
+
+    /// Delete the file, unless a newer transfer replaced it while the hook ran.
+    async fn delete(&self, job: &HookJob, sha256: &str) -> Result<(), String> {
+        if self.read_out(&job.filename, None).await? != sha256 {
+            return Err("replaced since its hook ran".to_string());
+        }
+        self.storage
+            .delete_file(&job.filename)
+            .await
+            .map_err(String::from)
+    }
+
+    /// Copy the file out of the storage into the quarantine directory, then
+    /// delete it. The copy is named after the transfer, so quarantining the
+    /// same name twice keeps both. A file replaced by a newer transfer while
+    /// the hook ran is left alone.
+    async fn quarantine(&self, job: &HookJob, sha256: &str) -> Result<PathBuf, String> {
+        let path = self
+            .settings
+            .quarantine_dir
+            .join(job.transfer.0.to_string())
+            .join(&job.filename);
+        if self.read_out(&job.filename, Some(&path)).await? != sha256 {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err("replaced since its hook ran".to_string());
+        }
+        self.storage
+            .delete_file(&job.filename)
+            .await
+            .map_err(String::from)?;
+        Ok(path)
+    }
+}
+
+/// Create `path` and its parent directories, readable by the node only: it
+/// may hold content that is encrypted in the storage.
+async fn create_private(path: &Path) -> std::io::Result<tokio::fs::File> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path).await
+}
+
+impl<S> HookService for HookServiceImpl<S>
+where
+    S: StorageRepository + Clone + Send + Sync + 'static,
+{
+    async fn run(&self, mut jobs: Receiver<HookJob>) {
+        let workers = Arc::new(Semaphore::new(self.settings.workers.max(1)));
+        while let Some(job) = jobs.recv().await {
+            // Waits for a free worker; the queue absorbs files meanwhile.
+            let Ok(permit) = Arc::clone(&workers).acquire_owned().await else {
+                return;
+            };
+            let service = self.clone();
+            tokio::spawn(async move {
+                service.handle(job).await;
+                drop(permit);
+            });
+        }
+    }
+}
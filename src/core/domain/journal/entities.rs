@@ -0,0 +1,94 @@
+use serde::Serialize;
+
+/// Identifies one transfer in the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct TransferId(pub i64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferStatus {
+    InProgress,
+    Completed,
+    Failed,
+    Rejected,
+}
+
+impl From<TransferStatus> for &'static str {
+    fn from(status: TransferStatus) -> Self {
+        match status {
+            TransferStatus::InProgress => "in_progress",
+            TransferStatus::Completed => "completed",
+            TransferStatus::Failed => "failed",
+            TransferStatus::Rejected => "rejected",
+        }
+    }
+}
+
+impl TryFrom<&str> for TransferStatus {
+    type Error = JournalError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "in_progress" => Ok(TransferStatus::InProgress),
+            "completed" => Ok(TransferStatus::Completed),
+            "failed" => Ok(TransferStatus::Failed),
+            "rejected" => Ok(TransferStatus::Rejected),
+            other => Err(JournalError::Corrupt(format!(
+                "unknown outcome '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// How a transfer ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferOutcome {
+    /// Stored; `final_path` is where the storage backend put it.
+    Completed {
+        final_path: String,
+    },
+    Failed(String),
+    /// Refused by the gate before any data was exchanged.
+    Rejected(String),
+}
+
+/// One transfer as recorded in the journal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TransferRecord {
+    pub id: TransferId,
+    pub peer: Option<String>,
+    pub filename: String,
+    /// None for STREAM transfers.
+    pub size: Option<u64>,
+    /// Size of the blocks announced by the sender, once known.
+    pub block_size: Option<u64>,
+    /// Unix seconds.
+    pub started_at: u64,
+    /// Unix seconds; None while in progress.
+    pub ended_at: Option<u64>,
+    pub status: TransferStatus,
+    pub error: Option<String>,
+    pub final_path: Option<String>,
+    pub blocks_received: u64,
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Database(String),
+    Corrupt(String),
+    UnknownTransfer(TransferId),
+    /// The transfer is still being received on another connection.
+    InProgress(TransferId),
+}
+
+impl From<JournalError> for String {
+    fn from(err: JournalError) -> Self {
+        match err {
+            JournalError::Database(msg) => format!("Journal database error: {}", msg),
+            JournalError::Corrupt(msg) => format!("Corrupt journal: {}", msg),
+            JournalError::UnknownTransfer(id) => format!("Unknown transfer {}", id.0),
+            JournalError::InProgress(id) => format!("Transfer {} is still in progress", id.0),
+        }
+    }
+}
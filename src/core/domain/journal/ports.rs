@@ -0,0 +1,73 @@
+use crate::core::domain::{
+    command::entities::IncomingTransfer,
+    journal::entities::{JournalError, TransferId, TransferOutcome, TransferRecord},
+};
+
+/// Record of every transfer, and of the blocks received for the ones in
+/// progress. The command service asks it which blocks a transfer already has.
+pub trait TransferJournal {
+    /// Record a transfer announced by HELLO, STREAM or DELTA.
+    fn begin(
+        &self,
+        transfer: &IncomingTransfer,
+        block_size: Option<u64>,
+    ) -> impl Future<Output = Result<TransferId, JournalError>> + Send + Sync;
+    /// Reopen the latest transfer of `transfer.filename` if it was cut short
+    /// and announced the same size and content hash, so it can go on from the
+    /// blocks it has. Rejected transfers don't count, as they stored nothing.
+    /// None when the new announcement has no hash to match.
+    ///
+    /// Cut short means failed, or left in progress by an earlier run of the
+    /// node. A transfer still in progress in this one is refused with
+    /// `JournalError::InProgress`, and reopening claims it, so two
+    /// announcements never get the same transfer.
+    fn resume(
+        &self,
+        transfer: &IncomingTransfer,
+    ) -> impl Future<Output = Result<Option<TransferId>, JournalError>> + Send + Sync;
+    /// Mark block `index` of `size` raw bytes as stored. The first block sets the
+    /// transfer's block size if the announcement didn't. Journals may hold the
+    /// record back until the next `flush`.
+    fn record_block(
+        &self,
+        id: TransferId,
+        index: u64,
+        size: u64,
+    ) -> impl Future<Output = Result<(), JournalError>> + Send + Sync;
+    /// Block size announced for the transfer, or set by its first block.
+    fn block_size(
+        &self,
+        id: TransferId,
+    ) -> impl Future<Output = Result<Option<u64>, JournalError>> + Send + Sync;
+    fn has_block(
+        &self,
+        id: TransferId,
+        index: u64,
+    ) -> impl Future<Output = Result<bool, JournalError>> + Send + Sync;
+    fn block_count(
+        &self,
+        id: TransferId,
+    ) -> impl Future<Output = Result<u64, JournalError>> + Send + Sync;
+    /// Indices of every block stored so far, for resuming a transfer.
+    fn received_blocks(
+        &self,
+        id: TransferId,
+    ) -> impl Future<Output = Result<Vec<u64>, JournalError>> + Send + Sync;
+    /// Make the blocks recorded since the last flush durable. Call it once the
+    /// storage made those blocks durable too, so the journal never lists
+    /// blocks a crash took from the partial copy.
+    fn flush(&self, id: TransferId)
+    -> impl Future<Output = Result<(), JournalError>> + Send + Sync;
+    /// Close the transfer, flushing its blocks first. A failed transfer keeps its first error; completing
+    /// it always wins.
+    fn finish(
+        &self,
+        id: TransferId,
+        outcome: &TransferOutcome,
+    ) -> impl Future<Output = Result<(), JournalError>> + Send + Sync;
+    /// The `limit` most recent transfers, newest first.
+    fn history(
+        &self,
+        limit: usize,
+    ) -> impl Future<Output = Result<Vec<TransferRecord>, JournalError>> + Send + Sync;
+}
@@ -0,0 +1,301 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::core::domain::{
+    command::entities::IncomingTransfer,
+    journal::{
+        entities::{JournalError, TransferId, TransferOutcome, TransferRecord, TransferStatus},
+        ports::TransferJournal,
+    },
+    queue::entities::unix_now,
+};
+
+/// Closed transfers kept in memory. Older ones are dropped, and can no
+/// longer be resumed.
+const HISTORY_LIMIT: usize = 1000;
+
+#[derive(Default)]
+struct Transfers {
+    records: HashMap<TransferId, TransferRecord>,
+    blocks: HashMap<TransferId, HashSet<u64>>,
+    // content hash announced for each transfer that had one
+    hashes: HashMap<TransferId, String>,
+    // closed transfers, oldest first; a reopened one may still be listed
+    closed: VecDeque<TransferId>,
+    last_id: i64,
+}
+
+impl Transfers {
+    /// Note that `id` was closed, dropping the oldest closed transfers past
+    /// `HISTORY_LIMIT`.
+    fn close(&mut self, id: TransferId) {
+        self.closed.push_back(id);
+        while self.closed.len() > HISTORY_LIMIT {
+            let Some(oldest) = self.closed.pop_front() else {
+                break;
+            };
+            // Reopened by a RESUME since: it is listed again once it closes.
+            if self
+                .records
+                .get(&oldest)
+                .is_some_and(|r| r.status == TransferStatus::InProgress)
+            {
+                continue;
+            }
+            self.records.remove(&oldest);
+            self.blocks.remove(&oldest);
+            self.hashes.remove(&oldest);
+        }
+    }
+}
+
+/// Journal kept in memory, for nodes that don't need history across restarts.
+/// Clones share the same journal.
+#[derive(Clone, Default)]
+pub struct InMemoryTransferJournal {
+    transfers: Arc<Mutex<Transfers>>,
+}
+
+impl InMemoryTransferJournal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_record<T>(
+        &self,
+        id: TransferId,
+        f: impl FnOnce(&mut TransferRecord, &mut HashSet<u64>) -> T,
+    ) -> Result<T, JournalError> {
+        let mut transfers = self.transfers.lock().unwrap_or_else(|e| e.into_inner());
+        let Transfers {
+            records, blocks, ..
+        } = &mut *transfers;
+        let record = records
+            .get_mut(&id)
+            .ok_or(JournalError::UnknownTransfer(id))?;
+        Ok(f(record, blocks.entry(id).or_default()))
+    }
+}
+
+impl TransferJournal for InMemoryTransferJournal {
+    async fn begin(
+        &self,
+        transfer: &IncomingTransfer,
+        block_size: Option<u64>,
+    ) -> Result<TransferId, JournalError> {
+        let mut transfers = self.transfers.lock().unwrap_or_else(|e| e.into_inner());
+        transfers.last_id += 1;
+        let id = TransferId(transfers.last_id);
+        transfers.records.insert(
+            id,
+            TransferRecord {
+                id,
+                peer: transfer.peer.map(|p| p.to_string()),
+                filename: transfer.filename.clone(),
+                size: transfer.filesize,
+                block_size,
+                started_at: unix_now(),
+                ended_at: None,
+                status: TransferStatus::InProgress,
+                error: None,
+                final_path: None,
+                blocks_received: 0,
+            },
+        );
+        if let Some(sha256) = &transfer.sha256 {
+            transfers.hashes.insert(id, sha256.clone());
+        }
+        Ok(id)
+    }
+
+    async fn resume(
+        &self,
+        transfer: &IncomingTransfer,
+    ) -> Result<Option<TransferId>, JournalError> {
+        let Some(sha256) = &transfer.sha256 else {
+            return Ok(None);
+        };
+        let mut transfers = self.transfers.lock().unwrap_or_else(|e| e.into_inner());
+        let Transfers {
+            records, hashes, ..
+        } = &mut *transfers;
+        let Some(record) = records
+            .values_mut()
+            .filter(|r| r.filename == transfer.filename && r.status != TransferStatus::Rejected)
+            .max_by_key(|r| r.id.0)
+        else {
+            return Ok(None);
+        };
+        if record.size != transfer.filesize || hashes.get(&record.id) != Some(sha256) {
+            return Ok(None);
+        }
+        match record.status {
+            TransferStatus::Failed => {}
+            // Nothing outlives the process here, so it is still being received.
+            TransferStatus::InProgress => return Err(JournalError::InProgress(record.id)),
+            _ => return Ok(None),
+        }
+        record.peer = transfer.peer.map(|p| p.to_string());
+        record.status = TransferStatus::InProgress;
+        record.error = None;
+        record.ended_at = None;
+        Ok(Some(record.id))
+    }
+
+    async fn record_block(
+        &self,
+        id: TransferId,
+        index: u64,
+        size: u64,
+    ) -> Result<(), JournalError> {
+        self.with_record(id, |record, blocks| {
+            if blocks.insert(index) {
+                record.blocks_received += 1;
+            }
+            record.block_size.get_or_insert(size);
+        })
+    }
+
+    async fn block_size(&self, id: TransferId) -> Result<Option<u64>, JournalError> {
+        self.with_record(id, |record, _| record.block_size)
+    }
+
+    async fn has_block(&self, id: TransferId, index: u64) -> Result<bool, JournalError> {
+        self.with_record(id, |_, blocks| blocks.contains(&index))
+    }
+
+    async fn block_count(&self, id: TransferId) -> Result<u64, JournalError> {
+        self.with_record(id, |record, _| record.blocks_received)
+    }
+
+    async fn received_blocks(&self, id: TransferId) -> Result<Vec<u64>, JournalError> {
+        self.with_record(id, |_, blocks| {
+            let mut indices: Vec<u64> = blocks.iter().copied().collect();
+            indices.sort_unstable();
+            indices
+        })
+    }
+
+    /// Nothing is held back.
+    async fn flush(&self, id: TransferId) -> Result<(), JournalError> {
+        self.with_record(id, |_, _| ())
+    }
+
+    async fn finish(&self, id: TransferId, outcome: &TransferOutcome) -> Result<(), JournalError> {
+        let mut transfers = self.transfers.lock().unwrap_or_else(|e| e.into_inner());
+        let Transfers {
+            records, blocks, ..
+        } = &mut *transfers;
+        let record = records
+            .get_mut(&id)
+            .ok_or(JournalError::UnknownTransfer(id))?;
+        let was_open = record.status == TransferStatus::InProgress;
+        match outcome {
+            TransferOutcome::Completed { final_path } => {
+                record.status = TransferStatus::Completed;
+                record.error = None;
+                record.final_path = Some(final_path.clone());
+                record.ended_at = Some(unix_now());
+                // Nothing left to resume.
+                blocks.remove(&id);
+            }
+            TransferOutcome::Failed(error) | TransferOutcome::Rejected(error) if was_open => {
+                record.status = match outcome {
+                    TransferOutcome::Rejected(_) => TransferStatus::Rejected,
+                    _ => TransferStatus::Failed,
+                };
+                record.error = Some(error.clone());
+                record.ended_at = Some(unix_now());
+            }
+            _ => {}
+        }
+        if was_open {
+            transfers.close(id);
+        }
+        Ok(())
+    }
+
+    async fn history(&self, limit: usize) -> Result<Vec<TransferRecord>, JournalError> {
+        let transfers = self.transfers.lock().unwrap_or_else(|e| e.into_inner());
+        let mut records: Vec<TransferRecord> = transfers.records.values().cloned().collect();
+        records.sort_unstable_by_key(|r| std::cmp::Reverse(r.id.0));
+        records.truncate(limit);
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::domain::command::entities::TransferKind;
+
+    fn announced(filename: &str) -> IncomingTransfer {
+        IncomingTransfer {
+            kind: TransferKind::Receive,
+            peer: None,
+            filename: filename.to_string(),
+            filesize: Some(2048),
+            sha256: Some("ab".repeat(32)),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_latest_closed_transfers_are_kept() {
+        let journal = InMemoryTransferJournal::new();
+        let open = journal.begin(&announced("open.bin"), None).await.unwrap();
+        let failed = journal.begin(&announced("f.bin"), None).await.unwrap();
+        journal
+            .finish(failed, &TransferOutcome::Failed("cut".to_string()))
+            .await
+            .unwrap();
+        for i in 0..HISTORY_LIMIT {
+            let id = journal
+                .begin(&announced(&format!("{}.bin", i)), None)
+                .await
+                .unwrap();
+            let done = TransferOutcome::Completed {
+                final_path: format!("{}.bin", i),
+            };
+            journal.finish(id, &done).await.unwrap();
+        }
+
+        let history = journal.history(usize::MAX).await.unwrap();
+        assert_eq!(history.len(), HISTORY_LIMIT + 1);
+        assert_eq!(history.last().map(|r| r.id), Some(open));
+        assert!(matches!(
+            journal.block_count(failed).await,
+            Err(JournalError::UnknownTransfer(_))
+        ));
+        // Dropped with its record: nothing left to resume.
+        assert_eq!(journal.resume(&announced("f.bin")).await.unwrap(), None);
+        journal.record_block(open, 0, 1024).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_reopened_transfer_outlives_its_first_closing() {
+        let journal = InMemoryTransferJournal::new();
+        let id = journal.begin(&announced("r.bin"), None).await.unwrap();
+        journal.record_block(id, 0, 1024).await.unwrap();
+        journal
+            .finish(id, &TransferOutcome::Failed("cut".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(journal.resume(&announced("r.bin")).await.unwrap(), Some(id));
+        for i in 0..HISTORY_LIMIT {
+            let other = journal
+                .begin(&announced(&format!("{}.bin", i)), None)
+                .await
+                .unwrap();
+            journal
+                .finish(other, &TransferOutcome::Rejected("no".to_string()))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(journal.received_blocks(id).await.unwrap(), vec![0]);
+        assert!(matches!(
+            journal.resume(&announced("r.bin")).await,
+            Err(JournalError::InProgress(_))
+        ));
+    }
+}
@@ -1,3 +1,16 @@
 pub mod command;
+pub mod compression;
+pub mod delta;
+pub mod discovery;
+pub mod encryption;
+pub mod forward;
+pub mod hook;
+pub mod journal;
 pub mod network;
+pub mod queue;
+pub mod relay;
+pub mod sender;
 pub mod storage;
+pub mod sync;
+pub mod watch;
+pub mod wormhole;
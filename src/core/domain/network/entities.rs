@@ -1,22 +1,65 @@
 use std::convert::TryFrom;
 
-use crate::core::domain::storage::entities::YeetBlock;
+use crate::core::domain::compression::entities::Codec;
+use crate::core::domain::delta::entities::BlockSignature;
+use crate::core::domain::journal::entities::TransferId;
+use crate::core::domain::storage::entities::{FileMetadata, ManifestEntry, YeetBlock};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ProtocolMessage {
     Hello {
-        // "HELLO <filename> <filesize>"
+        // "HELLO <filename> <filesize> [sha256]"
         filename: String,
         filesize: u64,
+        // Hex SHA-256 of the content, lets a deduplicating receiver skip the transfer.
+        sha256: Option<String>,
     },
-    Ok,                  // "OK"
-    Nope(String),        // "NOPE <reason>"
-    Yeet(YeetBlock),     // "YEET <block_index> <block_size> <check_sum>"
-    OkHousten(u64),      // "OK-HOUSTEN <block_index>"
-    MissionAccomplished, // "MISSION-ACCOMPLISHED"
-    Success,             // "SUCCESS"
-    Error(String),       // "ERROR <reason>"
-    ByeRis,              // "BYE-RIS"
+    Stream {
+        // "STREAM <filename>" (size unknown, terminated by EOS)
+        filename: String,
+    },
+    Ok,                   // "OK"
+    Nope(String),         // "NOPE <reason>"
+    Yeet(YeetBlock),      // "YEET <block_index> <block_size> <check_sum> [<codec> <raw_size>]"
+    OkHousten(u64),       // "OK-HOUSTEN <block_index>"
+    MissionAccomplished,  // "MISSION-ACCOMPLISHED"
+    EndOfStream(u64),     // "EOS <total_bytes>"
+    Success,              // "SUCCESS"
+    Error(String),        // "ERROR <reason>"
+    ByeRis,               // "BYE-RIS"
+    ManifestRequest,      // "MANIFEST"
+    Manifest(u64),        // "MANIFEST <count>", followed by <count> ENTRY lines
+    Entry(ManifestEntry), // "ENTRY <sha256> <size> <mtime> <path>"
+    Delete(String),       // "DELETE <path>"
+    Delta {
+        // "DELTA <filename> <filesize> <block_size>" (rebuild from the existing copy, ends with EOS)
+        filename: String,
+        filesize: u64,
+        block_size: u32,
+    },
+    Signature(u64),      // "SIGNATURE <count>", followed by <count> SIG lines
+    Sig(BlockSignature), // "SIG <weak> <strong>"
+    Copy {
+        // "COPY <offset> <len>"
+        offset: u64,
+        len: u64,
+    },
+    Compress(String), // "COMPRESS <codec>" (before the first block)
+    Hole {
+        // "HOLE <block_index> <len>" (an all-zero block, sent without its bytes)
+        index: u64,
+        len: u32,
+    },
+    Meta(FileMetadata), // "META <mode_octal> <mtime> [<xattr_name>=<hex_value> ...]"
+    Resume {
+        // "RESUME <filename> <filesize> <sha256> <block_size>" (a HELLO that may
+        // go on from an interrupted transfer of the same content)
+        filename: String,
+        filesize: u64,
+        sha256: String,
+        block_size: u32,
+    },
+    OkResume(u64), // "OK-RESUME <block_index>" (first block to send)
 }
 
 #[derive(Debug)]
@@ -44,7 +87,44 @@ impl TryFrom<&str> for ProtocolMessage {
                     .ok_or(ProtocolError::MissingArgs)?
                     .parse::<u64>()
                     .map_err(|_| ProtocolError::InvalidNumber)?;
-                Ok(ProtocolMessage::Hello { filename, filesize })
+                let sha256 = tokens.get(3).map(|s| s.to_string());
+                Ok(ProtocolMessage::Hello {
+                    filename,
+                    filesize,
+                    sha256,
+                })
+            }
+            Some("RESUME") => {
+                let filename = tokens.get(1).ok_or(ProtocolError::MissingArgs)?.to_string();
+                let filesize = tokens
+                    .get(2)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                let sha256 = tokens.get(3).ok_or(ProtocolError::MissingArgs)?.to_string();
+                let block_size = tokens
+                    .get(4)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u32>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                Ok(ProtocolMessage::Resume {
+                    filename,
+                    filesize,
+                    sha256,
+                    block_size,
+                })
+            }
+            Some("OK-RESUME") => {
+                let block_index = tokens
+                    .get(1)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                Ok(ProtocolMessage::OkResume(block_index))
+            }
+            Some("STREAM") => {
+                let filename = tokens.get(1).ok_or(ProtocolError::MissingArgs)?.to_string();
+                Ok(ProtocolMessage::Stream { filename })
             }
             Some("OK") => Ok(ProtocolMessage::Ok),
             Some("NOPE") => {
@@ -71,11 +151,19 @@ impl TryFrom<&str> for ProtocolMessage {
                     .parse::<u32>()
                     .map_err(|_| ProtocolError::InvalidNumber)?;
 
-                Ok(ProtocolMessage::Yeet(YeetBlock::new(
-                    block_index,
-                    block_size,
-                    check_sum,
-                )))
+                let mut block = YeetBlock::new(block_index, block_size, check_sum);
+                if let Some(codec) = tokens.get(4) {
+                    let codec =
+                        Codec::try_from(*codec).map_err(|_| ProtocolError::InvalidCommand)?;
+                    let raw_size = tokens
+                        .get(5)
+                        .ok_or(ProtocolError::MissingArgs)?
+                        .parse::<u32>()
+                        .map_err(|_| ProtocolError::InvalidNumber)?;
+                    block = block.with_codec(codec, raw_size);
+                }
+
+                Ok(ProtocolMessage::Yeet(block))
             }
             Some("OK-HOUSTEN") => {
                 let block_index = tokens
@@ -86,6 +174,14 @@ impl TryFrom<&str> for ProtocolMessage {
                 Ok(ProtocolMessage::OkHousten(block_index))
             }
             Some("MISSION-ACCOMPLISHED") => Ok(ProtocolMessage::MissionAccomplished),
+            Some("EOS") => {
+                let total_bytes = tokens
+                    .get(1)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                Ok(ProtocolMessage::EndOfStream(total_bytes))
+            }
             Some("SUCCESS") => Ok(ProtocolMessage::Success),
             Some("ERROR") => {
                 if tokens.len() < 2 {
@@ -95,6 +191,126 @@ impl TryFrom<&str> for ProtocolMessage {
                 Ok(ProtocolMessage::Error(reason))
             }
             Some("BYE-RIS") => Ok(ProtocolMessage::ByeRis),
+            Some("MANIFEST") => match tokens.get(1) {
+                None => Ok(ProtocolMessage::ManifestRequest),
+                Some(count) => count
+                    .parse::<u64>()
+                    .map(ProtocolMessage::Manifest)
+                    .map_err(|_| ProtocolError::InvalidNumber),
+            },
+            Some("ENTRY") => {
+                let sha256 = tokens.get(1).ok_or(ProtocolError::MissingArgs)?.to_string();
+                let size = tokens
+                    .get(2)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                let mtime = tokens
+                    .get(3)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                let path = tokens.get(4).ok_or(ProtocolError::MissingArgs)?.to_string();
+                Ok(ProtocolMessage::Entry(ManifestEntry {
+                    path,
+                    size,
+                    mtime,
+                    sha256,
+                }))
+            }
+            Some("DELETE") => {
+                let path = tokens.get(1).ok_or(ProtocolError::MissingArgs)?.to_string();
+                Ok(ProtocolMessage::Delete(path))
+            }
+            Some("DELTA") => {
+                let filename = tokens.get(1).ok_or(ProtocolError::MissingArgs)?.to_string();
+                let filesize = tokens
+                    .get(2)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                let block_size = tokens
+                    .get(3)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u32>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                Ok(ProtocolMessage::Delta {
+                    filename,
+                    filesize,
+                    block_size,
+                })
+            }
+            Some("SIGNATURE") => {
+                let count = tokens
+                    .get(1)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                Ok(ProtocolMessage::Signature(count))
+            }
+            Some("SIG") => {
+                let weak = tokens
+                    .get(1)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u32>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                let strong = tokens.get(2).ok_or(ProtocolError::MissingArgs)?.to_string();
+                Ok(ProtocolMessage::Sig(BlockSignature { weak, strong }))
+            }
+            Some("COPY") => {
+                let offset = tokens
+                    .get(1)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                let len = tokens
+                    .get(2)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                Ok(ProtocolMessage::Copy { offset, len })
+            }
+            Some("COMPRESS") => {
+                let codec = tokens.get(1).ok_or(ProtocolError::MissingArgs)?.to_string();
+                Ok(ProtocolMessage::Compress(codec))
+            }
+            Some("HOLE") => {
+                let index = tokens
+                    .get(1)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                let len = tokens
+                    .get(2)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u32>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                Ok(ProtocolMessage::Hole { index, len })
+            }
+            Some("META") => {
+                let mode = u32::from_str_radix(tokens.get(1).ok_or(ProtocolError::MissingArgs)?, 8)
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                let mtime = tokens
+                    .get(2)
+                    .ok_or(ProtocolError::MissingArgs)?
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber)?;
+                let xattrs = tokens[3..]
+                    .iter()
+                    .map(|token| {
+                        let (name, value) = token
+                            .rsplit_once('=')
+                            .ok_or(ProtocolError::InvalidCommand)?;
+                        let value = hex::decode(value).map_err(|_| ProtocolError::InvalidNumber)?;
+                        Ok((name.to_string(), value))
+                    })
+                    .collect::<Result<_, ProtocolError>>()?;
+                Ok(ProtocolMessage::Meta(FileMetadata {
+                    mode,
+                    mtime,
+                    xattrs,
+                }))
+            }
             _ => Err(ProtocolError::InvalidCommand),
         }
     }
@@ -103,20 +319,70 @@ impl TryFrom<&str> for ProtocolMessage {
 impl From<ProtocolMessage> for String {
     fn from(msg: ProtocolMessage) -> Self {
         match msg {
-            ProtocolMessage::Hello { filename, filesize } => {
-                format!("HELLO {} {}", filename, filesize)
-            }
+            ProtocolMessage::Hello {
+                filename,
+                filesize,
+                sha256: None,
+            } => format!("HELLO {} {}", filename, filesize),
+            ProtocolMessage::Hello {
+                filename,
+                filesize,
+                sha256: Some(sha256),
+            } => format!("HELLO {} {} {}", filename, filesize, sha256),
+            ProtocolMessage::Stream { filename } => format!("STREAM {}", filename),
             ProtocolMessage::Ok => "OK".to_string(),
             ProtocolMessage::Nope(reason) => format!("NOPE {}", reason),
-            ProtocolMessage::Yeet(yeet_block) => format!(
+            ProtocolMessage::Yeet(yeet_block) if yeet_block.codec == Codec::None => format!(
                 "YEET {} {} {}",
                 yeet_block.index, yeet_block.size, yeet_block.checksum
             ),
+            ProtocolMessage::Yeet(yeet_block) => format!(
+                "YEET {} {} {} {} {}",
+                yeet_block.index,
+                yeet_block.size,
+                yeet_block.checksum,
+                String::from(yeet_block.codec),
+                yeet_block.raw_size
+            ),
             ProtocolMessage::OkHousten(block_index) => format!("OK-HOUSTEN {}", block_index),
             ProtocolMessage::MissionAccomplished => "MISSION-ACCOMPLISHED".to_string(),
+            ProtocolMessage::EndOfStream(total_bytes) => format!("EOS {}", total_bytes),
             ProtocolMessage::Success => "SUCCESS".to_string(),
             ProtocolMessage::Error(reason) => format!("ERROR: {}", reason),
             ProtocolMessage::ByeRis => "BYE-RIS".to_string(),
+            ProtocolMessage::ManifestRequest => "MANIFEST".to_string(),
+            ProtocolMessage::Manifest(count) => format!("MANIFEST {}", count),
+            ProtocolMessage::Entry(entry) => format!(
+                "ENTRY {} {} {} {}",
+                entry.sha256, entry.size, entry.mtime, entry.path
+            ),
+            ProtocolMessage::Delete(path) => format!("DELETE {}", path),
+            ProtocolMessage::Delta {
+                filename,
+                filesize,
+                block_size,
+            } => format!("DELTA {} {} {}", filename, filesize, block_size),
+            ProtocolMessage::Signature(count) => format!("SIGNATURE {}", count),
+            ProtocolMessage::Sig(signature) => {
+                format!("SIG {} {}", signature.weak, signature.strong)
+            }
+            ProtocolMessage::Copy { offset, len } => format!("COPY {} {}", offset, len),
+            ProtocolMessage::Compress(codec) => format!("COMPRESS {}", codec),
+            ProtocolMessage::Hole { index, len } => format!("HOLE {} {}", index, len),
+            ProtocolMessage::Meta(metadata) => {
+                let mut line = format!("META {:o} {}", metadata.mode, metadata.mtime);
+                for (name, value) in &metadata.xattrs {
+                    line += &format!(" {}={}", name, hex::encode(value));
+                }
+                line
+            }
+            ProtocolMessage::Resume {
+                filename,
+                filesize,
+                sha256,
+                block_size,
+            } => format!("RESUME {} {} {} {}", filename, filesize, sha256, block_size),
+            ProtocolMessage::OkResume(block_index) => format!("OK-RESUME {}", block_index),
         }
     }
 }
@@ -174,9 +440,22 @@ pub enum TransferState {
     Idle,
     Receiving {
         current_file: String,
-        expected_blocks: u64,
+        // Size announced by HELLO, RESUME or DELTA. None for STREAM
+        // transfers, whose size is only known at EOS.
+        filesize: Option<u64>,
+        // Size of every block but the last: announced by RESUME and DELTA,
+        // otherwise set by the first block.
+        block_size: Option<u64>,
+        // STREAM and DELTA transfers end with EOS, the others with
+        // MISSION-ACCOMPLISHED.
+        ends_with_eos: bool,
         focused_block: Option<YeetBlock>,
-        received_blocks: Vec<u64>,
+        // Journal entry of this transfer, which tracks the blocks received.
+        transfer: TransferId,
+        // Bytes written so far; the next block is stored at this offset.
+        received_bytes: u64,
+        // Codec agreed with COMPRESS; blocks may use it or be sent raw.
+        codec: Codec,
     },
     Finished,
     Closed,
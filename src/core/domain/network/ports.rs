@@ -1,7 +1,9 @@
 use std::io::Error;
+use std::net::SocketAddr;
 
 use crate::core::domain::network::entities::{NetworkError, ProtocolError, ProtocolMessage};
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::TcpStream,
     sync::mpsc::{Receiver, Sender},
 };
@@ -13,6 +15,15 @@ pub trait NetworkService {
         tx: Sender<TcpStream>,
     ) -> impl Future<Output = Result<(), NetworkError>> + Send;
     fn handler(&self, rx: Receiver<TcpStream>) -> impl Future<Output = Result<(), Error>>;
+    /// Run the protocol on one connection until it closes. Returns whether a
+    /// transfer was completed on it.
+    fn handle_connection<S>(
+        &self,
+        stream: S,
+        peer: Option<SocketAddr>,
+    ) -> impl Future<Output = Result<bool, Error>>
+    where
+        S: AsyncRead + AsyncWrite + Send;
     fn trust_protocol(
         &self,
         message: ProtocolMessage,
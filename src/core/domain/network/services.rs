@@ -1,9 +1,12 @@
 use std::io::Error;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 
 use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
 use tokio::io::BufReader;
 use tokio::net::TcpListener;
@@ -26,6 +29,10 @@ where
     pub command_service: C,
     active: Arc<AtomicBool>,
     transfer_state: Arc<Mutex<TransferState>>,
+    // Remote address of the connection currently being handled.
+    peer: Arc<Mutex<Option<SocketAddr>>>,
+    // Stop the handler after this many completed transfers (None = run forever).
+    max_transfers: Option<u64>,
 }
 
 impl<C> NetworkServiceImpl<C>
@@ -37,14 +44,72 @@ where
             command_service,
             active: Arc::new(AtomicBool::new(false)),
             transfer_state: Arc::new(Mutex::new(TransferState::Idle)),
+            peer: Arc::new(Mutex::new(None)),
+            max_transfers: None,
         }
     }
 
+    /// Make `handler` return once `max` transfers have completed.
+    pub fn with_max_transfers(mut self, max: u64) -> Self {
+        self.max_transfers = Some(max);
+        self
+    }
+
     pub async fn reset_transfer_state(&self) {
         let mut state_guard = self.transfer_state.lock().await;
         *state_guard = TransferState::Idle;
         drop(state_guard);
     }
+
+    /// Reply to DELTA: the SIGNATURE header, then one SIG line per block of
+    /// the stored copy. A failure before the header is sent back as ERROR;
+    /// past it the sender sees an ERROR in place of a SIG line. Either way
+    /// the transfer is abandoned.
+    async fn send_signatures<W>(
+        &self,
+        writer: &mut W,
+        filename: &str,
+        block_size: u32,
+    ) -> Result<(), Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let count = match self
+            .command_service
+            .signature_count(filename, block_size)
+            .await
+        {
+            Ok(count) => count,
+            Err(e) => return self.fail_delta(writer, String::from(e)).await,
+        };
+        let header = String::from(ProtocolMessage::Signature(count)) + "\n";
+        writer.write_all(header.as_bytes()).await?;
+        for index in 0..count {
+            let signature = match self
+                .command_service
+                .signature(filename, block_size, index)
+                .await
+            {
+                Ok(signature) => signature,
+                Err(e) => return self.fail_delta(writer, String::from(e)).await,
+            };
+            let line = String::from(ProtocolMessage::Sig(signature)) + "\n";
+            writer.write_all(line.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    async fn fail_delta<W>(&self, writer: &mut W, reason: String) -> Result<(), Error>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        self.command_service
+            .abandon(Arc::clone(&self.transfer_state), &reason)
+            .await;
+        self.reset_transfer_state().await;
+        let line = String::from(ProtocolMessage::Error(reason)) + "\n";
+        writer.write_all(line.as_bytes()).await
+    }
 }
 
 impl<C> NetworkService for NetworkServiceImpl<C>
@@ -59,14 +124,14 @@ where
         let listener = TcpListener::bind(addr)
             .await
             .map_err(NetworkError::ListenerBindFailed)?;
-        println!("Listening on {}", addr);
+        eprintln!("Listening on {}", addr);
 
         loop {
             let (mut stream, addr) = listener
                 .accept()
                 .await
                 .map_err(|_| NetworkError::ConnectionLost)?;
-            println!("New connection from {}", addr);
+            eprintln!("New connection from {}", addr);
 
             // Vérifie si une connexion est déjà active
             if !self.active.load(std::sync::atomic::Ordering::SeqCst) {
@@ -82,7 +147,7 @@ where
 
             // Essaye d’envoyer la connexion au handler
             match tx.try_send(stream) {
-                Ok(_) => println!("Connection sent to handler."),
+                Ok(_) => eprintln!("Connection sent to handler."),
                 Err(e) => {
                     eprintln!("Failed to send connection to handler: {}", e);
                     // Optionally close the connection if it can't be handled
@@ -92,133 +157,202 @@ where
     }
 
     async fn handler(&self, mut rx: Receiver<TcpStream>) -> Result<(), Error> {
+        let mut completed_transfers: u64 = 0;
+
         while let Some(stream) = rx.recv().await {
-            let (read_half, mut write_half) = stream.into_split();
-            let mut reader = BufReader::new(read_half);
-            let mut buf = Vec::new();
-
-            loop {
-                buf.clear();
-
-                // Read one line (terminated by '\n'); returns 0 on EOF
-                let n = reader.read_until(b'\n', &mut buf).await?;
-                if n == 0 {
-                    println!("Client disconnected.");
-                    // Mark connection as inactive so listener can accept new ones
-                    self.active
-                        .store(false, std::sync::atomic::Ordering::SeqCst);
-                    self.reset_transfer_state().await;
+            let peer = stream.peer_addr().ok();
+            let transfer_completed = self.handle_connection(stream, peer).await?;
+
+            if transfer_completed {
+                completed_transfers += 1;
+                if let Some(max) = self.max_transfers
+                    && completed_transfers >= max
+                {
+                    eprintln!("Completed {} transfer(s), stopping handler.", max);
                     break;
                 }
+            }
+        }
 
-                // Trim trailing LF/CRLF
-                if buf.ends_with(b"\n") {
-                    buf.pop();
-                }
-                if buf.ends_with(b"\r") {
-                    buf.pop();
-                }
+        self.active
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        self.reset_transfer_state().await;
+        Ok(())
+    }
+
+    async fn handle_connection<S>(&self, stream: S, peer: Option<SocketAddr>) -> Result<bool, Error>
+    where
+        S: AsyncRead + AsyncWrite + Send,
+    {
+        *self.peer.lock().await = peer;
+        let (read_half, mut write_half) = tokio::io::split(stream);
+        let mut reader = BufReader::new(read_half);
+        let mut buf = Vec::new();
+
+        // Whether the connection ended after a successfully finalized transfer.
+        let transfer_completed = loop {
+            buf.clear();
+
+            // Read one line (terminated by '\n'); returns 0 on EOF
+            let n = reader.read_until(b'\n', &mut buf).await?;
+            if n == 0 {
+                eprintln!("Client disconnected.");
+                // A peer may hang up right after SUCCESS without saying BYE-RIS.
+                let finished = matches!(*self.transfer_state.lock().await, TransferState::Finished);
+                self.command_service
+                    .abandon(
+                        Arc::clone(&self.transfer_state),
+                        "connection closed before the transfer completed",
+                    )
+                    .await;
+                // Mark connection as inactive so listener can accept new ones
+                self.active
+                    .store(false, std::sync::atomic::Ordering::SeqCst);
+                self.reset_transfer_state().await;
+                break finished;
+            }
+
+            // Trim trailing LF/CRLF
+            if buf.ends_with(b"\n") {
+                buf.pop();
+            }
+            if buf.ends_with(b"\r") {
+                buf.pop();
+            }
+
+            // Convert to &str and parse into your ProtocolMessage
+            let line = std::str::from_utf8(&buf)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+            match ProtocolMessage::try_from(line) {
+                Ok(msg) => {
+                    eprintln!("Received message: {:?}", msg);
+
+                    match self.trust_protocol(msg).await {
+                        Ok(message) => match message {
+                            ProtocolMessage::Yeet(yeet_block) => {
+                                let mut bin_buf = vec![0u8; yeet_block.size as usize];
+                                if let Err(e) = reader.read_exact(&mut bin_buf).await {
+                                    eprintln!("Error reading binary block: {:?}", e);
+                                    let err_msg =
+                                        ProtocolMessage::Error(String::from("Read binary failed"));
+                                    let s = String::from(err_msg) + "\n";
+                                    let _ = write_half.write_all(s.as_bytes()).await;
+                                    continue;
+                                }
+
+                                // Consume the trailing newline after the binary block if any.
+                                let mut _end = Vec::new();
+                                let _ = reader.read_until(b'\n', &mut _end).await;
 
-                // Convert to &str and parse into your ProtocolMessage
-                let line = std::str::from_utf8(&buf)
-                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-
-                match ProtocolMessage::try_from(line) {
-                    Ok(msg) => {
-                        println!("Received message: {:?}", msg);
-
-                        match self.trust_protocol(msg).await {
-                            Ok(message) => match message {
-                                ProtocolMessage::Yeet(yeet_block) => {
-                                    let mut bin_buf = vec![0u8; yeet_block.size as usize];
-                                    if let Err(e) = reader.read_exact(&mut bin_buf).await {
-                                        eprintln!("Error reading binary block: {:?}", e);
-                                        let err_msg = ProtocolMessage::Error(String::from(
-                                            "Read binary failed",
-                                        ));
+                                // Forward the block to the command service for storage.
+                                match self
+                                    .command_service
+                                    .process_binary_data(Arc::clone(&self.transfer_state), &bin_buf)
+                                    .await
+                                {
+                                    Ok(response_msg) => {
+                                        let s = String::from(response_msg) + "\n";
+                                        if let Err(e) = write_half.write_all(s.as_bytes()).await {
+                                            eprintln!("Error sending response: {:?}", e);
+                                        }
+                                    }
+                                    Err(e) => {
+                                        eprintln!("Error processing binary data: {:?}", e);
+                                        let err_msg = ProtocolMessage::Error(String::from(e));
                                         let s = String::from(err_msg) + "\n";
                                         let _ = write_half.write_all(s.as_bytes()).await;
-                                        continue;
                                     }
-
-                                    // Consume the trailing newline after the binary block if any.
-                                    let mut _end = Vec::new();
-                                    let _ = reader.read_until(b'\n', &mut _end).await;
-
-                                    // Forward the block to the command service for storage.
-                                    match self
-                                        .command_service
-                                        .process_binary_data(
-                                            Arc::clone(&self.transfer_state),
-                                            &bin_buf,
-                                        )
-                                        .await
-                                    {
-                                        Ok(response_msg) => {
-                                            let s = String::from(response_msg) + "\n";
-                                            if let Err(e) = write_half.write_all(s.as_bytes()).await
-                                            {
-                                                eprintln!("Error sending response: {:?}", e);
-                                            }
-                                        }
-                                        Err(e) => {
-                                            eprintln!("Error processing binary data: {:?}", e);
-                                            let err_msg = ProtocolMessage::Error(String::from(e));
-                                            let s = String::from(err_msg) + "\n";
-                                            let _ = write_half.write_all(s.as_bytes()).await;
+                                }
+                            }
+                            ProtocolMessage::ManifestRequest => {
+                                // Header and entries go out in a single write.
+                                let reply = match self.command_service.manifest().await {
+                                    Ok(entries) => {
+                                        let mut reply = String::from(ProtocolMessage::Manifest(
+                                            entries.len() as u64,
+                                        )) + "\n";
+                                        for entry in entries {
+                                            reply += &(String::from(ProtocolMessage::Entry(entry))
+                                                + "\n");
                                         }
+                                        reply
                                     }
-                                }
-                                other => {
-                                    // Non-YEET responses (OK, SUCCESS, etc.) are sent back to writer.
-                                    let s = String::from(other) + "\n";
-                                    if let Err(e) = write_half.write_all(s.as_bytes()).await {
-                                        eprintln!("Error sending message: {:?}", e);
+                                    Err(e) => {
+                                        String::from(ProtocolMessage::Error(String::from(e))) + "\n"
                                     }
+                                };
+                                if let Err(e) = write_half.write_all(reply.as_bytes()).await {
+                                    eprintln!("Error sending manifest: {:?}", e);
+                                }
+                            }
+                            ProtocolMessage::Delta {
+                                filename,
+                                block_size,
+                                ..
+                            } => {
+                                // Unlike the manifest, SIG lines go out as they are read:
+                                // a large file has too many to hold in memory.
+                                if let Err(e) = self
+                                    .send_signatures(&mut write_half, &filename, block_size)
+                                    .await
+                                {
+                                    eprintln!("Error sending signatures: {:?}", e);
                                 }
-                            },
-                            Err(e) => eprintln!("Error handling protocol message: {:?}", e),
+                            }
+                            other => {
+                                // Non-YEET responses (OK, SUCCESS, etc.) are sent back to writer.
+                                let s = String::from(other) + "\n";
+                                if let Err(e) = write_half.write_all(s.as_bytes()).await {
+                                    eprintln!("Error sending message: {:?}", e);
+                                }
+                            }
+                        },
+                        Err(e) => {
+                            // Always answer, or a sender waiting for a reply hangs.
+                            eprintln!("Error handling protocol message: {:?}", e);
+                            let s = String::from(ProtocolMessage::Error(String::from(e))) + "\n";
+                            if let Err(e) = write_half.write_all(s.as_bytes()).await {
+                                eprintln!("Error sending message: {:?}", e);
+                            }
                         }
+                    }
 
-                        let guard = self.transfer_state.lock().await;
-                        match *guard {
-                            TransferState::Closed => {
-                                println!("Closing connection.");
-                                self.active
-                                    .store(false, std::sync::atomic::Ordering::SeqCst);
-                                drop(guard);
+                    let guard = self.transfer_state.lock().await;
+                    match *guard {
+                        TransferState::Closed => {
+                            eprintln!("Closing connection.");
+                            self.active
+                                .store(false, std::sync::atomic::Ordering::SeqCst);
+                            drop(guard);
 
-                                self.reset_transfer_state().await;
-                                // shutdown the write half
-                                let _ = write_half.shutdown().await;
+                            self.reset_transfer_state().await;
+                            // shutdown the write half
+                            let _ = write_half.shutdown().await;
 
-                                break;
-                            }
-                            _ => {
-                                continue;
-                            }
+                            break true;
                         }
-                    }
-                    Err(_) => {
-                        if let Err(e) = self
-                            .command_service
-                            .process_binary_data(Arc::clone(&self.transfer_state), &buf)
-                            .await
-                        {
-                            eprintln!("Error processing binary data: {:?}", e);
-                            let err_msg = ProtocolMessage::Error(format!("{:?}", e));
-                            let s = String::from(err_msg) + "\n";
-                            let _ = write_half.write_all(s.as_bytes()).await;
+                        _ => {
+                            continue;
                         }
                     }
                 }
+                Err(_) => {
+                    if let Err(e) = self
+                        .command_service
+                        .process_binary_data(Arc::clone(&self.transfer_state), &buf)
+                        .await
+                    {
+                        eprintln!("Error processing binary data: {:?}", e);
+                        let err_msg = ProtocolMessage::Error(format!("{:?}", e));
+                        let s = String::from(err_msg) + "\n";
+                        let _ = write_half.write_all(s.as_bytes()).await;
+                    }
+                }
             }
-        }
+        };
 
-        self.active
-            .store(false, std::sync::atomic::Ordering::SeqCst);
-        self.reset_transfer_state().await;
-        Ok(())
+        Ok(transfer_completed)
     }
 
     async fn trust_protocol(
@@ -228,27 +362,44 @@ where
         let guard = self.transfer_state.lock().await;
         match *guard {
             TransferState::Idle => {
-                if !matches!(message, ProtocolMessage::Hello { .. }) {
+                // DELETE and MANIFEST are refused by the command service
+                // unless the node has sync enabled.
+                if !matches!(
+                    message,
+                    ProtocolMessage::Hello { .. }
+                        | ProtocolMessage::Resume { .. }
+                        | ProtocolMessage::Stream { .. }
+                        | ProtocolMessage::ManifestRequest
+                        | ProtocolMessage::Delete(_)
+                        | ProtocolMessage::Delta { .. }
+                ) {
                     return Err(ProtocolError::InvalidCommand);
                 } else {
-                    println!("Transitioning from Idle to Receiving state.");
+                    eprintln!("Transitioning from Idle to Receiving state.");
                 }
             }
             TransferState::Receiving { .. } => {
-                // Accept either a Yeet message or a MissionAccomplished message while receiving.
-                if !(matches!(message, ProtocolMessage::Yeet { .. })
-                    || matches!(message, ProtocolMessage::MissionAccomplished))
-                {
+                // Accept a Yeet, HOLE, COPY or META message or an end marker (MissionAccomplished / EOS) while receiving.
+                if !matches!(
+                    message,
+                    ProtocolMessage::Yeet { .. }
+                        | ProtocolMessage::Hole { .. }
+                        | ProtocolMessage::Copy { .. }
+                        | ProtocolMessage::Compress(_)
+                        | ProtocolMessage::Meta(_)
+                        | ProtocolMessage::MissionAccomplished
+                        | ProtocolMessage::EndOfStream(_)
+                ) {
                     return Err(ProtocolError::InvalidCommand);
                 } else {
-                    println!("In Receiving state, processing Yeet or MissionAccomplished.");
+                    eprintln!("In Receiving state, processing Yeet or end of transfer.");
                 }
             }
             TransferState::Finished => {
                 if !matches!(message, ProtocolMessage::ByeRis) {
                     return Err(ProtocolError::InvalidCommand);
                 } else {
-                    println!("Transitioning from Finished to Closed state.");
+                    eprintln!("Transitioning from Finished to Closed state.");
                 }
             }
             _ => {
@@ -258,9 +409,10 @@ where
 
         drop(guard); // Release the lock before awaiting
 
-        println!("Executing command: {:?}", message);
+        eprintln!("Executing command: {:?}", message);
+        let peer = *self.peer.lock().await;
         self.command_service
-            .execute_protocol_command(Arc::clone(&self.transfer_state), &message)
+            .execute_protocol_command(Arc::clone(&self.transfer_state), peer, &message)
             .await
             .map_err(|e| ProtocolError::CommandExecutionFailed(format!("{:?}", e)))
     }
@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Pending,
+    /// Being sent by `worker`, which refreshes `since` (Unix seconds) while
+    /// it sends, so a job whose worker died can be told apart.
+    Running {
+        worker: String,
+        since: u64,
+    },
+    Done,
+    Failed,
+}
+
+/// One outbound transfer in the persistent queue.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueJob {
+    pub id: u64,
+    /// Absolute, so the worker can run from any directory.
+    pub path: PathBuf,
+    pub filename: String,
+    pub addr: String,
+    pub state: JobState,
+    pub attempts: u32,
+    /// Unix seconds.
+    pub added_at: u64,
+    /// Unix seconds; the worker leaves the job alone until then.
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum QueueError {
+    Io(std::io::Error),
+    CorruptJournal(String),
+}
+
+/// Current time in Unix seconds, as stored in the journal.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl From<std::io::Error> for QueueError {
+    fn from(err: std::io::Error) -> Self {
+        QueueError::Io(err)
+    }
+}
+
+impl From<QueueError> for String {
+    fn from(err: QueueError) -> Self {
+        match err {
+            QueueError::Io(e) => format!("Queue I/O error: {}", e),
+            QueueError::CorruptJournal(reason) => format!("Corrupt queue journal: {}", reason),
+        }
+    }
+}
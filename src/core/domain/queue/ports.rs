@@ -0,0 +1,28 @@
+use std::path::Path;
+
+use crate::core::domain::queue::entities::{QueueError, QueueJob};
+
+/// Where the queue survives restarts.
+pub trait QueueRepository {
+    fn load(&self) -> impl Future<Output = Result<Vec<QueueJob>, QueueError>> + Send;
+    /// Apply `change` to the stored jobs and save the result. Other writers,
+    /// in this process or another, wait until it is saved.
+    fn update<T, F>(&self, change: F) -> impl Future<Output = Result<T, QueueError>> + Send
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Vec<QueueJob>) -> T + Send + 'static;
+}
+
+pub trait QueueService {
+    /// Append a pending job sending `path` to `addr` as `filename`.
+    fn add(
+        &self,
+        path: &Path,
+        addr: &str,
+        filename: &str,
+    ) -> impl Future<Output = Result<QueueJob, QueueError>> + Send;
+    fn status(&self) -> impl Future<Output = Result<Vec<QueueJob>, QueueError>> + Send;
+    /// Work through pending jobs as they become due. Runs forever unless
+    /// `until_idle` is set, in which case it returns once nothing is pending.
+    fn run(&self, until_idle: bool) -> impl Future<Output = Result<(), QueueError>> + Send;
+}
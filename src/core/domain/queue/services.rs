@@ -0,0 +1,346 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::core::domain::forward::entities::RetryPolicy;
+use crate::core::domain::queue::entities::{JobState, QueueError, QueueJob, unix_now};
+use crate::core::domain::queue::ports::{QueueRepository, QueueService};
+use crate::core::domain::sender::ports::SenderService;
+
+/// How often an idle worker re-reads the journal for jobs added meanwhile.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How often a worker refreshes the claim on the job it is sending.
+const HEARTBEAT: Duration = Duration::from_secs(30);
+/// Unrefreshed for this long, a running job is taken to have lost its worker,
+/// and another worker may take it over.
+const STALE_AFTER: Duration = Duration::from_secs(120);
+
+/// Workers started by this process, to tell them apart in the journal.
+static WORKERS: AtomicU64 = AtomicU64::new(0);
+
+/// What a worker found in the journal.
+enum Next {
+    Idle,
+    Wait(u64),
+    Claimed(QueueJob),
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueServiceImpl<R, T>
+where
+    R: QueueRepository,
+    T: SenderService,
+{
+    repository: R,
+    sender: T,
+    policy: RetryPolicy,
+    // Recorded in the jobs this worker claims.
+    worker: String,
+}
+
+impl<R, T> QueueServiceImpl<R, T>
+where
+    R: QueueRepository + Send + Sync,
+    T: SenderService + Send + Sync,
+{
+    pub fn new(repository: R, sender: T) -> Self {
+        QueueServiceImpl {
+            repository,
+            sender,
+            policy: RetryPolicy::default(),
+            worker: format!(
+                "{}.{}",
+                std::process::id(),
+                WORKERS.fetch_add(1, Ordering::Relaxed)
+            ),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Apply `change` to job `id` under the journal's lock, so jobs added by
+    /// `queue add` while we were sending aren't lost.
+    async fn update<F>(&self, id: u64, change: F) -> Result<(), QueueError>
+    where
+        F: FnOnce(&mut QueueJob) + Send + 'static,
+    {
+        self.repository
+            .update(move |jobs| {
+                if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+                    change(job);
+                }
+            })
+            .await
+    }
+
+    /// Take the pending job due first, or a running one whose worker went
+    /// quiet, marking it running under the journal's lock so no other
+    /// worker sends it too.
+    async fn claim(&self) -> Result<Next, QueueError> {
+        let worker = self.worker.clone();
+        self.repository
+            .update(move |jobs| {
+                let now = unix_now();
+                let Some(job) = jobs
+                    .iter_mut()
+                    .filter(|job| match &job.state {
+                        JobState::Pending => true,
+                        JobState::Running { since, .. } => {
+                            since.saturating_add(STALE_AFTER.as_secs()) <= now
+                        }
+                        _ => false,
+                    })
+                    .min_by_key(|job| (job.next_attempt_at, job.id))
+                else {
+                    return Next::Idle;
+                };
+                if job.next_attempt_at > now {
+                    return Next::Wait(job.next_attempt_at - now);
+                }
+                if let JobState::Running { worker, .. } = &job.state {
+                    eprintln!("Job {}: taking over from silent worker {}", job.id, worker);
+                }
+                job.state = JobState::Running { worker, since: now };
+                Next::Claimed(job.clone())
+            })
+            .await
+    }
+
+    async fn attempt(&self, job: QueueJob) -> Result<(), QueueError> {
+        let send = self.sender.send_file(&job.addr, &job.path, &job.filename);
+        tokio::pin!(send);
+        let result = loop {
+            tokio::select! {
+                result = &mut send => break result,
+                _ = tokio::time::sleep(HEARTBEAT) => {
+                    let worker = self.worker.clone();
+                    self.update(job.id, move |job| {
+                        if let JobState::Running { worker: owner, since } = &mut job.state
+                            && *owner == worker
+                        {
+                            *since = unix_now();
+                        }
+                    })
+                    .await?;
+                }
+            }
+        };
+        let attempts = job.attempts + 1;
+
+        match result {
+            Ok(report) => {
+                eprintln!(
+                    "Job {}: sent {} ({} bytes) to {}",
+                    job.id, report.filename, report.bytes, job.addr
+                );
+                self.update(job.id, move |job| {
+                    job.attempts = attempts;
+                    job.state = JobState::Done;
+                    job.last_error = None;
+                })
+                .await
+            }
+            Err(e) => {
+                let reason = String::from(e);
+                let exhausted = self.policy.exhausted(attempts);
+                let delay = self.policy.backoff(attempts);
+                if exhausted {
+                    eprintln!(
+                        "Job {}: giving up after {} attempts: {}",
+                        job.id, attempts, reason
+                    );
+                } else {
+                    eprintln!(
+                        "Job {}: attempt {} failed: {}; retrying in {:?}",
+                        job.id, attempts, reason, delay
+                    );
+                }
+                self.update(job.id, move |job| {
+                    job.attempts = attempts;
+                    job.last_error = Some(reason);
+                    if exhausted {
+                        job.state = JobState::Failed;
+                    } else {
+                        job.state = JobState::Pending;
+                        job.next_attempt_at = unix_now() + delay.as_secs().max(1);
+                    }
+                })
+                .await
+            }
+        }
+    }
+}
+
+impl<R, T> QueueService for QueueServiceImpl<R, T>
+where
+    R: QueueRepository + Send + Sync,
+    T: SenderService + Send + Sync,
+{
+    async fn add(&self, path: &Path, addr: &str, filename: &str) -> Result<QueueJob, QueueError> {
+        let path = tokio::fs::canonicalize(path).await?;
+        let filename = filename.to_string();
+        let addr = addr.to_string();
+        self.repository
+            .update(move |jobs| {
+                let now = unix_now();
+                let job = QueueJob {
+                    id: jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1,
+                    path,
+                    filename,
+                    addr,
+                    state: JobState::Pending,
+                    attempts: 0,
+                    added_at: now,
+                    next_attempt_at: now,
+                    last_error: None,
+                };
+                jobs.push(job.clone());
+                job
+            })
+            .await
+    }
+
+    async fn status(&self) -> Result<Vec<QueueJob>, QueueError> {
+        self.repository.load().await
+    }
+
+    async fn run(&self, until_idle: bool) -> Result<(), QueueError> {
+        loop {
+            match self.claim().await? {
+                Next::Idle if until_idle => return Ok(()),
+                Next::Idle => tokio::time::sleep(POLL_INTERVAL).await,
+                Next::Wait(secs) => {
+                    tokio::time::sleep(Duration::from_secs(secs).min(POLL_INTERVAL)).await
+                }
+                Next::Claimed(job) => self.attempt(job).await?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::core::domain::sender::entities::{SendError, SendReport};
+
+    #[derive(Clone, Default)]
+    struct SharedJournal(Arc<Mutex<Vec<QueueJob>>>);
+
+    impl QueueRepository for SharedJournal {
+        async fn load(&self) -> Result<Vec<QueueJob>, QueueError> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+
+        async fn update<U, F>(&self, change: F) -> Result<U, QueueError>
+        where
+            U: Send + 'static,
+            F: FnOnce(&mut Vec<QueueJob>) -> U + Send + 'static,
+        {
+            Ok(change(&mut self.0.lock().unwrap()))
+        }
+    }
+
+    /// Records every file it is asked to send, taking a while about it.
+    #[derive(Clone, Default)]
+    struct SlowSender(Arc<Mutex<Vec<String>>>);
+
+    impl SenderService for SlowSender {
+        async fn send_file(
+            &self,
+            _addr: &str,
+            _path: &Path,
+            filename: &str,
+        ) -> Result<SendReport, SendError> {
+            self.0.lock().unwrap().push(filename.to_string());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(SendReport {
+                filename: filename.to_string(),
+                bytes: 0,
+                blocks: 0,
+                reused: 0,
+            })
+        }
+
+        async fn send_delta(
+            &self,
+            addr: &str,
+            path: &Path,
+            filename: &str,
+        ) -> Result<SendReport, SendError> {
+            self.send_file(addr, path, filename).await
+        }
+    }
+
+    fn job(id: u64, state: JobState) -> QueueJob {
+        QueueJob {
+            id,
+            path: PathBuf::from(format!("/tmp/{}.bin", id)),
+            filename: format!("{}.bin", id),
+            addr: "127.0.0.1:9000".to_string(),
+            state,
+            attempts: 0,
+            added_at: 0,
+            next_attempt_at: 0,
+            last_error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn two_workers_never_send_the_same_job() {
+        let journal = SharedJournal::default();
+        *journal.0.lock().unwrap() = (1..=6).map(|id| job(id, JobState::Pending)).collect();
+        let sender = SlowSender::default();
+        let first = QueueServiceImpl::new(journal.clone(), sender.clone());
+        let second = QueueServiceImpl::new(journal.clone(), sender.clone());
+
+        let (a, b) = tokio::join!(first.run(true), second.run(true));
+        a.unwrap();
+        b.unwrap();
+
+        let mut sent = sender.0.lock().unwrap().clone();
+        sent.sort();
+        assert_eq!(
+            sent,
+            (1..=6).map(|id| format!("{}.bin", id)).collect::<Vec<_>>()
+        );
+        assert!(
+            journal
+                .0
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|job| job.state == JobState::Done)
+        );
+    }
+
+    #[tokio::test]
+    async fn only_stale_running_jobs_are_taken_over() {
+        let journal = SharedJournal::default();
+        let busy = JobState::Running {
+            worker: "busy".to_string(),
+            since: unix_now(),
+        };
+        let gone = JobState::Running {
+            worker: "gone".to_string(),
+            since: 0,
+        };
+        *journal.0.lock().unwrap() = vec![job(1, busy.clone()), job(2, gone)];
+        let sender = SlowSender::default();
+
+        QueueServiceImpl::new(journal.clone(), sender.clone())
+            .run(true)
+            .await
+            .unwrap();
+
+        assert_eq!(*sender.0.lock().unwrap(), vec!["2.bin".to_string()]);
+        let jobs = journal.0.lock().unwrap().clone();
+        assert_eq!(jobs[0].state, busy);
+        assert_eq!(jobs[1].state, JobState::Done);
+    }
+}
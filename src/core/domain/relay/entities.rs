@@ -0,0 +1,80 @@
+use std::convert::TryFrom;
+
+/// Lines exchanged with the relay server before it starts piping bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayMessage {
+    Relay(String), // "RELAY <session_id>"
+    Paired,        // "PAIRED"
+    Nope(String),  // "NOPE <reason>"
+}
+
+#[derive(Debug)]
+pub enum RelayError {
+    RelayUnreachable(std::io::Error),
+    InvalidSessionId,
+    Refused(String),
+    InvalidMessage(String),
+    Io(std::io::Error),
+}
+
+impl RelayMessage {
+    /// Session IDs travel as a single protocol token.
+    pub fn is_valid_session_id(session_id: &str) -> bool {
+        !session_id.is_empty()
+            && session_id.len() <= 128
+            && session_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    }
+}
+
+impl TryFrom<&str> for RelayMessage {
+    type Error = RelayError;
+
+    fn try_from(value: &str) -> Result<Self, RelayError> {
+        let line = value.trim();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "RELAY" => {
+                let session_id = rest.trim();
+                if !RelayMessage::is_valid_session_id(session_id) {
+                    return Err(RelayError::InvalidSessionId);
+                }
+                Ok(RelayMessage::Relay(session_id.to_string()))
+            }
+            "PAIRED" => Ok(RelayMessage::Paired),
+            "NOPE" => Ok(RelayMessage::Nope(rest.to_string())),
+            _ => Err(RelayError::InvalidMessage(line.to_string())),
+        }
+    }
+}
+
+impl From<RelayMessage> for String {
+    fn from(msg: RelayMessage) -> Self {
+        match msg {
+            RelayMessage::Relay(session_id) => format!("RELAY {}", session_id),
+            RelayMessage::Paired => "PAIRED".to_string(),
+            RelayMessage::Nope(reason) => format!("NOPE {}", reason),
+        }
+    }
+}
+
+impl From<std::io::Error> for RelayError {
+    fn from(err: std::io::Error) -> Self {
+        RelayError::Io(err)
+    }
+}
+
+impl From<RelayError> for String {
+    fn from(err: RelayError) -> Self {
+        match err {
+            RelayError::RelayUnreachable(e) => format!("Relay unreachable: {}", e),
+            RelayError::InvalidSessionId => {
+                "Invalid session ID (use letters, digits, '-', '_' or '.')".to_string()
+            }
+            RelayError::Refused(reason) => format!("Relay refused session: {}", reason),
+            RelayError::InvalidMessage(line) => format!("Unexpected relay message: {}", line),
+            RelayError::Io(e) => format!("Relay I/O error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,9 @@
+use tokio::net::TcpStream;
+
+use crate::core::domain::relay::entities::RelayError;
+
+pub trait RelayClient {
+    /// Join `session_id` on the relay and wait until the other peer joins too.
+    /// The returned stream is piped to that peer.
+    fn dial(&self, session_id: &str) -> impl Future<Output = Result<TcpStream, RelayError>> + Send;
+}
@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Sender;
+
+use crate::core::domain::relay::entities::{RelayError, RelayMessage};
+use crate::core::domain::relay::ports::RelayClient;
+
+pub const DEFAULT_RELAY_ADDR: &str = "127.0.0.1:9020";
+/// Pause before dialing the relay again after a failed attempt.
+const REDIAL_DELAY: Duration = Duration::from_secs(5);
+/// Longest line accepted before pairing; session IDs are well below this.
+const MAX_LINE: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct RelayClientImpl {
+    relay_addr: String,
+}
+
+impl RelayClientImpl {
+    pub fn new(relay_addr: &str) -> Self {
+        RelayClientImpl {
+            relay_addr: relay_addr.to_string(),
+        }
+    }
+
+    /// Keep a connection parked on the relay under `session_id` and hand every
+    /// paired stream to the network handler, the same way the TCP listener does.
+    /// Returns once the handler is gone.
+    pub async fn listen(&self, session_id: &str, tx: Sender<TcpStream>) -> Result<(), RelayError> {
+        eprintln!(
+            "Waiting for peers on relay {} (session {})",
+            self.relay_addr, session_id
+        );
+        loop {
+            match self.dial(session_id).await {
+                Ok(stream) => {
+                    eprintln!("Peer joined through relay {}", self.relay_addr);
+                    if tx.send(stream).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                Err(RelayError::InvalidSessionId) => return Err(RelayError::InvalidSessionId),
+                Err(e) => {
+                    eprintln!("{}", String::from(e));
+                    tokio::time::sleep(REDIAL_DELAY).await;
+                }
+            }
+        }
+    }
+}
+
+/// Read a single line without buffering past it: once paired, the bytes that
+/// follow belong to the peer.
+pub async fn read_line_unbuffered<R>(reader: &mut R) -> Result<String, RelayError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut line = Vec::new();
+    loop {
+        let byte = reader.read_u8().await?;
+        if byte == b'\n' {
+            break;
+        }
+        line.push(byte);
+        if line.len() > MAX_LINE {
+            return Err(RelayError::InvalidMessage("line too long".to_string()));
+        }
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+impl RelayClient for RelayClientImpl {
+    async fn dial(&self, session_id: &str) -> Result<TcpStream, RelayError> {
+        if !RelayMessage::is_valid_session_id(session_id) {
+            return Err(RelayError::InvalidSessionId);
+        }
+
+        let mut stream = TcpStream::connect(&self.relay_addr)
+            .await
+            .map_err(RelayError::RelayUnreachable)?;
+        let hello = String::from(RelayMessage::Relay(session_id.to_string())) + "\n";
+        stream.write_all(hello.as_bytes()).await?;
+
+        let line = read_line_unbuffered(&mut stream).await?;
+        match RelayMessage::try_from(line.as_str())? {
+            RelayMessage::Paired => Ok(stream),
+            RelayMessage::Nope(reason) => Err(RelayError::Refused(reason)),
+            other => Err(RelayError::InvalidMessage(String::from(other))),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use crate::core::domain::storage::entities::FileMetadata;
+
+/// One block of the source, or the error that stopped reading it.
+pub type Block = std::io::Result<Arc<[u8]>>;
+
+/// What the receiver is told about a file before its blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingFile {
+    pub filename: String,
+    /// None sends a STREAM, ended with EOS once the source runs dry.
+    pub filesize: Option<u64>,
+    /// Announced in HELLO, so a receiver that has the content skips the data.
+    pub sha256: Option<String>,
+    /// Sent with META after the receiver accepted the transfer.
+    pub metadata: Option<FileMetadata>,
+}
+
+/// Outcome of a successfully delivered file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendReport {
+    pub filename: String,
+    /// Bytes sent over the wire.
+    pub bytes: u64,
+    pub blocks: u64,
+    /// Bytes the receiver rebuilt from its existing copy (delta sends only).
+    pub reused: u64,
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    ConnectFailed(std::io::Error),
+    Refused(String),
+    BlockRejected {
+        index: u64,
+        reply: String,
+    },
+    CopyRejected {
+        offset: u64,
+        reply: String,
+    },
+    NotFinalized(String),
+    ConnectionClosed,
+    /// The receiver went quiet for longer than the reply timeout.
+    NoReply(std::time::Duration),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for SendError {
+    fn from(err: std::io::Error) -> Self {
+        SendError::Io(err)
+    }
+}
+
+impl From<SendError> for String {
+    fn from(err: SendError) -> Self {
+        match err {
+            SendError::ConnectFailed(e) => format!("Connection failed: {}", e),
+            SendError::Refused(reply) => format!("Transfer refused: {}", reply),
+            SendError::BlockRejected { index, reply } => {
+                format!("Block {} rejected: {}", index, reply)
+            }
+            SendError::CopyRejected { offset, reply } => {
+                format!("Copy from offset {} rejected: {}", offset, reply)
+            }
+            SendError::NotFinalized(reply) => format!("Transfer not finalized: {}", reply),
+            SendError::ConnectionClosed => "Connection closed by receiver".to_string(),
+            SendError::NoReply(timeout) => format!("No reply from receiver within {:?}", timeout),
+            SendError::Io(e) => format!("I/O error: {}", e),
+        }
+    }
+}
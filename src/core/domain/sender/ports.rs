@@ -0,0 +1,34 @@
+use std::path::Path;
+
+use crate::core::domain::sender::entities::{SendError, SendReport};
+
+pub trait SenderService {
+    /// Deliver the file at `path` to the listener at `addr`, announced as `filename`.
+    fn send_file(
+        &self,
+        addr: &str,
+        path: &Path,
+        filename: &str,
+    ) -> impl Future<Output = Result<SendReport, SendError>> + Send;
+    /// Like `send_file`, but only sends what the receiver's existing copy of
+    /// `filename` lacks. Falls back to sending everything when it has none.
+    fn send_delta(
+        &self,
+        addr: &str,
+        path: &Path,
+        filename: &str,
+    ) -> impl Future<Output = Result<SendReport, SendError>> + Send;
+}
+
+/// Follows a transfer while it runs, e.g. to show progress. Does nothing by default.
+pub trait TransferObserver {
+    /// Replies other than block acks: OK, SUCCESS, or why COMPRESS or META was refused.
+    fn server_reply(&mut self, _line: &str) {}
+    /// The receiver accepted the transfer; blocks are about to go out.
+    fn start(&mut self) {}
+    /// The receiver acknowledged block `index`, holding `bytes` bytes of the source.
+    fn block_acked(&mut self, _index: u64, _bytes: u64) {}
+    /// The receiver already had the first `count` blocks, holding `bytes`
+    /// bytes of the source, so they are not sent.
+    fn blocks_skipped(&mut self, _count: u64, _bytes: u64) {}
+}
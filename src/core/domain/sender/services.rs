@@ -0,0 +1,531 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::core::domain::compression::{entities::Codec, services::encode_block};
+use crate::core::domain::delta::{entities::DeltaOp, services::DeltaEncoder};
+use crate::core::domain::network::entities::ProtocolMessage;
+use crate::core::domain::sender::entities::{Block, OutgoingFile, SendError, SendReport};
+use crate::core::domain::sender::ports::{SenderService, TransferObserver};
+use crate::core::domain::storage::{
+    entities::{FileMetadata, YeetBlock},
+    services::{read_metadata, sha256_file},
+};
+
+pub const DEFAULT_BLOCK_SIZE: usize = 1024;
+/// Blocks read ahead for each receiver; the slowest receiver sets the pace.
+pub const BLOCK_QUEUE: usize = 64;
+/// How long to wait for the receiver to close the connection after BYE-RIS.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for any other reply. Generous, since a receiver may be
+/// asking its user whether to accept the file.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Sends files with the regular HELLO / YEET / MISSION-ACCOMPLISHED exchange,
+/// for nodes and background jobs that deliver files without a user watching.
+#[derive(Debug, Clone)]
+pub struct TcpSenderService {
+    block_size: usize,
+    // announce the content hash in HELLO so deduplicating receivers can skip the data
+    content_hash: bool,
+    // codec proposed with COMPRESS; blocks that don't shrink still go out raw
+    codec: Codec,
+    // send all-zero blocks as HOLE instead of their bytes
+    sparse: bool,
+    // send the file's mode, mtime and xattrs with META
+    metadata: bool,
+    // announce files with RESUME, to go on from an interrupted attempt
+    resume: bool,
+}
+
+/// Observer for transfers nobody is watching.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl TransferObserver for NoProgress {}
+
+impl Default for TcpSenderService {
+    fn default() -> Self {
+        TcpSenderService {
+            block_size: DEFAULT_BLOCK_SIZE,
+            content_hash: false,
+            codec: Codec::None,
+            sparse: false,
+            metadata: false,
+            resume: false,
+        }
+    }
+}
+
+impl TcpSenderService {
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Hash every file before sending it and announce the hash in HELLO. Costs
+    /// an extra read of the file; pays off with content-addressed receivers.
+    pub fn with_content_hash(mut self, content_hash: bool) -> Self {
+        self.content_hash = content_hash;
+        self
+    }
+
+    /// Propose `codec` for every transfer. Receivers that refuse it get raw blocks.
+    pub fn with_compression(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Send all-zero blocks as HOLE. Only for receivers that understand it.
+    pub fn with_sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Send each file's permissions, mtime and xattrs with META. Only for
+    /// receivers that understand it; what they apply is up to them.
+    pub fn with_metadata(mut self, metadata: bool) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Announce files with RESUME, so a receiver that kept part of one from an
+    /// interrupted attempt only gets the rest. RESUME names the content by its
+    /// hash, so `send_file` hashes every file; a receiver that doesn't know
+    /// RESUME gets a HELLO instead.
+    pub fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Run one transfer of `file` over `stream`, sending the blocks received
+    /// on `blocks` (see `read_source`). With a known size it is a HELLO closed
+    /// with MISSION-ACCOMPLISHED, otherwise a STREAM closed with EOS. When the
+    /// receiver already has the content announced by `sha256` no data is sent.
+    /// Blocks a resumed transfer already delivered are taken off `blocks`
+    /// without being sent.
+    pub async fn send_over<S, P>(
+        &self,
+        stream: S,
+        file: &OutgoingFile,
+        mut blocks: Receiver<Block>,
+        progress: &mut P,
+    ) -> Result<SendReport, SendError>
+    where
+        S: AsyncRead + AsyncWrite,
+        P: TransferObserver,
+    {
+        let (read_half, mut write_half) = tokio::io::split(stream);
+        let mut replies = BufReader::new(read_half);
+
+        let hello = match file.filesize {
+            Some(filesize) => ProtocolMessage::Hello {
+                filename: file.filename.clone(),
+                filesize,
+                sha256: file.sha256.clone(),
+            },
+            None => ProtocolMessage::Stream {
+                filename: file.filename.clone(),
+            },
+        };
+        let mut announce = match (file.filesize, &file.sha256) {
+            (Some(filesize), Some(sha256)) if self.resume => ProtocolMessage::Resume {
+                filename: file.filename.clone(),
+                filesize,
+                sha256: sha256.clone(),
+                block_size: self.block_size as u32,
+            },
+            _ => hello.clone(),
+        };
+        // Blocks the receiver kept from an earlier attempt.
+        let skip = loop {
+            write_half
+                .write_all((String::from(announce.clone()) + "\n").as_bytes())
+                .await?;
+            match read_reply(&mut replies).await? {
+                (ProtocolMessage::Ok, line) => {
+                    progress.server_reply(&line);
+                    break 0;
+                }
+                (ProtocolMessage::OkResume(blocks), line) => {
+                    progress.server_reply(&line);
+                    break blocks;
+                }
+                (ProtocolMessage::Success, line) if file.sha256.is_some() => {
+                    progress.server_reply(&line);
+                    return self
+                        .close(&file.filename, replies, write_half, 0, 0, 0)
+                        .await;
+                }
+                // A receiver that doesn't know RESUME answers ERROR and stays idle.
+                (ProtocolMessage::Error(_), _)
+                    if matches!(announce, ProtocolMessage::Resume { .. }) =>
+                {
+                    announce = hello.clone();
+                }
+                (_, line) => return Err(SendError::Refused(line)),
+            }
+        };
+
+        let codec = if self.codec == Codec::None {
+            Codec::None
+        } else {
+            let compress = ProtocolMessage::Compress(String::from(self.codec));
+            write_half
+                .write_all((String::from(compress) + "\n").as_bytes())
+                .await?;
+            match read_reply(&mut replies).await? {
+                (ProtocolMessage::Ok, _) => self.codec,
+                // Raw blocks work with any receiver.
+                (_, line) => {
+                    progress.server_reply(&line);
+                    Codec::None
+                }
+            }
+        };
+        if let Some(metadata) = &file.metadata {
+            send_metadata(&mut replies, &mut write_half, metadata, progress).await?;
+        }
+        progress.start();
+
+        let mut index: u64 = 0;
+        let mut bytes: u64 = 0;
+        let mut skipped: u64 = 0;
+        while let Some(block) = blocks.recv().await {
+            let block = block?;
+            if index < skip {
+                skipped += block.len() as u64;
+                index += 1;
+                if index == skip {
+                    progress.blocks_skipped(skip, skipped);
+                }
+                continue;
+            }
+            let frame = self
+                .sparse
+                .then(|| hole_frame(index, &block))
+                .flatten()
+                .unwrap_or_else(|| yeet_frame(index, codec, &block));
+            write_half.write_all(&frame).await?;
+
+            match read_reply(&mut replies).await? {
+                (ProtocolMessage::OkHousten(acked), _) if acked == index => {
+                    progress.block_acked(index, block.len() as u64)
+                }
+                (_, reply) => return Err(SendError::BlockRejected { index, reply }),
+            }
+            index += 1;
+            bytes += block.len() as u64;
+        }
+
+        let end = match file.filesize {
+            Some(_) => ProtocolMessage::MissionAccomplished,
+            None => ProtocolMessage::EndOfStream(bytes),
+        };
+        write_half
+            .write_all((String::from(end) + "\n").as_bytes())
+            .await?;
+        match read_reply(&mut replies).await? {
+            (ProtocolMessage::Success, line) => progress.server_reply(&line),
+            (_, line) => return Err(SendError::NotFinalized(line)),
+        }
+        self.close(&file.filename, replies, write_half, bytes, index, 0)
+            .await
+    }
+
+    /// Run one DELTA transfer over `stream`: fetch the signatures of the
+    /// receiver's copy, then send literals and copies rebuilding `reader`.
+    pub async fn send_delta_over<S, R>(
+        &self,
+        stream: S,
+        filename: &str,
+        filesize: u64,
+        metadata: Option<&FileMetadata>,
+        mut reader: R,
+    ) -> Result<SendReport, SendError>
+    where
+        S: AsyncRead + AsyncWrite,
+        R: AsyncRead + Unpin,
+    {
+        let (read_half, mut write_half) = tokio::io::split(stream);
+        let mut replies = BufReader::new(read_half);
+
+        let delta = ProtocolMessage::Delta {
+            filename: filename.to_string(),
+            filesize,
+            block_size: self.block_size as u32,
+        };
+        write_half
+            .write_all((String::from(delta) + "\n").as_bytes())
+            .await?;
+        let count = match read_reply(&mut replies).await? {
+            (ProtocolMessage::Signature(count), _) => count,
+            (_, line) => return Err(SendError::Refused(line)),
+        };
+        let mut signatures = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            match read_reply(&mut replies).await? {
+                (ProtocolMessage::Sig(signature), _) => signatures.push(signature),
+                (_, line) => return Err(SendError::Refused(line)),
+            }
+        }
+        if let Some(metadata) = metadata {
+            send_metadata(&mut replies, &mut write_half, metadata, &mut NoProgress).await?;
+        }
+
+        let mut encoder = DeltaEncoder::new(&signatures, self.block_size as u32);
+        let mut index: u64 = 0;
+        let mut bytes: u64 = 0;
+        let mut reused: u64 = 0;
+        while let Some(op) = encoder.next_op(&mut reader).await? {
+            match op {
+                DeltaOp::Literal(data) => {
+                    write_half
+                        .write_all(&yeet_frame(index, Codec::None, &data))
+                        .await?;
+
+                    match read_reply(&mut replies).await? {
+                        (ProtocolMessage::OkHousten(acked), _) if acked == index => {}
+                        (_, reply) => return Err(SendError::BlockRejected { index, reply }),
+                    }
+                    index += 1;
+                    bytes += data.len() as u64;
+                }
+                DeltaOp::Copy { offset, len } => {
+                    let copy = ProtocolMessage::Copy { offset, len };
+                    write_half
+                        .write_all((String::from(copy) + "\n").as_bytes())
+                        .await?;
+                    match read_reply(&mut replies).await? {
+                        (ProtocolMessage::Ok, _) => {}
+                        (_, reply) => return Err(SendError::CopyRejected { offset, reply }),
+                    }
+                    reused += len;
+                }
+            }
+        }
+
+        write_half
+            .write_all(
+                (String::from(ProtocolMessage::EndOfStream(bytes + reused)) + "\n").as_bytes(),
+            )
+            .await?;
+        match read_reply(&mut replies).await? {
+            (ProtocolMessage::Success, _) => {}
+            (_, line) => return Err(SendError::NotFinalized(line)),
+        }
+        self.close(filename, replies, write_half, bytes, index, reused)
+            .await
+    }
+
+    /// Say BYE-RIS after SUCCESS and report the delivered file.
+    async fn close<R, W>(
+        &self,
+        filename: &str,
+        mut replies: R,
+        mut write_half: W,
+        bytes: u64,
+        blocks: u64,
+        reused: u64,
+    ) -> Result<SendReport, SendError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        write_half
+            .write_all((String::from(ProtocolMessage::ByeRis) + "\n").as_bytes())
+            .await?;
+        // The listener turns away new connections until the receiver has
+        // closed this one, so wait for that before reporting success: back to
+        // back sends would otherwise race it.
+        let mut rest = Vec::new();
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, replies.read_to_end(&mut rest)).await;
+
+        Ok(SendReport {
+            filename: filename.to_string(),
+            bytes,
+            blocks,
+            reused,
+        })
+    }
+}
+
+impl SenderService for TcpSenderService {
+    async fn send_file(
+        &self,
+        addr: &str,
+        path: &Path,
+        filename: &str,
+    ) -> Result<SendReport, SendError> {
+        let source = tokio::fs::File::open(path).await?;
+        let file = OutgoingFile {
+            filename: filename.to_string(),
+            filesize: Some(source.metadata().await?.len()),
+            sha256: if self.content_hash || self.resume {
+                Some(sha256_file(path).await?)
+            } else {
+                None
+            },
+            metadata: if self.metadata {
+                Some(read_metadata(path).await?)
+            } else {
+                None
+            },
+        };
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(SendError::ConnectFailed)?;
+        let (tx, rx) = mpsc::channel(BLOCK_QUEUE);
+        let mut progress = NoProgress;
+        let (_, report) = tokio::join!(
+            read_source(source, self.block_size, vec![tx]),
+            self.send_over(stream, &file, rx, &mut progress)
+        );
+        report
+    }
+
+    async fn send_delta(
+        &self,
+        addr: &str,
+        path: &Path,
+        filename: &str,
+    ) -> Result<SendReport, SendError> {
+        let file = tokio::fs::File::open(path).await?;
+        let filesize = file.metadata().await?.len();
+        let metadata = if self.metadata {
+            Some(read_metadata(path).await?)
+        } else {
+            None
+        };
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(SendError::ConnectFailed)?;
+        self.send_delta_over(stream, filename, filesize, metadata.as_ref(), file)
+            .await
+    }
+}
+
+/// YEET header, payload and trailing newline for block `index`, compressed with
+/// `codec` when that makes it smaller. Sent in a single write so Nagle doesn't
+/// hold back the block until the next ack.
+pub fn yeet_frame(index: u64, codec: Codec, data: &[u8]) -> Vec<u8> {
+    let (codec, payload) = encode_block(codec, data);
+    // checksum placeholder 0 for now
+    let block = YeetBlock::new(index, payload.len() as u32, 0).with_codec(codec, data.len() as u32);
+    let mut frame = (String::from(ProtocolMessage::Yeet(block)) + "\n").into_bytes();
+    frame.extend_from_slice(&payload);
+    frame.push(b'\n');
+    frame
+}
+
+/// HOLE line for block `index` when `data` is all zeros, so the receiver
+/// doesn't get the bytes and can keep the file sparse.
+pub fn hole_frame(index: u64, data: &[u8]) -> Option<Vec<u8>> {
+    if data.is_empty() || data.iter().any(|&b| b != 0) {
+        return None;
+    }
+    let hole = ProtocolMessage::Hole {
+        index,
+        len: data.len() as u32,
+    };
+    Some((String::from(hole) + "\n").into_bytes())
+}
+
+/// Send META and carry on whatever the answer: the content matters more
+/// than its attributes.
+async fn send_metadata<R, W, P>(
+    replies: &mut R,
+    write_half: &mut W,
+    metadata: &FileMetadata,
+    progress: &mut P,
+) -> Result<(), SendError>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+    P: TransferObserver,
+{
+    let meta = ProtocolMessage::Meta(metadata.clone());
+    write_half
+        .write_all((String::from(meta) + "\n").as_bytes())
+        .await?;
+    match read_reply(replies).await? {
+        (ProtocolMessage::Ok, _) => {}
+        (_, line) => progress.server_reply(&line),
+    }
+    Ok(())
+}
+
+/// Read `reader` once and hand every block to each queue still open.
+///
+/// A queue whose transfer failed is dropped; reading stops early once nobody
+/// is left. A read error is passed on to every queue instead of an end of
+/// input, so no receiver mistakes it for a complete file.
+pub async fn read_source<R>(mut reader: R, block_size: usize, mut queues: Vec<Sender<Block>>)
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buf = vec![0u8; block_size];
+    while !queues.is_empty() {
+        match read_block(&mut reader, &mut buf).await {
+            Ok(0) => break,
+            Ok(n) => {
+                let block: Arc<[u8]> = Arc::from(&buf[..n]);
+                let mut open = Vec::with_capacity(queues.len());
+                for tx in queues {
+                    if tx.send(Ok(block.clone())).await.is_ok() {
+                        open.push(tx);
+                    }
+                }
+                queues = open;
+            }
+            Err(e) => {
+                for tx in &queues {
+                    let _ = tx
+                        .send(Err(std::io::Error::new(e.kind(), e.to_string())))
+                        .await;
+                }
+                break;
+            }
+        }
+    }
+}
+
+/// Fill `buf` as far as possible, so pipes yield full blocks rather than
+/// whatever chunk the writer happened to flush. Returns 0 at end of input.
+async fn read_block<R>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// Read one reply line; unparseable lines (e.g. `ERROR: ...`) become `Error`.
+/// Gives up after `REPLY_TIMEOUT` so a receiver that never answers can't hang us.
+async fn read_reply<R>(reader: &mut R) -> Result<(ProtocolMessage, String), SendError>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+{
+    let mut line = String::new();
+    let read = tokio::time::timeout(REPLY_TIMEOUT, reader.read_line(&mut line))
+        .await
+        .map_err(|_| SendError::NoReply(REPLY_TIMEOUT))?;
+    if read? == 0 {
+        return Err(SendError::ConnectionClosed);
+    }
+    let line = line.trim().to_string();
+    let msg = ProtocolMessage::try_from(line.as_str())
+        .unwrap_or_else(|_| ProtocolMessage::Error(line.clone()));
+    Ok((msg, line))
+}
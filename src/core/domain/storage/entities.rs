@@ -1,8 +1,18 @@
+use crate::core::domain::compression::entities::Codec;
+
+/// Largest block any transfer may use. It bounds what one YEET, HOLE or COPY
+/// makes the receiver read or decode at once.
+pub const MAX_BLOCK_SIZE: u32 = 16 * 1024 * 1024;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct YeetBlock {
     pub index: u64,
+    /// Bytes on the wire, compressed or not.
     pub size: u32,
     pub checksum: u32,
+    pub codec: Codec,
+    /// Bytes once decoded; equal to `size` for uncompressed blocks.
+    pub raw_size: u32,
 }
 
 impl YeetBlock {
@@ -11,7 +21,88 @@ impl YeetBlock {
             index,
             size,
             checksum,
+            codec: Codec::None,
+            raw_size: size,
+        }
+    }
+
+    /// Mark the payload as encoded with `codec`, expanding to `raw_size` bytes.
+    pub fn with_codec(mut self, codec: Codec, raw_size: u32) -> Self {
+        self.codec = codec;
+        self.raw_size = raw_size;
+        self
+    }
+}
+
+/// One stored file as exchanged in a sync manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Relative to the storage root, `/`-separated.
+    pub path: String,
+    pub size: u64,
+    /// Unix seconds. Informational only: receivers only keep mtimes sent with
+    /// META, so files are compared by size and hash.
+    pub mtime: u64,
+    /// Hex-encoded SHA-256 of the content.
+    pub sha256: String,
+}
+
+/// When received data is forced to disk with fsync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Leave it to the OS: a power loss may lose files already acknowledged.
+    None,
+    /// Sync the file and its directory around the final rename, before SUCCESS.
+    #[default]
+    OnFinalize,
+    /// Like `OnFinalize`, and also sync every N blocks so a crash loses at most
+    /// N blocks of a partial transfer.
+    EveryBlocks(u64),
+}
+
+/// Attributes sent along with a file in META.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FileMetadata {
+    /// Unix permission bits, setuid, setgid and sticky included.
+    pub mode: u32,
+    /// Unix seconds.
+    pub mtime: u64,
+    /// Extended attributes as name and raw value.
+    pub xattrs: Vec<(String, Vec<u8>)>,
+}
+
+/// How much of the metadata sent by a peer a receiver applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetadataPolicy {
+    /// Ignore META: files get default permissions and the time they were written.
+    Ignore,
+    /// Apply the permission bits without setuid, setgid and sticky, and the mtime.
+    #[default]
+    Safe,
+    /// Like `Safe`, plus extended attributes in the `user.` namespace.
+    UserXattrs,
+    /// Apply everything as sent, including setuid bits and `security.` xattrs.
+    All,
+}
+
+impl MetadataPolicy {
+    /// What of `metadata` to apply, if anything.
+    pub fn filter(self, mut metadata: FileMetadata) -> Option<FileMetadata> {
+        match self {
+            MetadataPolicy::Ignore => return None,
+            MetadataPolicy::Safe => {
+                metadata.mode &= 0o777;
+                metadata.xattrs.clear();
+            }
+            MetadataPolicy::UserXattrs => {
+                metadata.mode &= 0o777;
+                metadata
+                    .xattrs
+                    .retain(|(name, _)| name.starts_with("user."));
+            }
+            MetadataPolicy::All => metadata.mode &= 0o7777,
         }
+        Some(metadata)
     }
 }
 
@@ -29,6 +120,7 @@ pub enum StorageError {
     AbsolutePathNotAllowed,
     ParentDirSegmentNotAllowed,
     InvalidFilename,
+    InsufficientSpace,
     Unknown(String),
 }
 
@@ -45,6 +137,8 @@ impl From<StorageError> for String {
                 "Parent directory segments are not allowed in filenames".into()
             }
             StorageError::InvalidFilename => "Invalid filename".into(),
+            // Sent as is in NOPE replies.
+            StorageError::InsufficientSpace => "insufficient space".into(),
             StorageError::Unknown(msg) => format!("Unknown storage error: {}", msg),
         }
     }
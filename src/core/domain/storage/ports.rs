@@ -1,15 +1,100 @@
-use crate::core::domain::storage::entities::{StorageError, YeetBlock};
+use crate::core::domain::storage::entities::{
+    FileMetadata, ManifestEntry, StorageError, YeetBlock,
+};
 
 pub trait StorageRepository {
-    fn open_file(&self, filename: &str) -> impl Future<Output = Result<(), StorageError>> + Send;
-    fn write_block(
+    /// A file being received, from `open_file` until it is finalized.
+    type Session: StorageSession + Send + Sync + 'static;
+
+    /// Start receiving `filename`, replacing any partial copy left by an
+    /// earlier attempt. Every block of the transfer goes through the session.
+    /// When the final `size` is known, backends that can check for and reserve
+    /// the space do so here, failing with `InsufficientSpace`.
+    fn open_file(
+        &self,
+        filename: &str,
+        size: Option<u64>,
+    ) -> impl Future<Output = Result<Self::Session, StorageError>> + Send + Sync;
+    /// Go on with the partial copy an interrupted transfer left of `filename`,
+    /// keeping its first `offset` bytes and dropping the rest. Fails when
+    /// there is no such copy or it is shorter; backends that can't write to an
+    /// existing partial copy always fail, and the transfer starts over.
+    fn resume_file(
+        &self,
+        filename: &str,
+        size: Option<u64>,
+        offset: u64,
+    ) -> impl Future<Output = Result<Self::Session, StorageError>> + Send + Sync;
+    /// Every finalized file, for sync manifests.
+    fn list_files(&self) -> impl Future<Output = Result<Vec<ManifestEntry>, StorageError>> + Send;
+    fn delete_file(
+        &self,
+        filename: &str,
+    ) -> impl Future<Output = Result<(), StorageError>> + Send + Sync;
+    /// Up to `len` bytes of the finalized `filename` from `offset`; shorter at end of file.
+    fn read_range(
+        &self,
+        filename: &str,
+        offset: u64,
+        len: u64,
+    ) -> impl Future<Output = Result<Vec<u8>, StorageError>> + Send + Sync;
+    /// Size in bytes of the finalized `filename`.
+    fn file_size(
         &self,
         filename: &str,
+    ) -> impl Future<Output = Result<u64, StorageError>> + Send + Sync;
+    /// Where the finalized `filename` is stored, as recorded in the transfer journal.
+    fn location(&self, filename: &str) -> String;
+}
+
+/// One transfer being written, kept open between blocks.
+///
+/// Writes may be buffered: they are only guaranteed to have reached the
+/// backing store after `sync`, `finalize` or `abandon`. Dropping a session
+/// without either leaves whatever partial copy the backend keeps.
+pub trait StorageSession {
+    fn write_block(
+        &mut self,
+        offset: u64,
         block: &YeetBlock,
         data: &[u8],
-    ) -> impl Future<Output = Result<(), StorageError>> + Send;
-    fn finalize(
+    ) -> impl Future<Output = Result<(), StorageError>> + Send + Sync;
+    /// `len` zero bytes at `offset`, left as a hole where the backend supports
+    /// sparse files and written out as zeros elsewhere.
+    fn write_hole(
+        &mut self,
+        offset: u64,
+        len: u64,
+    ) -> impl Future<Output = Result<(), StorageError>> + Send + Sync;
+    /// Write bytes `offset..offset + len` of the finalized copy of this file
+    /// at `dst_offset`. Used to rebuild files from a delta.
+    fn copy_range(
+        &mut self,
+        offset: u64,
+        len: u64,
+        dst_offset: u64,
+    ) -> impl Future<Output = Result<(), StorageError>> + Send + Sync;
+    /// Metadata to give the file when it is finalized. Backends that have
+    /// nowhere to keep it ignore it.
+    fn set_metadata(&mut self, metadata: FileMetadata);
+    /// Make everything written so far durable: the explicit sync point.
+    fn sync(&mut self) -> impl Future<Output = Result<(), StorageError>> + Send + Sync;
+    /// Flush what is buffered and make the file visible under its final name.
+    fn finalize(self) -> impl Future<Output = Result<(), StorageError>> + Send + Sync;
+    /// Give up on the transfer: keep what was written, synced as by `sync`,
+    /// and release whatever was reserved for the rest of the file.
+    fn abandon(self) -> impl Future<Output = Result<(), StorageError>> + Send + Sync;
+}
+
+/// Extension for backends that store content by hash, so a transfer whose
+/// content is already stored can complete without any data.
+pub trait ContentLookup {
+    /// When content of `size` bytes hashing to `sha256` is stored, make
+    /// `filename` refer to it and return true. Return false to receive it.
+    fn claim(
         &self,
         filename: &str,
-    ) -> impl Future<Output = Result<(), StorageError>> + Send + Sync;
+        sha256: &str,
+        size: u64,
+    ) -> impl Future<Output = Result<bool, StorageError>> + Send + Sync;
 }
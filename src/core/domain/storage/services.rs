@@ -0,0 +1,182 @@
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::core::domain::storage::entities::{FileMetadata, ManifestEntry, StorageError};
+use crate::core::domain::storage::ports::ContentLookup;
+
+/// Extension of files still being received; they never show up in manifests.
+pub const PARTIAL_EXTENSION: &str = "ferrisshare";
+
+/// Source of zeros for backends that can't leave holes and write them out.
+pub static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+/// Lookup for backends without content addressing: every transfer is received.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoContentLookup;
+
+impl ContentLookup for NoContentLookup {
+    async fn claim(
+        &self,
+        _filename: &str,
+        _sha256: &str,
+        _size: u64,
+    ) -> Result<bool, StorageError> {
+        Ok(false)
+    }
+}
+
+/// Hex-encoded SHA-256 of a file's content.
+pub async fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Mode, mtime and extended attributes of a file, to send with META. Xattrs
+/// whose names can't travel in a META line are left out.
+pub async fn read_metadata(path: &Path) -> std::io::Result<FileMetadata> {
+    let meta = tokio::fs::metadata(path).await?;
+    #[cfg(unix)]
+    let mode = std::os::unix::fs::PermissionsExt::mode(&meta.permissions()) & 0o7777;
+    #[cfg(not(unix))]
+    let mode = if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    };
+    let mtime = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let owned = path.to_path_buf();
+    let xattrs = tokio::task::spawn_blocking(move || read_xattrs(&owned))
+        .await
+        .map_err(std::io::Error::other)??
+        .into_iter()
+        .filter(|(name, _)| !name.is_empty() && !name.contains(char::is_whitespace))
+        .collect();
+    Ok(FileMetadata {
+        mode,
+        mtime,
+        xattrs,
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn read_xattrs(path: &Path) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: a null buffer of size 0 only asks for the size of the list.
+    let size = unsafe { libc::listxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        let err = std::io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOTSUP) => Ok(Vec::new()),
+            _ => Err(err),
+        };
+    }
+    let mut names = vec![0u8; size as usize];
+    // SAFETY: `names` has room for `names.len()` bytes.
+    let size = unsafe { libc::listxattr(c_path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+    if size < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    names.truncate(size as usize);
+
+    let mut xattrs = Vec::new();
+    for name in names.split(|&b| b == 0).filter(|n| !n.is_empty()) {
+        let (Ok(c_name), Ok(text)) = (CString::new(name), std::str::from_utf8(name)) else {
+            continue;
+        };
+        // SAFETY: as above, first the size, then the value into a buffer that large.
+        let len =
+            unsafe { libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if len < 0 {
+            continue;
+        }
+        let mut value = vec![0u8; len as usize];
+        let len = unsafe {
+            libc::getxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        // Removed or grown since it was listed: skip it.
+        if len < 0 {
+            continue;
+        }
+        value.truncate(len as usize);
+        xattrs.push((text.to_string(), value));
+    }
+    Ok(xattrs)
+}
+
+/// Extended attributes are only read on Linux.
+#[cfg(not(target_os = "linux"))]
+fn read_xattrs(_path: &Path) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+/// List every regular file under `root` (recursively, symlinks skipped) with
+/// its size, mtime and hash, sorted by path.
+pub async fn scan_dir(root: &Path) -> std::io::Result<Vec<ManifestEntry>> {
+    let mut entries = Vec::new();
+    let mut pending: Vec<PathBuf> = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let mut read_dir = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            if !file_type.is_file() || path.extension().is_some_and(|ext| ext == PARTIAL_EXTENSION)
+            {
+                continue;
+            }
+            let Some(relative) = path
+                .strip_prefix(root)
+                .ok()
+                .and_then(|p| p.to_str())
+                .map(|p| p.replace(std::path::MAIN_SEPARATOR, "/"))
+            else {
+                continue;
+            };
+
+            let meta = entry.metadata().await?;
+            let mtime = meta
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            entries.push(ManifestEntry {
+                path: relative,
+                size: meta.len(),
+                mtime,
+                sha256: sha256_file(&path).await?,
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
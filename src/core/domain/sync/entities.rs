@@ -0,0 +1,78 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::core::domain::storage::entities::ManifestEntry;
+
+/// What a sync has to do to make the receiver match the local directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncPlan {
+    /// New files, and files whose size or hash differ on the receiver.
+    pub upload: Vec<ManifestEntry>,
+    /// Files only the receiver has (empty unless deletions are propagated).
+    pub delete: Vec<String>,
+    /// Local files the protocol can't carry (whitespace in the path).
+    pub skipped: Vec<String>,
+    pub unchanged: usize,
+}
+
+impl SyncPlan {
+    pub fn new(local: &[ManifestEntry], remote: &[ManifestEntry], propagate_deletes: bool) -> Self {
+        let remote_by_path: HashMap<&str, &ManifestEntry> =
+            remote.iter().map(|e| (e.path.as_str(), e)).collect();
+        let mut plan = SyncPlan::default();
+
+        for entry in local {
+            if entry.path.contains(char::is_whitespace) {
+                plan.skipped.push(entry.path.clone());
+                continue;
+            }
+            match remote_by_path.get(entry.path.as_str()) {
+                Some(theirs) if theirs.size == entry.size && theirs.sha256 == entry.sha256 => {
+                    plan.unchanged += 1
+                }
+                _ => plan.upload.push(entry.clone()),
+            }
+        }
+
+        if propagate_deletes {
+            let local_paths: HashSet<&str> = local.iter().map(|e| e.path.as_str()).collect();
+            plan.delete = remote
+                .iter()
+                .filter(|e| !local_paths.contains(e.path.as_str()))
+                .map(|e| e.path.clone())
+                .collect();
+        }
+        plan
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub uploaded: Vec<String>,
+    pub deleted: Vec<String>,
+    pub unchanged: usize,
+    /// Path and reason for every upload, delete or skip that didn't happen.
+    pub failed: Vec<(String, String)>,
+}
+
+#[derive(Debug)]
+pub enum SyncError {
+    ConnectFailed(std::io::Error),
+    Protocol(String),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for SyncError {
+    fn from(err: std::io::Error) -> Self {
+        SyncError::Io(err)
+    }
+}
+
+impl From<SyncError> for String {
+    fn from(err: SyncError) -> Self {
+        match err {
+            SyncError::ConnectFailed(e) => format!("Connection failed: {}", e),
+            SyncError::Protocol(reason) => format!("Sync protocol error: {}", reason),
+            SyncError::Io(e) => format!("Sync I/O error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use std::path::Path;
+
+use crate::core::domain::sync::entities::{SyncError, SyncPlan, SyncReport};
+
+pub trait SyncService {
+    /// Compare `dir` with the receiver's manifest without changing anything.
+    fn plan(
+        &self,
+        dir: &Path,
+        addr: &str,
+        propagate_deletes: bool,
+    ) -> impl Future<Output = Result<SyncPlan, SyncError>> + Send;
+    /// Upload new and changed files, then delete the receiver's extra files
+    /// if `propagate_deletes` is set.
+    fn sync(
+        &self,
+        dir: &Path,
+        addr: &str,
+        propagate_deletes: bool,
+    ) -> impl Future<Output = Result<SyncReport, SyncError>> + Send;
+}
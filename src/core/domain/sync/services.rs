@@ -0,0 +1,182 @@
+use std::path::Path;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+use crate::core::domain::network::entities::ProtocolMessage;
+use crate::core::domain::sender::ports::SenderService;
+use crate::core::domain::storage::entities::ManifestEntry;
+use crate::core::domain::storage::services::scan_dir;
+use crate::core::domain::sync::entities::{SyncError, SyncPlan, SyncReport};
+use crate::core::domain::sync::ports::SyncService;
+
+/// A control connection for MANIFEST and DELETE, which keep the receiver Idle.
+///
+/// The receiver handles one connection at a time, so it must be closed before
+/// files are uploaded on connections of their own.
+struct Control {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Control {
+    async fn connect(addr: &str) -> Result<Self, SyncError> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(SyncError::ConnectFailed)?;
+        let (read_half, writer) = stream.into_split();
+        Ok(Control {
+            reader: BufReader::new(read_half),
+            writer,
+        })
+    }
+
+    async fn send(&mut self, msg: ProtocolMessage) -> Result<(), SyncError> {
+        let line = String::from(msg) + "\n";
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<ProtocolMessage, SyncError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(SyncError::Protocol(
+                "connection closed by receiver".to_string(),
+            ));
+        }
+        ProtocolMessage::try_from(line.as_str())
+            .map_err(|_| SyncError::Protocol(format!("unexpected reply: {}", line.trim())))
+    }
+
+    async fn manifest(&mut self) -> Result<Vec<ManifestEntry>, SyncError> {
+        self.send(ProtocolMessage::ManifestRequest).await?;
+        let count = match self.recv().await? {
+            ProtocolMessage::Manifest(count) => count,
+            ProtocolMessage::Nope(reason) => {
+                return Err(SyncError::Protocol(format!(
+                    "receiver refused MANIFEST: {}",
+                    reason
+                )));
+            }
+            other => {
+                return Err(SyncError::Protocol(format!(
+                    "expected MANIFEST, got {}",
+                    String::from(other)
+                )));
+            }
+        };
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            match self.recv().await? {
+                ProtocolMessage::Entry(entry) => entries.push(entry),
+                other => {
+                    return Err(SyncError::Protocol(format!(
+                        "expected ENTRY, got {}",
+                        String::from(other)
+                    )));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Hang up and wait for the receiver to notice, so it accepts the next connection.
+    async fn close(mut self) -> Result<(), SyncError> {
+        self.writer.shutdown().await?;
+        let mut rest = Vec::new();
+        self.reader.read_to_end(&mut rest).await?;
+        Ok(())
+    }
+
+    /// Returns the receiver's reason when it refused.
+    async fn delete(&mut self, path: &str) -> Result<Result<(), String>, SyncError> {
+        self.send(ProtocolMessage::Delete(path.to_string())).await?;
+        match self.recv().await? {
+            ProtocolMessage::Ok => Ok(Ok(())),
+            ProtocolMessage::Nope(reason) => Ok(Err(reason)),
+            other => Err(SyncError::Protocol(format!(
+                "expected OK or NOPE, got {}",
+                String::from(other)
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncServiceImpl<T>
+where
+    T: SenderService,
+{
+    sender: T,
+}
+
+impl<T> SyncServiceImpl<T>
+where
+    T: SenderService + Send + Sync,
+{
+    pub fn new(sender: T) -> Self {
+        SyncServiceImpl { sender }
+    }
+}
+
+impl<T> SyncService for SyncServiceImpl<T>
+where
+    T: SenderService + Send + Sync,
+{
+    async fn plan(
+        &self,
+        dir: &Path,
+        addr: &str,
+        propagate_deletes: bool,
+    ) -> Result<SyncPlan, SyncError> {
+        let local = scan_dir(dir).await?;
+        let mut control = Control::connect(addr).await?;
+        let remote = control.manifest().await?;
+        control.close().await?;
+        Ok(SyncPlan::new(&local, &remote, propagate_deletes))
+    }
+
+    async fn sync(
+        &self,
+        dir: &Path,
+        addr: &str,
+        propagate_deletes: bool,
+    ) -> Result<SyncReport, SyncError> {
+        let plan = self.plan(dir, addr, propagate_deletes).await?;
+        let mut report = SyncReport {
+            unchanged: plan.unchanged,
+            failed: plan
+                .skipped
+                .iter()
+                .map(|path| (path.clone(), "whitespace in path".to_string()))
+                .collect(),
+            ..SyncReport::default()
+        };
+
+        for entry in &plan.upload {
+            match self
+                .sender
+                // Changed files only send what differs; new ones go out whole.
+                .send_delta(addr, &dir.join(&entry.path), &entry.path)
+                .await
+            {
+                Ok(_) => report.uploaded.push(entry.path.clone()),
+                Err(e) => report.failed.push((entry.path.clone(), String::from(e))),
+            }
+        }
+
+        if !plan.delete.is_empty() {
+            let mut control = Control::connect(addr).await?;
+            for path in &plan.delete {
+                match control.delete(path).await? {
+                    Ok(()) => report.deleted.push(path.clone()),
+                    Err(reason) => report.failed.push((path.clone(), reason)),
+                }
+            }
+            control.close().await?;
+        }
+
+        Ok(report)
+    }
+}
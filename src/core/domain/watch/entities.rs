@@ -0,0 +1,49 @@
+use std::time::{Instant, SystemTime};
+
+/// A file seen in the watched folder that hasn't been sent yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// Size and mtime at the last check; any difference restarts the wait.
+    pub snapshot: Option<(u64, SystemTime)>,
+    pub changed_at: Instant,
+    pub attempts: u32,
+    pub not_before: Instant,
+}
+
+impl Candidate {
+    pub fn new(now: Instant) -> Self {
+        Candidate {
+            snapshot: None,
+            changed_at: now,
+            attempts: 0,
+            not_before: now,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WatchError {
+    WatcherFailed(String),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for WatchError {
+    fn from(err: std::io::Error) -> Self {
+        WatchError::Io(err)
+    }
+}
+
+impl From<notify::Error> for WatchError {
+    fn from(err: notify::Error) -> Self {
+        WatchError::WatcherFailed(err.to_string())
+    }
+}
+
+impl From<WatchError> for String {
+    fn from(err: WatchError) -> Self {
+        match err {
+            WatchError::WatcherFailed(reason) => format!("Watcher failed: {}", reason),
+            WatchError::Io(e) => format!("Watch I/O error: {}", e),
+        }
+    }
+}
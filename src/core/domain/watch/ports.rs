@@ -0,0 +1,7 @@
+use crate::core::domain::watch::entities::WatchError;
+
+pub trait WatchService {
+    /// Send files dropped into the watched folder once they stop changing.
+    /// Only returns on error.
+    fn run(&self) -> impl Future<Output = Result<(), WatchError>> + Send;
+}
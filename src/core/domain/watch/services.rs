@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::core::domain::forward::entities::RetryPolicy;
+use crate::core::domain::sender::ports::SenderService;
+use crate::core::domain::watch::entities::{Candidate, WatchError};
+use crate::core::domain::watch::ports::WatchService;
+
+/// How often candidates are re-checked for stability.
+const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct WatchServiceImpl<T>
+where
+    T: SenderService,
+{
+    sender: T,
+    dir: PathBuf,
+    addr: String,
+    stable_for: Duration,
+    sent_dir: Option<PathBuf>,
+    include_existing: bool,
+    policy: RetryPolicy,
+}
+
+impl<T> WatchServiceImpl<T>
+where
+    T: SenderService + Send + Sync,
+{
+    pub fn new(sender: T, dir: PathBuf, addr: &str) -> Self {
+        WatchServiceImpl {
+            sender,
+            dir,
+            addr: addr.to_string(),
+            stable_for: Duration::from_secs(5),
+            sent_dir: None,
+            include_existing: false,
+            // A watch folder has nowhere to park failed files, so keep retrying.
+            policy: RetryPolicy {
+                max_attempts: 0,
+                ..RetryPolicy::default()
+            },
+        }
+    }
+
+    /// Wait until a file saw no writes for `stable_for` before sending it.
+    pub fn with_stable_for(mut self, stable_for: Duration) -> Self {
+        self.stable_for = stable_for;
+        self
+    }
+
+    /// Move sent files into `sent_dir` instead of leaving them in place.
+    pub fn with_sent_dir(mut self, sent_dir: PathBuf) -> Self {
+        self.sent_dir = Some(sent_dir);
+        self
+    }
+
+    /// Also send the files already in the folder when the watch starts.
+    pub fn with_include_existing(mut self, include_existing: bool) -> Self {
+        self.include_existing = include_existing;
+        self
+    }
+
+    /// Hidden files and our own temporary files are never sent.
+    fn is_candidate(path: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|s| s.to_str()) else {
+            return false;
+        };
+        !name.starts_with('.') && !name.ends_with(".ferrisshare")
+    }
+
+    fn apply(candidates: &mut HashMap<PathBuf, Candidate>, event: Event) {
+        let now = Instant::now();
+        let mut touched = Vec::new();
+        match event.kind {
+            EventKind::Access(_) => {}
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                for path in &event.paths {
+                    candidates.remove(path);
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                if let [from, to] = event.paths.as_slice() {
+                    candidates.remove(from);
+                    touched.push(to.clone());
+                }
+            }
+            _ => touched = event.paths,
+        }
+        for path in touched.into_iter().filter(|p| Self::is_candidate(p)) {
+            candidates
+                .entry(path)
+                .and_modify(|c| c.changed_at = now)
+                .or_insert_with(|| Candidate::new(now));
+        }
+    }
+
+    async fn send_stable(&self, candidates: &mut HashMap<PathBuf, Candidate>) {
+        let paths: Vec<PathBuf> = candidates.keys().cloned().collect();
+        for path in paths {
+            let now = Instant::now();
+            let snapshot = match tokio::fs::metadata(&path).await {
+                Ok(meta) if meta.is_file() => meta.modified().ok().map(|m| (meta.len(), m)),
+                // Gone, or a directory.
+                _ => {
+                    candidates.remove(&path);
+                    continue;
+                }
+            };
+            let Some(candidate) = candidates.get_mut(&path) else {
+                continue;
+            };
+            if candidate.snapshot != snapshot {
+                candidate.snapshot = snapshot;
+                candidate.changed_at = now;
+                continue;
+            }
+            if now.duration_since(candidate.changed_at) < self.stable_for
+                || now < candidate.not_before
+            {
+                continue;
+            }
+
+            let Some(filename) = path.file_name().and_then(|s| s.to_str()) else {
+                candidates.remove(&path);
+                continue;
+            };
+            match self.sender.send_file(&self.addr, &path, filename).await {
+                Ok(report) => {
+                    eprintln!(
+                        "Sent {} ({} bytes) to {}",
+                        report.filename, report.bytes, self.addr
+                    );
+                    candidates.remove(&path);
+                    if let Some(sent_dir) = &self.sent_dir
+                        && let Err(e) = Self::move_to(&path, sent_dir).await
+                    {
+                        eprintln!(
+                            "Failed to move {} to {}: {}",
+                            filename,
+                            sent_dir.display(),
+                            e
+                        );
+                    }
+                }
+                Err(e) => {
+                    candidate.attempts += 1;
+                    let delay = self.policy.backoff(candidate.attempts);
+                    candidate.not_before = Instant::now() + delay;
+                    eprintln!(
+                        "Sending {} failed: {}; retrying in {:?}",
+                        filename,
+                        String::from(e),
+                        delay
+                    );
+                }
+            }
+        }
+    }
+
+    async fn move_to(path: &Path, sent_dir: &Path) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(sent_dir).await?;
+        let name = path.file_name().unwrap_or_default();
+        tokio::fs::rename(path, sent_dir.join(name)).await
+    }
+}
+
+impl<T> WatchService for WatchServiceImpl<T>
+where
+    T: SenderService + Send + Sync,
+{
+    async fn run(&self) -> Result<(), WatchError> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        })?;
+        // Not recursive: sub-folders (including sent/) are left alone.
+        watcher.watch(&self.dir, RecursiveMode::NonRecursive)?;
+        eprintln!(
+            "Watching {} for files to send to {}",
+            self.dir.display(),
+            self.addr
+        );
+
+        let mut candidates = HashMap::new();
+        if self.include_existing {
+            let mut entries = tokio::fs::read_dir(&self.dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if Self::is_candidate(&path) {
+                    candidates.insert(path, Candidate::new(Instant::now()));
+                }
+            }
+        }
+
+        let mut tick = tokio::time::interval(TICK);
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Some(Ok(event)) => Self::apply(&mut candidates, event),
+                    Some(Err(e)) => eprintln!("Watch error: {}", e),
+                    None => return Err(WatchError::WatcherFailed("watcher stopped".to_string())),
+                },
+                _ = tick.tick() => self.send_stable(&mut candidates).await,
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Words used to build human-friendly codes such as `7-crab-shell`.
+pub const CODE_WORDS: [&str; 64] = [
+    "anchor", "barnacle", "beach", "bubble", "buoy", "canal", "cargo", "claw", "coral", "cove",
+    "crab", "current", "delta", "dock", "drift", "dune", "ferry", "fjord", "foam", "gull",
+    "harbor", "hermit", "island", "jetty", "kelp", "krill", "lagoon", "lobster", "marina",
+    "mussel", "net", "oar", "ocean", "otter", "oyster", "paddle", "pearl", "pier", "pincer",
+    "plankton", "pool", "reef", "rudder", "sail", "salt", "sand", "scuttle", "seal", "shell",
+    "shore", "shrimp", "sonar", "spray", "starfish", "surf", "tide", "urchin", "walrus", "wave",
+    "whale", "wharf", "wreck", "yacht", "zephyr",
+];
+
+/// Number of words after the nameplate in a generated code.
+pub const CODE_WORD_COUNT: usize = 2;
+
+/// A short code shared out of band by the two peers: `<nameplate>-<word>-<word>`.
+///
+/// The nameplate picks the mailbox on the rendezvous server; the whole code is
+/// the PAKE password.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WormholeCode {
+    pub nameplate: u32,
+    pub words: Vec<String>,
+}
+
+impl fmt::Display for WormholeCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.nameplate)?;
+        for word in &self.words {
+            write!(f, "-{}", word)?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&str> for WormholeCode {
+    type Error = WormholeError;
+
+    fn try_from(value: &str) -> Result<Self, WormholeError> {
+        let mut parts = value.trim().split('-');
+        let nameplate = parts
+            .next()
+            .and_then(|n| n.parse::<u32>().ok())
+            .ok_or(WormholeError::InvalidCode)?;
+        let words: Vec<String> = parts.map(|w| w.to_ascii_lowercase()).collect();
+        if words.is_empty() || words.iter().any(|w| w.is_empty()) {
+            return Err(WormholeError::InvalidCode);
+        }
+        Ok(WormholeCode { nameplate, words })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WormholeRole {
+    Sender,
+    Receiver,
+}
+
+/// Lines exchanged with the rendezvous server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RendezvousMessage {
+    Allocate,       // "ALLOCATE"
+    Claim(u32),     // "CLAIM <nameplate>"
+    Nameplate(u32), // "NAMEPLATE <nameplate>"
+    Ok,             // "OK"
+    Nope(String),   // "NOPE <reason>"
+    Msg(String),    // "MSG <payload>" forwarded verbatim to the other side
+}
+
+impl TryFrom<&str> for RendezvousMessage {
+    type Error = WormholeError;
+
+    fn try_from(value: &str) -> Result<Self, WormholeError> {
+        let line = value.trim();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let nameplate = || {
+            rest.trim()
+                .parse::<u32>()
+                .map_err(|_| WormholeError::Rendezvous(format!("Invalid nameplate: {}", rest)))
+        };
+
+        match command {
+            "ALLOCATE" => Ok(RendezvousMessage::Allocate),
+            "CLAIM" => Ok(RendezvousMessage::Claim(nameplate()?)),
+            "NAMEPLATE" => Ok(RendezvousMessage::Nameplate(nameplate()?)),
+            "OK" => Ok(RendezvousMessage::Ok),
+            "NOPE" => Ok(RendezvousMessage::Nope(rest.to_string())),
+            "MSG" if !rest.is_empty() => Ok(RendezvousMessage::Msg(rest.to_string())),
+            _ => Err(WormholeError::Rendezvous(format!(
+                "Unexpected message: {}",
+                line
+            ))),
+        }
+    }
+}
+
+impl From<RendezvousMessage> for String {
+    fn from(msg: RendezvousMessage) -> Self {
+        match msg {
+            RendezvousMessage::Allocate => "ALLOCATE".to_string(),
+            RendezvousMessage::Claim(nameplate) => format!("CLAIM {}", nameplate),
+            RendezvousMessage::Nameplate(nameplate) => format!("NAMEPLATE {}", nameplate),
+            RendezvousMessage::Ok => "OK".to_string(),
+            RendezvousMessage::Nope(reason) => format!("NOPE {}", reason),
+            RendezvousMessage::Msg(payload) => format!("MSG {}", payload),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WormholeError {
+    InvalidCode,
+    RendezvousUnreachable(std::io::Error),
+    Rendezvous(String),
+    Pake(String),
+    WrongCode,
+    Transit(String),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for WormholeError {
+    fn from(err: std::io::Error) -> Self {
+        WormholeError::Io(err)
+    }
+}
+
+impl From<WormholeError> for String {
+    fn from(err: WormholeError) -> Self {
+        match err {
+            WormholeError::InvalidCode => {
+                "Invalid wormhole code (expected e.g. 7-crab-shell)".to_string()
+            }
+            WormholeError::RendezvousUnreachable(e) => {
+                format!("Rendezvous server unreachable: {}", e)
+            }
+            WormholeError::Rendezvous(msg) => format!("Rendezvous error: {}", msg),
+            WormholeError::Pake(msg) => format!("Key exchange failed: {}", msg),
+            WormholeError::WrongCode => "Key confirmation failed: wrong code?".to_string(),
+            WormholeError::Transit(msg) => format!("Transit error: {}", msg),
+            WormholeError::Io(e) => format!("I/O error: {}", e),
+        }
+    }
+}
@@ -0,0 +1,4 @@
+pub mod entities;
+pub mod ports;
+pub mod services;
+pub mod transit;
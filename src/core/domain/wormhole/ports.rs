@@ -0,0 +1,21 @@
+use std::net::SocketAddr;
+
+use tokio::io::DuplexStream;
+
+use crate::core::domain::wormhole::entities::{WormholeCode, WormholeError};
+
+pub trait WormholeService {
+    /// Allocate a code, hand it to `on_code` (to be shown to the user), then wait
+    /// for the receiver and return an encrypted stream connected to it.
+    fn connect_as_sender<F>(
+        &self,
+        on_code: F,
+    ) -> impl Future<Output = Result<DuplexStream, WormholeError>> + Send
+    where
+        F: FnOnce(&WormholeCode) + Send;
+    /// Join the sender that allocated `code` and return an encrypted stream from it.
+    fn connect_as_receiver(
+        &self,
+        code: &WormholeCode,
+    ) -> impl Future<Output = Result<(DuplexStream, SocketAddr), WormholeError>> + Send;
+}
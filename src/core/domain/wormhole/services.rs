@@ -0,0 +1,291 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::core::domain::relay::ports::RelayClient as _;
+use crate::core::domain::relay::services::RelayClientImpl;
+use crate::core::domain::wormhole::entities::{
+    CODE_WORD_COUNT, CODE_WORDS, RendezvousMessage, WormholeCode, WormholeError, WormholeRole,
+};
+use crate::core::domain::wormhole::ports::WormholeService;
+use crate::core::domain::wormhole::transit::{TransitKeys, derive_key, secure_stream};
+
+pub const DEFAULT_RENDEZVOUS_ADDR: &str = "127.0.0.1:9010";
+const PAKE_IDENTITY: &[u8] = b"ferrisshare-wormhole";
+/// How long the receiver waits for the sender to dial its transit listener.
+const TRANSIT_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long the sender tries the receiver's transit port before using the relay.
+const DIRECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Our side of a nameplate on the rendezvous server.
+struct Mailbox {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    local_addr: SocketAddr,
+}
+
+impl Mailbox {
+    async fn connect(addr: &str) -> Result<Self, WormholeError> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(WormholeError::RendezvousUnreachable)?;
+        let local_addr = stream.local_addr()?;
+        let (read_half, writer) = stream.into_split();
+        Ok(Mailbox {
+            reader: BufReader::new(read_half),
+            writer,
+            local_addr,
+        })
+    }
+
+    async fn send(&mut self, msg: RendezvousMessage) -> Result<(), WormholeError> {
+        let line = String::from(msg) + "\n";
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Result<RendezvousMessage, WormholeError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(WormholeError::Rendezvous(
+                "connection closed by rendezvous server".to_string(),
+            ));
+        }
+        RendezvousMessage::try_from(line.as_str())
+    }
+
+    /// Send a `MSG <kind> <value>` to the peer.
+    async fn post(&mut self, kind: &str, value: &str) -> Result<(), WormholeError> {
+        self.send(RendezvousMessage::Msg(format!("{} {}", kind, value)))
+            .await
+    }
+
+    /// Wait for the peer's `MSG <kind> <value>` and return the value.
+    async fn expect(&mut self, kind: &str) -> Result<String, WormholeError> {
+        match self.recv().await? {
+            RendezvousMessage::Msg(payload) => match payload.split_once(' ') {
+                Some((k, value)) if k == kind => Ok(value.to_string()),
+                _ => Err(WormholeError::Rendezvous(format!(
+                    "expected '{}' from peer, got '{}'",
+                    kind, payload
+                ))),
+            },
+            RendezvousMessage::Nope(reason) => Err(WormholeError::Rendezvous(reason)),
+            other => Err(WormholeError::Rendezvous(format!(
+                "unexpected message {:?}",
+                other
+            ))),
+        }
+    }
+
+    /// Run SPAKE2 with the code as password and check both sides got the same key.
+    async fn establish(
+        &mut self,
+        code: &WormholeCode,
+        role: WormholeRole,
+    ) -> Result<Vec<u8>, WormholeError> {
+        let password = code.to_string();
+        let (spake, outbound) = Spake2::<Ed25519Group>::start_symmetric(
+            &Password::new(password.as_bytes()),
+            &Identity::new(PAKE_IDENTITY),
+        );
+        self.post("pake", &hex::encode(outbound)).await?;
+
+        let inbound = hex::decode(self.expect("pake").await?)
+            .map_err(|e| WormholeError::Pake(e.to_string()))?;
+        let key = spake
+            .finish(&inbound)
+            .map_err(|e| WormholeError::Pake(format!("{:?}", e)))?;
+
+        // Each side proves the key with its own value, so a peer without the
+        // key can't pass by echoing ours back. A mismatch means the codes differ.
+        let sender_confirm = hex::encode(derive_key(&key, b"ferrisshare confirm sender"));
+        let receiver_confirm = hex::encode(derive_key(&key, b"ferrisshare confirm receiver"));
+        let (ours, theirs) = match role {
+            WormholeRole::Sender => (sender_confirm, receiver_confirm),
+            WormholeRole::Receiver => (receiver_confirm, sender_confirm),
+        };
+        self.post("confirm", &ours).await?;
+        if self.expect("confirm").await? != theirs {
+            return Err(WormholeError::WrongCode);
+        }
+        Ok(key)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WormholeServiceImpl {
+    rendezvous_addr: String,
+    // Relay the sender falls back to when the transit port is unreachable.
+    relay_addr: Option<String>,
+}
+
+impl WormholeServiceImpl {
+    pub fn new(rendezvous_addr: &str) -> Self {
+        WormholeServiceImpl {
+            rendezvous_addr: rendezvous_addr.to_string(),
+            relay_addr: None,
+        }
+    }
+
+    /// Fall back to `relay_addr` when the sender can't reach the receiver directly.
+    /// Only the sender's setting matters; it tells the receiver which relay to use.
+    pub fn with_relay(mut self, relay_addr: &str) -> Self {
+        self.relay_addr = Some(relay_addr.to_string());
+        self
+    }
+
+    /// Both sides derive the relay session from the PAKE key, so nobody else
+    /// can guess it and it doesn't leak anything about the key.
+    fn relay_session(key: &[u8]) -> String {
+        hex::encode(&derive_key(key, b"ferrisshare relay session")[..16])
+    }
+
+    async fn dial_relay(relay_addr: &str, key: &[u8]) -> Result<TcpStream, WormholeError> {
+        RelayClientImpl::new(relay_addr)
+            .dial(&Self::relay_session(key))
+            .await
+            .map_err(|e| WormholeError::Transit(String::from(e)))
+    }
+
+    fn generate_code(nameplate: u32) -> WormholeCode {
+        let words = (0..CODE_WORD_COUNT)
+            .map(|_| CODE_WORDS[OsRng.next_u32() as usize % CODE_WORDS.len()].to_string())
+            .collect();
+        WormholeCode { nameplate, words }
+    }
+}
+
+impl WormholeService for WormholeServiceImpl {
+    async fn connect_as_sender<F>(&self, on_code: F) -> Result<DuplexStream, WormholeError>
+    where
+        F: FnOnce(&WormholeCode) + Send,
+    {
+        let mut mailbox = Mailbox::connect(&self.rendezvous_addr).await?;
+        mailbox.send(RendezvousMessage::Allocate).await?;
+        let nameplate = match mailbox.recv().await? {
+            RendezvousMessage::Nameplate(n) => n,
+            RendezvousMessage::Nope(reason) => return Err(WormholeError::Rendezvous(reason)),
+            other => {
+                return Err(WormholeError::Rendezvous(format!(
+                    "unexpected message {:?}",
+                    other
+                )));
+            }
+        };
+
+        let code = Self::generate_code(nameplate);
+        on_code(&code);
+
+        let key = mailbox.establish(&code, WormholeRole::Sender).await?;
+        let transit_addr = mailbox.expect("transit").await?;
+        eprintln!("Connecting to receiver at {}", transit_addr);
+
+        let direct =
+            match tokio::time::timeout(DIRECT_TIMEOUT, TcpStream::connect(&transit_addr)).await {
+                Ok(Ok(stream)) => Ok(stream),
+                Ok(Err(e)) => Err(format!("{}: {}", transit_addr, e)),
+                Err(_) => Err(format!("{}: timed out", transit_addr)),
+            };
+        let stream = match (direct, &self.relay_addr) {
+            (Ok(stream), _) => stream,
+            (Err(e), Some(relay_addr)) => {
+                eprintln!(
+                    "Direct connection failed ({}), using relay {}",
+                    e, relay_addr
+                );
+                mailbox.post("relay", relay_addr).await?;
+                Self::dial_relay(relay_addr, &key).await?
+            }
+            (Err(e), None) => return Err(WormholeError::Transit(e)),
+        };
+        // The transit layer encrypts end to end, relay or not.
+        let keys = TransitKeys::derive(&key, WormholeRole::Sender);
+        secure_stream(stream, keys, WormholeRole::Sender).await
+    }
+
+    async fn connect_as_receiver(
+        &self,
+        code: &WormholeCode,
+    ) -> Result<(DuplexStream, SocketAddr), WormholeError> {
+        let mut mailbox = Mailbox::connect(&self.rendezvous_addr).await?;
+        mailbox
+            .send(RendezvousMessage::Claim(code.nameplate))
+            .await?;
+        match mailbox.recv().await? {
+            RendezvousMessage::Ok => {}
+            RendezvousMessage::Nope(reason) => return Err(WormholeError::Rendezvous(reason)),
+            other => {
+                return Err(WormholeError::Rendezvous(format!(
+                    "unexpected message {:?}",
+                    other
+                )));
+            }
+        }
+
+        let key = mailbox.establish(code, WormholeRole::Receiver).await?;
+
+        // Listen on the interface we use to reach the rendezvous server, which
+        // is the one most likely to be reachable by the sender too.
+        let listener = TcpListener::bind(SocketAddr::new(mailbox.local_addr.ip(), 0)).await?;
+        let transit_addr = listener.local_addr()?;
+        mailbox.post("transit", &transit_addr.to_string()).await?;
+
+        let accept = async {
+            // Handshakes run on their own tasks, so a peer that connects and
+            // says nothing can't keep the sender from being accepted. The
+            // ones still running are dropped with the set.
+            let mut handshakes = tokio::task::JoinSet::new();
+            loop {
+                tokio::select! {
+                    accepted = listener.accept() => {
+                        let (stream, peer) = accepted?;
+                        let keys = TransitKeys::derive(&key, WormholeRole::Receiver);
+                        handshakes.spawn(async move {
+                            (peer, secure_stream(stream, keys, WormholeRole::Receiver).await)
+                        });
+                    }
+                    Some(handshake) = handshakes.join_next() => match handshake {
+                        Ok((peer, Ok(secured))) => return Ok((secured, peer)),
+                        // Not our sender (or a corrupted handshake): keep waiting.
+                        Ok((peer, Err(e))) => eprintln!(
+                            "Rejected transit connection from {}: {}",
+                            peer,
+                            String::from(e)
+                        ),
+                        Err(e) => eprintln!("Transit handshake task failed: {}", e),
+                    },
+                }
+            }
+        };
+        // The sender asks us to meet on its relay if it can't reach our port.
+        // If it connects directly it drops the mailbox instead, so an error here
+        // just means there is no relay to wait for.
+        let relay_hint = async {
+            match mailbox.expect("relay").await {
+                Ok(relay_addr) => relay_addr,
+                Err(_) => std::future::pending().await,
+            }
+        };
+        let connect = async {
+            tokio::select! {
+                accepted = accept => accepted,
+                relay_addr = relay_hint => {
+                    eprintln!("Sender can't reach us directly, joining relay {}", relay_addr);
+                    let stream = Self::dial_relay(&relay_addr, &key).await?;
+                    let peer = stream.peer_addr()?;
+                    let keys = TransitKeys::derive(&key, WormholeRole::Receiver);
+                    Ok((secure_stream(stream, keys, WormholeRole::Receiver).await?, peer))
+                }
+            }
+        };
+        tokio::time::timeout(TRANSIT_TIMEOUT, connect)
+            .await
+            .map_err(|_| WormholeError::Transit("sender never connected".to_string()))?
+    }
+}
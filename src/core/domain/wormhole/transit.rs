@@ -0,0 +1,193 @@
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce, aead::Aead};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+
+use crate::core::domain::wormhole::entities::{WormholeError, WormholeRole};
+
+/// Largest plaintext carried by one encrypted frame.
+const MAX_FRAME: usize = 64 * 1024;
+/// AEAD tag appended to every frame.
+const TAG_LEN: usize = 16;
+const HANDSHAKE: &[u8] = b"ferrisshare-transit-v1";
+
+/// One direction of an encrypted transit connection.
+struct FrameCipher {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl FrameCipher {
+    fn new(key: &[u8; 32]) -> Self {
+        FrameCipher {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+            counter: 0,
+        }
+    }
+
+    // Each direction has its own key, so a plain counter is a unique nonce.
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..8].copy_from_slice(&self.counter.to_le_bytes());
+        self.counter += 1;
+        nonce
+    }
+
+    fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, WormholeError> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| WormholeError::Transit("encryption failed".to_string()))
+    }
+
+    fn open(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, WormholeError> {
+        let nonce = self.next_nonce();
+        self.cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .map_err(|_| WormholeError::Transit("frame failed authentication".to_string()))
+    }
+}
+
+/// Per-direction keys derived from the PAKE session key.
+pub struct TransitKeys {
+    send: [u8; 32],
+    recv: [u8; 32],
+}
+
+impl TransitKeys {
+    pub fn derive(session_key: &[u8], role: WormholeRole) -> Self {
+        let sender_to_receiver = derive_key(session_key, b"ferrisshare transit sender");
+        let receiver_to_sender = derive_key(session_key, b"ferrisshare transit receiver");
+        match role {
+            WormholeRole::Sender => TransitKeys {
+                send: sender_to_receiver,
+                recv: receiver_to_sender,
+            },
+            WormholeRole::Receiver => TransitKeys {
+                send: receiver_to_sender,
+                recv: sender_to_receiver,
+            },
+        }
+    }
+}
+
+/// Expand the session key into a 32-byte subkey for `purpose`.
+pub fn derive_key(session_key: &[u8], purpose: &[u8]) -> [u8; 32] {
+    let mut okm = [0u8; 32];
+    Hkdf::<Sha256>::new(None, session_key)
+        .expand(purpose, &mut okm)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    okm
+}
+
+async fn write_frame<W>(
+    writer: &mut W,
+    cipher: &mut FrameCipher,
+    plaintext: &[u8],
+) -> Result<(), WormholeError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let ciphertext = cipher.seal(plaintext)?;
+    let mut frame = (ciphertext.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(&ciphertext);
+    writer.write_all(&frame).await?;
+    Ok(())
+}
+
+/// Read and decrypt one frame; `Ok(None)` on a clean end of stream.
+async fn read_frame<R>(
+    reader: &mut R,
+    cipher: &mut FrameCipher,
+) -> Result<Option<Vec<u8>>, WormholeError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME + TAG_LEN {
+        return Err(WormholeError::Transit(format!("frame too large: {}", len)));
+    }
+    let mut ciphertext = vec![0u8; len];
+    reader.read_exact(&mut ciphertext).await?;
+    cipher.open(&ciphertext).map(Some)
+}
+
+/// Authenticate `stream` with the transit keys and return a plaintext stream.
+///
+/// Both sides first exchange an encrypted handshake frame, so a peer holding
+/// the wrong key is rejected here rather than halfway through the protocol.
+/// Afterwards two tasks pump data between `stream` and the returned
+/// `DuplexStream`, sealing each chunk in a length-prefixed ChaCha20-Poly1305 frame.
+pub async fn secure_stream<S>(
+    stream: S,
+    keys: TransitKeys,
+    role: WormholeRole,
+) -> Result<DuplexStream, WormholeError>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (mut net_read, mut net_write) = tokio::io::split(stream);
+    let mut sealer = FrameCipher::new(&keys.send);
+    let mut opener = FrameCipher::new(&keys.recv);
+
+    let check = |frame: Option<Vec<u8>>| match frame {
+        Some(hello) if hello == HANDSHAKE => Ok(()),
+        _ => Err(WormholeError::Transit("handshake failed".to_string())),
+    };
+    match role {
+        WormholeRole::Sender => {
+            write_frame(&mut net_write, &mut sealer, HANDSHAKE).await?;
+            check(read_frame(&mut net_read, &mut opener).await?)?;
+        }
+        WormholeRole::Receiver => {
+            check(read_frame(&mut net_read, &mut opener).await?)?;
+            write_frame(&mut net_write, &mut sealer, HANDSHAKE).await?;
+        }
+    }
+
+    let (plain, inner) = tokio::io::duplex(MAX_FRAME);
+    let (mut inner_read, mut inner_write) = tokio::io::split(inner);
+
+    // plaintext written by the caller -> encrypted frames on the network
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; MAX_FRAME];
+        loop {
+            let n = match inner_read.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            if let Err(e) = write_frame(&mut net_write, &mut sealer, &buf[..n]).await {
+                eprintln!("Transit send error: {}", String::from(e));
+                break;
+            }
+        }
+        let _ = net_write.shutdown().await;
+    });
+
+    // encrypted frames from the network -> plaintext for the caller
+    tokio::spawn(async move {
+        loop {
+            match read_frame(&mut net_read, &mut opener).await {
+                Ok(Some(plaintext)) => {
+                    if inner_write.write_all(&plaintext).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("Transit receive error: {}", String::from(e));
+                    break;
+                }
+            }
+        }
+        let _ = inner_write.shutdown().await;
+    });
+
+    Ok(plain)
+}
@@ -0,0 +1 @@
+pub mod tty_transfer_gate;
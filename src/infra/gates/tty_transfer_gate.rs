@@ -0,0 +1,80 @@
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, BufReader, Stdin};
+use tokio::sync::Mutex;
+
+use crate::core::domain::command::{
+    entities::{IncomingTransfer, TransferDecision, TransferKind},
+    ports::TransferGate,
+};
+
+/// Asks the operator on the terminal whether to accept each incoming transfer,
+/// and each DELETE or MANIFEST when sync is enabled.
+///
+/// Peers whose IP is in `trusted` are accepted without prompting.
+#[derive(Clone)]
+pub struct TtyTransferGate {
+    trusted: Vec<IpAddr>,
+    // Shared so input buffered by one prompt is not lost for the next one.
+    input: Arc<Mutex<BufReader<Stdin>>>,
+}
+
+impl TtyTransferGate {
+    pub fn new(trusted: Vec<IpAddr>) -> Self {
+        TtyTransferGate {
+            trusted,
+            input: Arc::new(Mutex::new(BufReader::new(tokio::io::stdin()))),
+        }
+    }
+
+    fn is_trusted(&self, transfer: &IncomingTransfer) -> bool {
+        transfer
+            .peer
+            .is_some_and(|peer| self.trusted.contains(&peer.ip()))
+    }
+}
+
+impl TransferGate for TtyTransferGate {
+    async fn review(&self, transfer: &IncomingTransfer) -> TransferDecision {
+        let peer = transfer
+            .peer
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "unknown peer".to_string());
+
+        let question = match transfer.kind {
+            TransferKind::Receive => {
+                let size = transfer
+                    .filesize
+                    .map(|s| format!("{} bytes", s))
+                    .unwrap_or_else(|| "unknown size".to_string());
+                format!("Accept {} ({}) from {}?", transfer.filename, size, peer)
+            }
+            TransferKind::Delete => format!("Let {} delete {}?", peer, transfer.filename),
+            TransferKind::Manifest => format!("Let {} list the stored files?", peer),
+        };
+
+        if self.is_trusted(transfer) {
+            eprintln!(
+                "Auto-accepting {:?} {} from trusted peer {}",
+                transfer.kind, transfer.filename, peer
+            );
+            return TransferDecision::Accept;
+        }
+
+        // The prompt goes to stderr: stdout may be carrying the received data.
+        let mut input = self.input.lock().await;
+        eprint!("{} [y/N] ", question);
+        let _ = std::io::stderr().flush();
+
+        let mut answer = String::new();
+        match input.read_line(&mut answer).await {
+            Ok(0) | Err(_) => TransferDecision::Reject("no operator available".to_string()),
+            Ok(_) => match answer.trim().to_ascii_lowercase().as_str() {
+                "y" | "yes" => TransferDecision::Accept,
+                _ => TransferDecision::Reject("rejected by operator".to_string()),
+            },
+        }
+    }
+}
@@ -1 +1,2 @@
+pub mod gates;
 pub mod repositories;
@@ -0,0 +1,369 @@
+use std::path::PathBuf;
+
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::core::domain::storage::{
+    entities::{Durability, FileMetadata, ManifestEntry, StorageError, YeetBlock},
+    ports::{ContentLookup, StorageRepository, StorageSession},
+    services::{PARTIAL_EXTENSION, sha256_file},
+};
+use crate::infra::repositories::fs::fs_storage_repository::{
+    FSStorageRepository, PartFile, sync_dir,
+};
+
+/// Content-addressed storage: each distinct content is stored once as a blob
+/// named after its SHA-256, and every received filename is a ref pointing at one.
+///
+/// Layout under the base path:
+/// - `blobs/<first two hex digits>/<sha256>`: the content
+/// - `refs/<filename>`: the hex hash of the blob it names
+/// - `tmp/<filename>.ferrisshare`: transfers in progress, hashed on finalize
+#[derive(Clone)]
+pub struct CasStorageRepository {
+    base_path: PathBuf,
+    durability: Durability,
+}
+
+impl CasStorageRepository {
+    pub fn new(base_path: String) -> Self {
+        CasStorageRepository {
+            base_path: PathBuf::from(base_path),
+            durability: Durability::default(),
+        }
+    }
+
+    /// Replace the default `Durability::OnFinalize` policy.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    fn ref_path(&self, filename: &str) -> PathBuf {
+        self.base_path.join("refs").join(filename)
+    }
+
+    fn part_path(&self, filename: &str) -> PathBuf {
+        self.base_path
+            .join("tmp")
+            .join(filename)
+            .with_extension(PARTIAL_EXTENSION)
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.base_path.join("blobs").join(&sha256[..2]).join(sha256)
+    }
+
+    /// Blob a ref points at.
+    async fn resolve(&self, filename: &str) -> Result<PathBuf, StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        match tokio::fs::read_to_string(self.ref_path(filename)).await {
+            Ok(sha256) if is_sha256(sha256.trim()) => Ok(self.blob_path(sha256.trim())),
+            Ok(_) => Err(StorageError::Unknown(format!(
+                "Corrupt ref for {}",
+                filename
+            ))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(StorageError::FileNotFound),
+            Err(e) => Err(StorageError::Unknown(e.to_string())),
+        }
+    }
+
+    /// Point `filename` at `sha256`, replacing any previous ref atomically.
+    async fn write_ref(&self, filename: &str, sha256: &str) -> Result<(), StorageError> {
+        let path = self.ref_path(filename);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| StorageError::Unknown(format!("Failed to create dir: {}", e)))?;
+        }
+        let tmp = path.with_extension(PARTIAL_EXTENSION);
+        let mut part = PartFile::create(&tmp, self.durability).await?;
+        part.write_at(0, sha256.as_bytes()).await?;
+        let durability = part.finish().await?;
+        drop(part);
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))?;
+        sync_dir(&path, durability).await
+    }
+
+    /// Every ref as (filename, sha256), sorted by filename.
+    async fn refs(&self) -> std::io::Result<Vec<(String, String)>> {
+        let root = self.base_path.join("refs");
+        let mut refs = Vec::new();
+        let mut pending = vec![root.clone()];
+
+        while let Some(dir) = pending.pop() {
+            let mut read_dir = match tokio::fs::read_dir(&dir).await {
+                Ok(read_dir) => read_dir,
+                // nothing received yet
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && dir == root => {
+                    return Ok(refs);
+                }
+                Err(e) => return Err(e),
+            };
+            while let Some(entry) = read_dir.next_entry().await? {
+                let path = entry.path();
+                let file_type = entry.file_type().await?;
+                if file_type.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                if !file_type.is_file()
+                    || path.extension().is_some_and(|ext| ext == PARTIAL_EXTENSION)
+                {
+                    continue;
+                }
+                let Some(relative) = path
+                    .strip_prefix(&root)
+                    .ok()
+                    .and_then(|p| p.to_str())
+                    .map(|p| p.replace(std::path::MAIN_SEPARATOR, "/"))
+                else {
+                    continue;
+                };
+                let sha256 = tokio::fs::read_to_string(&path).await?;
+                let sha256 = sha256.trim();
+                if is_sha256(sha256) {
+                    refs.push((relative, sha256.to_string()));
+                }
+            }
+        }
+
+        refs.sort();
+        Ok(refs)
+    }
+}
+
+impl StorageRepository for CasStorageRepository {
+    type Session = CasSession;
+
+    async fn open_file(
+        &self,
+        filename: &str,
+        size: Option<u64>,
+    ) -> Result<CasSession, StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        let part_path = self.part_path(filename);
+        let mut part = PartFile::create(&part_path, self.durability).await?;
+        // Blobs land on the same filesystem as `tmp/`.
+        if let Some(size) = size
+            && let Err(e) = part.reserve(size).await
+        {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(e);
+        }
+        Ok(CasSession {
+            repository: self.clone(),
+            filename: filename.to_string(),
+            part_path,
+            part,
+        })
+    }
+
+    async fn resume_file(
+        &self,
+        filename: &str,
+        size: Option<u64>,
+        offset: u64,
+    ) -> Result<CasSession, StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        let part_path = self.part_path(filename);
+        let mut part = PartFile::reopen(&part_path, self.durability, offset).await?;
+        if let Some(size) = size {
+            part.reserve(size).await?;
+        }
+        Ok(CasSession {
+            repository: self.clone(),
+            filename: filename.to_string(),
+            part_path,
+            part,
+        })
+    }
+
+    async fn list_files(&self) -> Result<Vec<ManifestEntry>, StorageError> {
+        let refs = self
+            .refs()
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))?;
+        let mut entries = Vec::with_capacity(refs.len());
+        for (path, sha256) in refs {
+            let Ok(meta) = tokio::fs::metadata(self.blob_path(&sha256)).await else {
+                eprintln!("Ref {} points at missing blob {}", path, sha256);
+                continue;
+            };
+            let mtime = meta
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            entries.push(ManifestEntry {
+                path,
+                size: meta.len(),
+                mtime,
+                sha256,
+            });
+        }
+        Ok(entries)
+    }
+
+    async fn delete_file(&self, filename: &str) -> Result<(), StorageError> {
+        let blob = self.resolve(filename).await?;
+        tokio::fs::remove_file(self.ref_path(filename))
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))?;
+
+        // Drop the blob once no other ref points at it.
+        let sha256 = blob
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let refs = self
+            .refs()
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))?;
+        if !refs.iter().any(|(_, other)| *other == sha256) {
+            let _ = tokio::fs::remove_file(&blob).await;
+        }
+        Ok(())
+    }
+
+    async fn read_range(
+        &self,
+        filename: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, StorageError> {
+        let blob = self.resolve(filename).await?;
+        let mut file = tokio::fs::File::open(&blob)
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))?;
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))?;
+        let mut data = Vec::new();
+        file.take(len)
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))?;
+        Ok(data)
+    }
+
+    async fn file_size(&self, filename: &str) -> Result<u64, StorageError> {
+        let blob = self.resolve(filename).await?;
+        tokio::fs::metadata(&blob)
+            .await
+            .map(|metadata| metadata.len())
+            .map_err(|e| StorageError::Unknown(e.to_string()))
+    }
+
+    fn location(&self, filename: &str) -> String {
+        self.ref_path(filename).display().to_string()
+    }
+}
+
+impl ContentLookup for CasStorageRepository {
+    async fn claim(&self, filename: &str, sha256: &str, size: u64) -> Result<bool, StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        if !is_sha256(sha256) {
+            return Ok(false);
+        }
+        match tokio::fs::metadata(self.blob_path(sha256)).await {
+            Ok(meta) if meta.len() == size => {
+                self.write_ref(filename, sha256).await?;
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(StorageError::Unknown(e.to_string())),
+        }
+    }
+}
+
+/// Lowercase hex SHA-256, the only thing allowed to become a blob path.
+fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// A transfer written to `tmp/`, hashed into a blob on finalize.
+pub struct CasSession {
+    repository: CasStorageRepository,
+    filename: String,
+    part_path: PathBuf,
+    part: PartFile,
+}
+
+impl StorageSession for CasSession {
+    async fn write_block(
+        &mut self,
+        offset: u64,
+        _block: &YeetBlock,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        self.part.write_block(offset, data).await
+    }
+
+    async fn write_hole(&mut self, offset: u64, len: u64) -> Result<(), StorageError> {
+        self.part.write_hole(offset, len).await
+    }
+
+    async fn copy_range(
+        &mut self,
+        offset: u64,
+        len: u64,
+        dst_offset: u64,
+    ) -> Result<(), StorageError> {
+        let blob = self.repository.resolve(&self.filename).await?;
+        let mut source = tokio::fs::File::open(&blob)
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))?;
+        self.part
+            .copy_from(&mut source, offset, len, dst_offset)
+            .await
+    }
+
+    /// Blobs are shared by every name with the same content, so they keep
+    /// the permissions and mtime they were stored with.
+    fn set_metadata(&mut self, _metadata: FileMetadata) {}
+
+    async fn sync(&mut self) -> Result<(), StorageError> {
+        self.part.sync().await
+    }
+
+    async fn finalize(mut self) -> Result<(), StorageError> {
+        let durability = self.part.finish().await?;
+        let sha256 = sha256_file(&self.part_path)
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))?;
+
+        let blob = self.repository.blob_path(&sha256);
+        if tokio::fs::try_exists(&blob).await.unwrap_or(false) {
+            // Same content already stored: keep the existing blob.
+            let _ = tokio::fs::remove_file(&self.part_path).await;
+        } else {
+            if let Some(parent) = blob.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .map_err(|e| StorageError::Unknown(format!("Failed to create dir: {}", e)))?;
+            }
+            tokio::fs::rename(&self.part_path, &blob)
+                .await
+                .map_err(|e| StorageError::Unknown(e.to_string()))?;
+            sync_dir(&blob, durability).await?;
+        }
+        eprintln!("Stored {} as blob {}", self.filename, sha256);
+        self.repository.write_ref(&self.filename, &sha256).await
+    }
+
+    /// A partial file with nothing in it is removed rather than kept.
+    async fn abandon(mut self) -> Result<(), StorageError> {
+        if self.part.release().await? == 0 {
+            drop(self.part);
+            tokio::fs::remove_file(&self.part_path)
+                .await
+                .map_err(|e| StorageError::Unknown(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
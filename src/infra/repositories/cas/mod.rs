@@ -0,0 +1 @@
+pub mod cas_storage_repository;
@@ -1,24 +1,41 @@
 use std::future::Future;
+use std::io::SeekFrom;
 use std::path::{Path, PathBuf};
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
 
 use crate::core::domain::storage::{
-    entities::{StorageError, YeetBlock},
-    ports::StorageRepository,
+    entities::{Durability, FileMetadata, ManifestEntry, StorageError, YeetBlock},
+    ports::{StorageRepository, StorageSession},
+    services::{PARTIAL_EXTENSION, scan_dir},
 };
 
+/// Bytes buffered before a partial file is written to.
+const WRITE_BUFFER: usize = 256 * 1024;
+/// Bytes read from the existing copy at a time for a delta COPY.
+const COPY_CHUNK: u64 = 64 * 1024;
+
 #[derive(Clone)]
 pub struct FSStorageRepository {
     base_path: String,
+    durability: Durability,
 }
 
 impl FSStorageRepository {
     pub fn new(base_path: String) -> Self {
-        FSStorageRepository { base_path }
+        FSStorageRepository {
+            base_path,
+            durability: Durability::default(),
+        }
+    }
+
+    /// Replace the default `Durability::OnFinalize` policy.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
     }
 
     // helper pour sécuriser le filename (simple)
-    fn sanitize_filename(filename: &str) -> Result<(), StorageError> {
+    pub(crate) fn sanitize_filename(filename: &str) -> Result<(), StorageError> {
         let p = Path::new(filename);
         // refuse les chemins absolus ou qui remontent (..).
         if p.is_absolute() {
@@ -42,7 +59,13 @@ impl FSStorageRepository {
 }
 
 impl StorageRepository for FSStorageRepository {
-    fn open_file(&self, filename: &str) -> impl Future<Output = Result<(), StorageError>> + Send {
+    type Session = FsSession;
+
+    fn open_file(
+        &self,
+        filename: &str,
+        size: Option<u64>,
+    ) -> impl Future<Output = Result<FsSession, StorageError>> + Send + Sync {
         let filename = filename.to_string();
 
         async move {
@@ -51,88 +74,839 @@ impl StorageRepository for FSStorageRepository {
 
             let path = self.file_path_for(&filename);
             // Use a temporary extension during transfer
-            let part_path = path.with_extension("ferrisshare");
+            let part_path = path.with_extension(PARTIAL_EXTENSION);
+            let mut part = PartFile::create(&part_path, self.durability).await?;
+            if let Some(size) = size
+                && let Err(e) = part.reserve(size).await
+            {
+                let _ = tokio::fs::remove_file(&part_path).await;
+                return Err(e);
+            }
 
-            // create parent dirs if needed
-            if let Some(parent) = path.parent() {
-                tokio::fs::create_dir_all(parent)
-                    .await
-                    .map_err(|e| StorageError::Unknown(format!("Failed to create dir: {}", e)))?;
+            Ok(FsSession {
+                path,
+                part_path,
+                part,
+                source: None,
+                metadata: None,
+            })
+        }
+    }
+
+    fn resume_file(
+        &self,
+        filename: &str,
+        size: Option<u64>,
+        offset: u64,
+    ) -> impl Future<Output = Result<FsSession, StorageError>> + Send + Sync {
+        let filename = filename.to_string();
+
+        async move {
+            FSStorageRepository::sanitize_filename(&filename)?;
+
+            let path = self.file_path_for(&filename);
+            let part_path = path.with_extension(PARTIAL_EXTENSION);
+            let mut part = PartFile::reopen(&part_path, self.durability, offset).await?;
+            if let Some(size) = size {
+                part.reserve(size).await?;
             }
 
-            match tokio::fs::File::create(&part_path).await {
-                Ok(_) => Ok(()),
+            Ok(FsSession {
+                path,
+                part_path,
+                part,
+                source: None,
+                metadata: None,
+            })
+        }
+    }
+
+    fn list_files(&self) -> impl Future<Output = Result<Vec<ManifestEntry>, StorageError>> + Send {
+        let base = PathBuf::from(&self.base_path);
+
+        async move {
+            match scan_dir(&base).await {
+                Ok(entries) => Ok(entries),
+                // nothing received yet
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
                 Err(e) => Err(StorageError::Unknown(e.to_string())),
             }
         }
     }
 
-    fn write_block(
+    fn delete_file(
         &self,
         filename: &str,
-        block: &YeetBlock,
-        data: &[u8],
-    ) -> impl Future<Output = Result<(), StorageError>> + Send {
+    ) -> impl Future<Output = Result<(), StorageError>> + Send + Sync {
         let filename = filename.to_string();
-        let data = data.to_vec();
-        let block_index = block.index;
 
         async move {
             // sanitize
             FSStorageRepository::sanitize_filename(&filename)?;
 
-            let path = self.file_path_for(&filename);
-            // write into a .ferrisshare temporary file while transferring
-            let part_path = path.with_extension("ferrisshare");
-
-            // ensure parent dir exists before open
-            if let Some(parent) = path.parent() {
-                tokio::fs::create_dir_all(parent)
-                    .await
-                    .map_err(|e| StorageError::Unknown(format!("Failed to create dir: {}", e)))?;
-            }
-
-            match tokio::fs::OpenOptions::new()
-                .create(true)
-                .truncate(false)
-                .write(true)
-                .open(&part_path)
-                .await
-            {
-                Ok(mut file) => {
-                    let offset = block_index * block.size as u64;
-                    if let Err(e) = file.seek(std::io::SeekFrom::Start(offset)).await {
-                        return Err(StorageError::Unknown(e.to_string()));
-                    }
-                    if let Err(e) = file.write_all(&data).await {
-                        return Err(StorageError::Unknown(e.to_string()));
-                    }
-                    // flush si tu veux (optionnel)
-                    if let Err(e) = file.flush().await {
-                        return Err(StorageError::Unknown(e.to_string()));
-                    }
-                    Ok(())
+            match tokio::fs::remove_file(self.file_path_for(&filename)).await {
+                Ok(_) => Ok(()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    Err(StorageError::FileNotFound)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                    Err(StorageError::PermissionDenied)
                 }
                 Err(e) => Err(StorageError::Unknown(e.to_string())),
             }
         }
     }
 
-    fn finalize(&self, filename: &str) -> impl Future<Output = Result<(), StorageError>> + Send {
-        let base = self.base_path.clone();
+    fn read_range(
+        &self,
+        filename: &str,
+        offset: u64,
+        len: u64,
+    ) -> impl Future<Output = Result<Vec<u8>, StorageError>> + Send {
         let filename = filename.to_string();
 
         async move {
             // sanitize
             FSStorageRepository::sanitize_filename(&filename)?;
 
-            let path = PathBuf::from(&base).join(&filename);
-            // Rename the .ferrisshare temp file to the final filename
-            let part = path.with_extension("ferrisshare");
-            match tokio::fs::rename(&part, &path).await {
-                Ok(_) => Ok(()),
+            let mut file = match tokio::fs::File::open(self.file_path_for(&filename)).await {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(StorageError::FileNotFound);
+                }
+                Err(e) => return Err(StorageError::Unknown(e.to_string())),
+            };
+            file.seek(std::io::SeekFrom::Start(offset))
+                .await
+                .map_err(|e| StorageError::Unknown(e.to_string()))?;
+            let mut data = Vec::new();
+            file.take(len)
+                .read_to_end(&mut data)
+                .await
+                .map_err(|e| StorageError::Unknown(e.to_string()))?;
+            Ok(data)
+        }
+    }
+
+    fn file_size(&self, filename: &str) -> impl Future<Output = Result<u64, StorageError>> + Send {
+        let filename = filename.to_string();
+
+        async move {
+            FSStorageRepository::sanitize_filename(&filename)?;
+
+            match tokio::fs::metadata(self.file_path_for(&filename)).await {
+                Ok(metadata) => Ok(metadata.len()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    Err(StorageError::FileNotFound)
+                }
                 Err(e) => Err(StorageError::Unknown(e.to_string())),
             }
         }
     }
+
+    fn location(&self, filename: &str) -> String {
+        self.file_path_for(filename).display().to_string()
+    }
+}
+
+/// A `.ferrisshare` file being written, kept open with a buffered writer for
+/// the whole transfer instead of being reopened for every block.
+pub(crate) struct PartFile {
+    writer: BufWriter<tokio::fs::File>,
+    // where the next buffered byte lands; writing elsewhere seeks first
+    position: u64,
+    durability: Durability,
+    // blocks written since the file was created, for `Durability::EveryBlocks`
+    blocks: u64,
+    // space was preallocated, so holes must be punched to stay sparse
+    reserved: bool,
+    // adjacent holes not punched yet, merged into one range
+    hole: Option<(u64, u64)>,
+    // end of the furthest hole; the file is extended to it on finish
+    end: u64,
+}
+
+impl PartFile {
+    /// Create (or truncate) `path` and its parent directories.
+    pub(crate) async fn create(path: &Path, durability: Durability) -> Result<Self, StorageError> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| StorageError::Unknown(format!("Failed to create dir: {}", e)))?;
+        }
+        let file = tokio::fs::File::create(path).await.map_err(io_error)?;
+        Ok(PartFile {
+            writer: BufWriter::with_capacity(WRITE_BUFFER, file),
+            position: 0,
+            durability,
+            blocks: 0,
+            reserved: false,
+            hole: None,
+            end: 0,
+        })
+    }
+
+    /// Open the existing `path` to go on writing at `offset`, cutting off
+    /// whatever was written past it.
+    pub(crate) async fn reopen(
+        path: &Path,
+        durability: Durability,
+        offset: u64,
+    ) -> Result<Self, StorageError> {
+        let mut file = match tokio::fs::OpenOptions::new().write(true).open(path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(StorageError::FileNotFound);
+            }
+            Err(e) => return Err(io_error(e)),
+        };
+        let len = file.metadata().await.map_err(io_error)?.len();
+        if len < offset {
+            return Err(StorageError::Unknown(format!(
+                "{} holds {} bytes, not {}",
+                path.display(),
+                len,
+                offset
+            )));
+        }
+        file.set_len(offset).await.map_err(io_error)?;
+        file.seek(SeekFrom::Start(offset)).await.map_err(io_error)?;
+        Ok(PartFile {
+            writer: BufWriter::with_capacity(WRITE_BUFFER, file),
+            position: offset,
+            durability,
+            blocks: 0,
+            reserved: false,
+            hole: None,
+            end: offset,
+        })
+    }
+
+    pub(crate) async fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), StorageError> {
+        if offset != self.position {
+            // Seeking flushes the buffer first.
+            self.writer
+                .seek(SeekFrom::Start(offset))
+                .await
+                .map_err(io_error)?;
+            self.position = offset;
+        }
+        self.writer.write_all(data).await.map_err(io_error)?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    /// Copy `len` bytes at `offset` in `source` to `dst_offset`, for delta
+    /// copies. The range is checked against the source's length before
+    /// anything is read, and the data goes through a bounded buffer.
+    pub(crate) async fn copy_from(
+        &mut self,
+        source: &mut tokio::fs::File,
+        offset: u64,
+        len: u64,
+        dst_offset: u64,
+    ) -> Result<(), StorageError> {
+        let source_len = source.metadata().await.map_err(io_error)?.len();
+        if offset.checked_add(len).is_none_or(|end| end > source_len) {
+            return Err(StorageError::Unknown(format!(
+                "Range {}+{} is past the end of the file",
+                offset, len
+            )));
+        }
+        source
+            .seek(SeekFrom::Start(offset))
+            .await
+            .map_err(io_error)?;
+        let mut buf = vec![0u8; COPY_CHUNK.min(len) as usize];
+        let mut done = 0;
+        while done < len {
+            let n = COPY_CHUNK.min(len - done) as usize;
+            source.read_exact(&mut buf[..n]).await.map_err(io_error)?;
+            self.write_at(dst_offset + done, &buf[..n]).await?;
+            done += n as u64;
+        }
+        Ok(())
+    }
+
+    /// Check that `size` bytes fit on the filesystem and reserve them, so a
+    /// transfer that can't fit is refused before any data is sent.
+    pub(crate) async fn reserve(&mut self, size: u64) -> Result<(), StorageError> {
+        let file = self.std_file().await?;
+        self.reserved = tokio::task::spawn_blocking(move || reserve_space(&file, size))
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))??;
+        Ok(())
+    }
+
+    /// Leave `offset..offset + len` unwritten so it reads back as zeros
+    /// without taking disk space. Preallocated space under it is given back.
+    pub(crate) async fn write_hole(&mut self, offset: u64, len: u64) -> Result<(), StorageError> {
+        if len == 0 {
+            return Ok(());
+        }
+        self.end = self.end.max(offset + len);
+        if !self.reserved {
+            // Nothing is allocated there: seeking past it leaves a hole.
+            return Ok(());
+        }
+        match &mut self.hole {
+            Some((_, end)) if *end == offset => *end = offset + len,
+            _ => {
+                self.punch_pending().await?;
+                self.hole = Some((offset, offset + len));
+            }
+        }
+        Ok(())
+    }
+
+    /// Punch the pending hole, one call for a whole run of zero blocks.
+    async fn punch_pending(&mut self) -> Result<(), StorageError> {
+        let Some((start, end)) = self.hole.take() else {
+            return Ok(());
+        };
+        // Filesystems ignore punches past the end of the file, where the
+        // preallocated space is.
+        self.extend_to(end).await?;
+        let file = self.std_file().await?;
+        tokio::task::spawn_blocking(move || punch_hole(&file, start, end - start))
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))?
+    }
+
+    /// Grow the file to `len` bytes if it is shorter, leaving a hole.
+    async fn extend_to(&mut self, len: u64) -> Result<(), StorageError> {
+        self.flush().await?;
+        let file = self.writer.get_ref();
+        if file.metadata().await.map_err(io_error)?.len() < len {
+            file.set_len(len).await.map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    /// A blocking handle on the same file, for syscalls tokio doesn't wrap.
+    async fn std_file(&self) -> Result<std::fs::File, StorageError> {
+        Ok(self
+            .writer
+            .get_ref()
+            .try_clone()
+            .await
+            .map_err(io_error)?
+            .into_std()
+            .await)
+    }
+
+    /// Write one received block, syncing when the policy says so.
+    pub(crate) async fn write_block(
+        &mut self,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        self.write_at(offset, data).await?;
+        self.blocks += 1;
+        if let Durability::EveryBlocks(every) = self.durability
+            && self.blocks.is_multiple_of(every)
+        {
+            self.sync().await?;
+        }
+        Ok(())
+    }
+
+    /// Hand buffered bytes to the OS.
+    pub(crate) async fn flush(&mut self) -> Result<(), StorageError> {
+        self.writer.flush().await.map_err(io_error)
+    }
+
+    /// Flush, then wait until the data is on disk unless durability is off.
+    pub(crate) async fn sync(&mut self) -> Result<(), StorageError> {
+        self.flush().await?;
+        if self.durability == Durability::None {
+            return Ok(());
+        }
+        self.writer.get_ref().sync_data().await.map_err(io_error)
+    }
+
+    /// Leave the file of an abandoned transfer with what was written so far,
+    /// synced per the policy, and give back the space reserved past it.
+    /// Returns the length of the file.
+    pub(crate) async fn release(&mut self) -> Result<u64, StorageError> {
+        self.punch_pending().await?;
+        self.extend_to(self.end).await?;
+        let file = self.writer.get_ref();
+        let len = file.metadata().await.map_err(io_error)?.len();
+        if self.reserved {
+            // Truncating, even to the same size, frees blocks preallocated past the end.
+            file.set_len(len).await.map_err(io_error)?;
+            self.reserved = false;
+        }
+        self.sync().await?;
+        Ok(len)
+    }
+
+    /// Ready the file to be renamed into place: flushed, and with its data
+    /// and size on disk unless durability is off. The caller syncs the
+    /// directory after the rename, see `sync_dir`.
+    pub(crate) async fn finish(&mut self) -> Result<Durability, StorageError> {
+        self.punch_pending().await?;
+        // Trailing holes were never written, so the file is still short of them.
+        self.extend_to(self.end).await?;
+        if self.durability != Durability::None {
+            self.writer.get_ref().sync_all().await.map_err(io_error)?;
+        }
+        Ok(self.durability)
+    }
+}
+
+/// A full disk is reported as such rather than as an opaque I/O error.
+fn io_error(err: std::io::Error) -> StorageError {
+    match err.kind() {
+        std::io::ErrorKind::StorageFull => StorageError::InsufficientSpace,
+        _ => StorageError::Unknown(err.to_string()),
+    }
+}
+
+/// Check free space for `size` bytes and reserve them where the platform
+/// allows it. Returns whether space was actually reserved.
+#[cfg(unix)]
+fn reserve_space(file: &std::fs::File, size: u64) -> Result<bool, StorageError> {
+    use std::os::fd::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    // SAFETY: statvfs is plain old data, filled in by fstatvfs on success.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `fd` stays open for the duration of the call, `stat` is writable.
+    if unsafe { libc::fstatvfs(fd, &mut stat) } != 0 {
+        return Err(io_error(std::io::Error::last_os_error()));
+    }
+    let available = (stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64);
+    if size > available {
+        return Err(StorageError::InsufficientSpace);
+    }
+
+    // Keep the size at zero so the partial file only ever holds received data.
+    #[cfg(target_os = "linux")]
+    if size > 0 {
+        let len = libc::off_t::try_from(size).map_err(|_| StorageError::InsufficientSpace)?;
+        // SAFETY: `fd` stays open for the duration of the call.
+        if unsafe { libc::fallocate(fd, libc::FALLOC_FL_KEEP_SIZE, 0, len) } != 0 {
+            let err = std::io::Error::last_os_error();
+            // Not every filesystem preallocates; the free space check still applies.
+            if err.raw_os_error() != Some(libc::EOPNOTSUPP) {
+                return Err(io_error(err));
+            }
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+#[cfg(not(unix))]
+fn reserve_space(_file: &std::fs::File, _size: u64) -> Result<bool, StorageError> {
+    Ok(false)
+}
+
+/// Deallocate `len` bytes at `offset`, keeping the file size.
+#[cfg(target_os = "linux")]
+fn punch_hole(file: &std::fs::File, offset: u64, len: u64) -> Result<(), StorageError> {
+    use std::os::fd::AsRawFd;
+
+    let (Ok(offset), Ok(len)) = (libc::off_t::try_from(offset), libc::off_t::try_from(len)) else {
+        return Err(StorageError::Unknown(format!(
+            "Hole {}+{} too large",
+            offset, len
+        )));
+    };
+    let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+    // SAFETY: `file` stays open for the duration of the call.
+    if unsafe { libc::fallocate(file.as_raw_fd(), mode, offset, len) } != 0 {
+        let err = std::io::Error::last_os_error();
+        // The zeros then just stay allocated.
+        if err.raw_os_error() != Some(libc::EOPNOTSUPP) {
+            return Err(io_error(err));
+        }
+    }
+    Ok(())
+}
+
+/// Only Linux preallocates, so there is never anything to punch elsewhere.
+#[cfg(not(target_os = "linux"))]
+fn punch_hole(_file: &std::fs::File, _offset: u64, _len: u64) -> Result<(), StorageError> {
+    Ok(())
+}
+
+/// Give the file at `path` the xattrs, mtime and mode of `metadata`, in that
+/// order since a read-only mode would forbid setting xattrs.
+pub(crate) async fn apply_metadata(
+    path: &Path,
+    metadata: FileMetadata,
+    durability: Durability,
+) -> Result<(), StorageError> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(&path).map_err(io_error)?;
+        for (name, value) in &metadata.xattrs {
+            // Not every filesystem takes every namespace; the rest still applies.
+            if let Err(e) = set_xattr(&path, name, value) {
+                eprintln!("Skipping xattr {} on {}: {}", name, path.display(), e);
+            }
+        }
+        // The mtime comes from the peer; one the clock can't hold is dropped.
+        match std::time::UNIX_EPOCH.checked_add(std::time::Duration::from_secs(metadata.mtime)) {
+            Some(mtime) => file
+                .set_times(std::fs::FileTimes::new().set_modified(mtime))
+                .map_err(io_error)?,
+            None => eprintln!(
+                "Skipping out-of-range mtime {} on {}",
+                metadata.mtime,
+                path.display()
+            ),
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(metadata.mode))
+                .map_err(io_error)?;
+        }
+        if durability != Durability::None {
+            file.sync_all().map_err(io_error)?;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| StorageError::Unknown(e.to_string()))?
+}
+
+#[cfg(target_os = "linux")]
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let (Ok(c_path), Ok(c_name)) = (
+        std::ffi::CString::new(path.as_os_str().as_bytes()),
+        std::ffi::CString::new(name),
+    ) else {
+        return Err(std::io::ErrorKind::InvalidInput.into());
+    };
+    // SAFETY: both strings are NUL-terminated and `value` outlives the call.
+    let set = unsafe {
+        libc::setxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if set != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Extended attributes are only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+fn set_xattr(_path: &Path, _name: &str, _value: &[u8]) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Sync the directory holding `path`, so a rename into it survives a power
+/// loss. A no-op when durability is off.
+pub(crate) async fn sync_dir(path: &Path, durability: Durability) -> Result<(), StorageError> {
+    if durability == Durability::None {
+        return Ok(());
+    }
+    let Some(dir) = path.parent() else {
+        return Ok(());
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    // Directories can't be opened for syncing on Windows, where NTFS journals renames.
+    if cfg!(windows) {
+        return Ok(());
+    }
+    tokio::fs::File::open(dir)
+        .await
+        .map_err(|e| StorageError::Unknown(format!("{}: {}", dir.display(), e)))?
+        .sync_all()
+        .await
+        .map_err(|e| StorageError::Unknown(format!("{}: {}", dir.display(), e)))
+}
+
+/// A file being received into `<name>.ferrisshare`, renamed on finalize.
+pub struct FsSession {
+    path: PathBuf,
+    part_path: PathBuf,
+    part: PartFile,
+    // the existing copy, opened on the first COPY of a delta transfer
+    source: Option<tokio::fs::File>,
+    // applied once the file has its final name
+    metadata: Option<FileMetadata>,
+}
+
+impl StorageSession for FsSession {
+    async fn write_block(
+        &mut self,
+        offset: u64,
+        _block: &YeetBlock,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        self.part.write_block(offset, data).await
+    }
+
+    async fn write_hole(&mut self, offset: u64, len: u64) -> Result<(), StorageError> {
+        self.part.write_hole(offset, len).await
+    }
+
+    async fn copy_range(
+        &mut self,
+        offset: u64,
+        len: u64,
+        dst_offset: u64,
+    ) -> Result<(), StorageError> {
+        // the existing file is the source, the .ferrisshare copy the destination
+        let source = match &mut self.source {
+            Some(source) => source,
+            None => match tokio::fs::File::open(&self.path).await {
+                Ok(file) => self.source.insert(file),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(StorageError::FileNotFound);
+                }
+                Err(e) => return Err(StorageError::Unknown(e.to_string())),
+            },
+        };
+        self.part.copy_from(source, offset, len, dst_offset).await
+    }
+
+    fn set_metadata(&mut self, metadata: FileMetadata) {
+        self.metadata = Some(metadata);
+    }
+
+    async fn sync(&mut self) -> Result<(), StorageError> {
+        self.part.sync().await
+    }
+
+    async fn finalize(mut self) -> Result<(), StorageError> {
+        let durability = self.part.finish().await?;
+        drop(self.source);
+        // Rename the .ferrisshare temp file to the final filename
+        if let Err(e) = tokio::fs::rename(&self.part_path, &self.path).await {
+            return Err(StorageError::Unknown(e.to_string()));
+        }
+        if let Some(metadata) = self.metadata
+            && let Err(e) = apply_metadata(&self.path, metadata, durability).await
+        {
+            // The content is in place; only its attributes are missing.
+            eprintln!(
+                "Failed to apply metadata to {}: {}",
+                self.path.display(),
+                String::from(e)
+            );
+        }
+        sync_dir(&self.path, durability).await
+    }
+
+    /// A partial file with nothing in it is removed rather than kept.
+    async fn abandon(mut self) -> Result<(), StorageError> {
+        if self.part.release().await? == 0 {
+            drop(self.part);
+            tokio::fs::remove_file(&self.part_path)
+                .await
+                .map_err(io_error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A fresh directory under the system temp dir, removed on drop.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("ferrisshare-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Scratch(path)
+        }
+
+        fn repository(&self) -> FSStorageRepository {
+            FSStorageRepository::new(self.0.to_string_lossy().into_owned())
+                .with_durability(Durability::None)
+        }
+    }
+
+    /// Bytes the filesystem actually allocated for `path`.
+    #[cfg(target_os = "linux")]
+    fn allocated(path: &Path) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata(path).unwrap().blocks() * 512
+    }
+
+    const BLOCK: u64 = 64 * 1024;
+
+    fn block(byte: u8) -> (YeetBlock, Vec<u8>) {
+        (
+            YeetBlock::new(0, BLOCK as u32, 0),
+            vec![byte; BLOCK as usize],
+        )
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn an_mtime_out_of_range_is_skipped() {
+        let dir = Scratch::new("mtime");
+        let path = dir.0.join("a.bin");
+        std::fs::write(&path, b"data").unwrap();
+        let before = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        let metadata = FileMetadata {
+            mode: 0o600,
+            mtime: u64::MAX,
+            xattrs: Vec::new(),
+        };
+        apply_metadata(&path, metadata, Durability::None)
+            .await
+            .unwrap();
+
+        let after = std::fs::metadata(&path).unwrap();
+        assert_eq!(after.modified().unwrap(), before);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(after.permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn announced_sizes_are_reserved_without_growing_the_file() {
+        let dir = Scratch::new("reserve");
+        let repository = dir.repository();
+        let session = repository
+            .open_file("a.bin", Some(4 * BLOCK))
+            .await
+            .unwrap();
+        let part_path = dir.0.join("a.ferrisshare");
+        assert_eq!(std::fs::metadata(&part_path).unwrap().len(), 0);
+        #[cfg(target_os = "linux")]
+        if session.part.reserved {
+            assert!(allocated(&part_path) >= 4 * BLOCK);
+        }
+
+        assert!(matches!(
+            repository.open_file("b.bin", Some(u64::MAX / 2)).await,
+            Err(StorageError::InsufficientSpace)
+        ));
+        assert!(!dir.0.join("b.ferrisshare").exists());
+
+        session.abandon().await.unwrap();
+        // Nothing was written, so nothing is kept.
+        assert!(!part_path.exists());
+    }
+
+    #[tokio::test]
+    async fn holes_read_back_as_zeros_and_give_back_reserved_space() {
+        let dir = Scratch::new("holes");
+        let repository = dir.repository();
+        let mut session = repository
+            .open_file("a.bin", Some(4 * BLOCK))
+            .await
+            .unwrap();
+        let (yeet, data) = block(7);
+        session.write_block(0, &yeet, &data).await.unwrap();
+        session.write_hole(BLOCK, BLOCK).await.unwrap();
+        session.write_hole(2 * BLOCK, BLOCK).await.unwrap();
+        session.write_block(3 * BLOCK, &yeet, &data).await.unwrap();
+        #[cfg(target_os = "linux")]
+        let reserved = session.part.reserved;
+        session.finalize().await.unwrap();
+
+        let path = dir.0.join("a.bin");
+        let written = std::fs::read(&path).unwrap();
+        assert_eq!(written.len() as u64, 4 * BLOCK);
+        assert_eq!(&written[..BLOCK as usize], data.as_slice());
+        assert!(
+            written[BLOCK as usize..3 * BLOCK as usize]
+                .iter()
+                .all(|b| *b == 0)
+        );
+        assert_eq!(&written[3 * BLOCK as usize..], data.as_slice());
+        #[cfg(target_os = "linux")]
+        if reserved {
+            assert!(allocated(&path) < 4 * BLOCK);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_trailing_hole_still_counts_towards_the_size() {
+        let dir = Scratch::new("trailing-hole");
+        let repository = dir.repository();
+        let mut session = repository.open_file("a.bin", None).await.unwrap();
+        let (yeet, data) = block(1);
+        session.write_block(0, &yeet, &data).await.unwrap();
+        session.write_hole(BLOCK, BLOCK).await.unwrap();
+        session.finalize().await.unwrap();
+
+        let written = std::fs::read(dir.0.join("a.bin")).unwrap();
+        assert_eq!(written.len() as u64, 2 * BLOCK);
+        assert!(written[BLOCK as usize..].iter().all(|b| *b == 0));
+    }
+
+    #[tokio::test]
+    async fn resuming_keeps_only_the_first_offset_bytes() {
+        let dir = Scratch::new("resume");
+        let repository = dir.repository();
+        let mut session = repository
+            .open_file("a.bin", Some(3 * BLOCK))
+            .await
+            .unwrap();
+        let (yeet, first) = block(1);
+        session.write_block(0, &yeet, &first).await.unwrap();
+        session
+            .write_block(BLOCK, &yeet, &block(2).1)
+            .await
+            .unwrap();
+        session.abandon().await.unwrap();
+        // The reservation past what was written is given back.
+        let part_path = dir.0.join("a.ferrisshare");
+        assert_eq!(std::fs::metadata(&part_path).unwrap().len(), 2 * BLOCK);
+
+        assert!(
+            repository
+                .resume_file("a.bin", Some(3 * BLOCK), 3 * BLOCK)
+                .await
+                .is_err()
+        );
+        assert!(matches!(
+            repository.resume_file("b.bin", None, 0).await,
+            Err(StorageError::FileNotFound)
+        ));
+
+        let mut session = repository
+            .resume_file("a.bin", Some(3 * BLOCK), BLOCK)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::metadata(&part_path).unwrap().len(), BLOCK);
+        let (_, third) = block(3);
+        session.write_block(BLOCK, &yeet, &third).await.unwrap();
+        session.write_block(2 * BLOCK, &yeet, &third).await.unwrap();
+        session.finalize().await.unwrap();
+
+        let written = std::fs::read(dir.0.join("a.bin")).unwrap();
+        assert_eq!(written.len() as u64, 3 * BLOCK);
+        assert_eq!(&written[..BLOCK as usize], first.as_slice());
+        assert!(written[BLOCK as usize..].iter().all(|b| *b == 3));
+    }
 }
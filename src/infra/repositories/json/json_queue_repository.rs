@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::core::domain::queue::{
+    entities::{QueueError, QueueJob},
+    ports::QueueRepository,
+};
+
+/// Keeps the queue as a JSON array in a single file. Writes go to a temporary
+/// file first and are renamed into place, so a crash never leaves half a journal.
+/// Updates hold an exclusive lock on a `.lock` file next to it for the whole
+/// read-modify-write, so a worker and `queue add` don't drop each other's jobs.
+#[derive(Debug, Clone)]
+pub struct JsonQueueRepository {
+    path: PathBuf,
+}
+
+impl JsonQueueRepository {
+    pub fn new(path: PathBuf) -> Self {
+        JsonQueueRepository { path }
+    }
+}
+
+fn parse(path: &Path, bytes: &[u8]) -> Result<Vec<QueueJob>, QueueError> {
+    serde_json::from_slice(bytes)
+        .map_err(|e| QueueError::CorruptJournal(format!("{}: {}", path.display(), e)))
+}
+
+fn read_jobs(path: &Path) -> Result<Vec<QueueJob>, QueueError> {
+    match std::fs::read(path) {
+        Ok(bytes) => parse(path, &bytes),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(QueueError::Io(e)),
+    }
+}
+
+fn write_jobs(path: &Path, jobs: &[QueueJob]) -> Result<(), QueueError> {
+    let bytes =
+        serde_json::to_vec_pretty(jobs).map_err(|e| QueueError::CorruptJournal(e.to_string()))?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+impl QueueRepository for JsonQueueRepository {
+    async fn load(&self) -> Result<Vec<QueueJob>, QueueError> {
+        // The journal is only ever replaced whole, so reading needs no lock.
+        match tokio::fs::read(&self.path).await {
+            Ok(bytes) => parse(&self.path, &bytes),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(QueueError::Io(e)),
+        }
+    }
+
+    async fn update<T, F>(&self, change: F) -> Result<T, QueueError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Vec<QueueJob>) -> T + Send + 'static,
+    {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let lock = File::options()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(path.with_extension("lock"))?;
+            // Released when `lock` is dropped.
+            lock.lock()?;
+            let mut jobs = read_jobs(&path)?;
+            let result = change(&mut jobs);
+            write_jobs(&path, &jobs)?;
+            Ok(result)
+        })
+        .await
+        .map_err(|e| QueueError::Io(std::io::Error::other(e)))?
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::core::domain::storage::{
+    entities::{FileMetadata, ManifestEntry, StorageError, YeetBlock},
+    ports::{StorageRepository, StorageSession},
+};
+use crate::infra::repositories::fs::fs_storage_repository::FSStorageRepository;
+
+#[derive(Default)]
+struct Files {
+    // transfers in progress, the counterpart of `.ferrisshare` files
+    partial: HashMap<String, Vec<u8>>,
+    // finalized content and its mtime (unix seconds)
+    finalized: HashMap<String, (Vec<u8>, u64)>,
+}
+
+/// Keeps every file in memory, for tests and nodes that don't need to keep
+/// what they receive across restarts.
+///
+/// Behaves like `FSStorageRepository`: same filename rules, blocks written at
+/// their offset into a partial copy that only becomes visible on `finalize`.
+/// Clones share the same files, so a test can keep one to inspect what a
+/// `CommandServiceImpl` stored through another.
+#[derive(Clone, Default)]
+pub struct InMemoryStorageRepository {
+    files: Arc<Mutex<Files>>,
+}
+
+impl InMemoryStorageRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Content of the finalized `filename`.
+    pub fn file(&self, filename: &str) -> Option<Vec<u8>> {
+        self.lock()
+            .finalized
+            .get(filename)
+            .map(|(data, _)| data.clone())
+    }
+
+    /// Bytes received so far for `filename`, before it is finalized.
+    pub fn partial(&self, filename: &str) -> Option<Vec<u8>> {
+        self.lock().partial.get(filename).cloned()
+    }
+
+    /// Names of all finalized files, sorted.
+    pub fn filenames(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.lock().finalized.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Store `data` as a finalized file, e.g. an existing copy for a delta transfer.
+    pub fn insert_file(&self, filename: &str, data: &[u8]) {
+        self.lock()
+            .finalized
+            .insert(filename.to_string(), (data.to_vec(), unix_now()));
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Files> {
+        // A panic while holding the lock can't leave the maps half-updated.
+        self.files.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl StorageRepository for InMemoryStorageRepository {
+    type Session = InMemorySession;
+
+    async fn open_file(
+        &self,
+        filename: &str,
+        _size: Option<u64>,
+    ) -> Result<InMemorySession, StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        self.lock().partial.insert(filename.to_string(), Vec::new());
+        Ok(InMemorySession {
+            repository: self.clone(),
+            filename: filename.to_string(),
+        })
+    }
+
+    async fn resume_file(
+        &self,
+        filename: &str,
+        _size: Option<u64>,
+        offset: u64,
+    ) -> Result<InMemorySession, StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        let mut files = self.lock();
+        let partial = files
+            .partial
+            .get_mut(filename)
+            .ok_or(StorageError::FileNotFound)?;
+        if (partial.len() as u64) < offset {
+            return Err(StorageError::Unknown(format!(
+                "Partial copy of {} holds {} bytes, not {}",
+                filename,
+                partial.len(),
+                offset
+            )));
+        }
+        partial.truncate(offset as usize);
+        Ok(InMemorySession {
+            repository: self.clone(),
+            filename: filename.to_string(),
+        })
+    }
+
+    async fn list_files(&self) -> Result<Vec<ManifestEntry>, StorageError> {
+        let files = self.lock();
+        let mut entries: Vec<ManifestEntry> = files
+            .finalized
+            .iter()
+            .map(|(path, (data, mtime))| ManifestEntry {
+                path: path.clone(),
+                size: data.len() as u64,
+                mtime: *mtime,
+                sha256: hex::encode(Sha256::digest(data)),
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    async fn delete_file(&self, filename: &str) -> Result<(), StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        self.lock()
+            .finalized
+            .remove(filename)
+            .map(|_| ())
+            .ok_or(StorageError::FileNotFound)
+    }
+
+    async fn read_range(
+        &self,
+        filename: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        let files = self.lock();
+        let (data, _) = files
+            .finalized
+            .get(filename)
+            .ok_or(StorageError::FileNotFound)?;
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(len as usize).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
+    async fn file_size(&self, filename: &str) -> Result<u64, StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        self.lock()
+            .finalized
+            .get(filename)
+            .map(|(data, _)| data.len() as u64)
+            .ok_or(StorageError::FileNotFound)
+    }
+
+    fn location(&self, filename: &str) -> String {
+        format!("memory:{}", filename)
+    }
+}
+
+/// A transfer written straight into the shared partial copy, so `partial`
+/// shows every block as soon as it is written.
+pub struct InMemorySession {
+    repository: InMemoryStorageRepository,
+    filename: String,
+}
+
+impl StorageSession for InMemorySession {
+    async fn write_block(
+        &mut self,
+        offset: u64,
+        _block: &YeetBlock,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        let mut files = self.repository.lock();
+        let partial = files.partial.entry(self.filename.clone()).or_default();
+        write_at(partial, offset, data);
+        Ok(())
+    }
+
+    async fn write_hole(&mut self, offset: u64, len: u64) -> Result<(), StorageError> {
+        let mut files = self.repository.lock();
+        let partial = files.partial.entry(self.filename.clone()).or_default();
+        let start = offset as usize;
+        let end = start + len as usize;
+        if partial.len() < end {
+            partial.resize(end, 0);
+        }
+        partial[start..end].fill(0);
+        Ok(())
+    }
+
+    async fn copy_range(
+        &mut self,
+        offset: u64,
+        len: u64,
+        dst_offset: u64,
+    ) -> Result<(), StorageError> {
+        let data = self
+            .repository
+            .read_range(&self.filename, offset, len)
+            .await?;
+        if data.len() as u64 != len {
+            return Err(StorageError::Unknown(format!(
+                "Range {}+{} is past the end of {}",
+                offset, len, self.filename
+            )));
+        }
+        let mut files = self.repository.lock();
+        let partial = files.partial.entry(self.filename.clone()).or_default();
+        write_at(partial, dst_offset, &data);
+        Ok(())
+    }
+
+    /// Files in memory only have content.
+    fn set_metadata(&mut self, _metadata: FileMetadata) {}
+
+    /// Nothing is buffered.
+    async fn sync(&mut self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    async fn finalize(self) -> Result<(), StorageError> {
+        let mut files = self.repository.lock();
+        let data = files
+            .partial
+            .remove(&self.filename)
+            .ok_or(StorageError::FileNotFound)?;
+        files.finalized.insert(self.filename, (data, unix_now()));
+        Ok(())
+    }
+
+    /// The partial copy stays in `partial`, as it would on disk.
+    async fn abandon(self) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+/// Write `data` at `offset`, zero-filling any gap like a sparse file would read.
+fn write_at(buf: &mut Vec<u8>, offset: u64, data: &[u8]) {
+    let start = offset as usize;
+    let end = start + data.len();
+    if buf.len() < end {
+        buf.resize(end, 0);
+    }
+    buf[start..end].copy_from_slice(data);
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1 @@
+pub mod in_memory_storage_repository;
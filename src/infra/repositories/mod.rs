@@ -1 +1,8 @@
+pub mod cas;
 pub mod fs;
+pub mod json;
+pub mod memory;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod sqlite;
+pub mod stdout;
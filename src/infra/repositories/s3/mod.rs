@@ -0,0 +1,2 @@
+pub mod s3_storage_repository;
+pub mod sigv4;
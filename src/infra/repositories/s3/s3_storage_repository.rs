@@ -0,0 +1,738 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::core::domain::storage::{
+    entities::{FileMetadata, ManifestEntry, StorageError, YeetBlock},
+    ports::{StorageRepository, StorageSession},
+    services::ZEROS,
+};
+use crate::infra::repositories::fs::fs_storage_repository::FSStorageRepository;
+use crate::infra::repositories::s3::sigv4::{
+    CanonicalRequest, Credentials, parse_timestamp, sign, uri_encode,
+};
+
+/// Size of the multipart parts uploaded while receiving. S3 requires at least
+/// 5 MiB for every part but the last, so blocks are buffered up to this.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Where hashes of uploaded objects are kept, under the prefix: S3 can't
+/// attach metadata to a multipart upload once its content is known.
+const HASH_DIR: &str = ".ferrisshare/sha256/";
+
+/// Bytes fetched per ranged GET. Delta transfers read a stored file one block
+/// at a time; one request per block would be far too slow.
+const READ_WINDOW: u64 = 8 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct S3Settings {
+    /// e.g. `https://s3.eu-west-1.amazonaws.com` or `http://127.0.0.1:9000`.
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    /// Prepended to every filename to build the object key.
+    pub prefix: String,
+    pub credentials: Credentials,
+}
+
+/// A transfer being uploaded.
+struct Upload {
+    // None until the first part is due; small files go out with one PUT
+    upload_id: Option<String>,
+    buffer: Vec<u8>,
+    // bytes accepted so far; the next block must start here
+    written: u64,
+    // (part number, ETag)
+    parts: Vec<(u32, String)>,
+    hasher: Sha256,
+}
+
+impl Upload {
+    fn new() -> Self {
+        Upload {
+            upload_id: None,
+            buffer: Vec::new(),
+            written: 0,
+            parts: Vec::new(),
+            hasher: Sha256::new(),
+        }
+    }
+}
+
+/// The last range fetched from an object, serving reads that fall inside it.
+struct ReadWindow {
+    filename: String,
+    start: u64,
+    data: Vec<u8>,
+    // the object ends at the end of `data`
+    eof: bool,
+}
+
+impl ReadWindow {
+    fn slice(&self, filename: &str, offset: u64, len: u64) -> Option<Vec<u8>> {
+        if self.filename != filename || offset < self.start {
+            return None;
+        }
+        let from = (offset - self.start) as usize;
+        let to = from.saturating_add(len as usize);
+        if to <= self.data.len() {
+            Some(self.data[from..to].to_vec())
+        } else if self.eof && from <= self.data.len() {
+            Some(self.data[from..].to_vec())
+        } else {
+            None
+        }
+    }
+}
+
+/// What is kept of an S3 reply.
+struct S3Response {
+    status: u16,
+    etag: Option<String>,
+    content_length: Option<u64>,
+    body: Vec<u8>,
+}
+
+impl S3Response {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Stores received files as objects in an S3-compatible bucket.
+///
+/// Blocks become multipart-upload parts and `finalize` completes the upload,
+/// so nothing touches the local disk. Like stdout, uploads can't seek: blocks
+/// must arrive in order. Objects are addressed path-style, which MinIO and
+/// most S3-compatible stores require.
+#[derive(Clone)]
+pub struct S3StorageRepository {
+    settings: Arc<S3Settings>,
+    client: reqwest::Client,
+    read_window: Arc<Mutex<Option<ReadWindow>>>,
+}
+
+impl S3StorageRepository {
+    pub fn new(settings: S3Settings) -> Self {
+        S3StorageRepository {
+            settings: Arc::new(settings),
+            client: reqwest::Client::new(),
+            read_window: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn key_for(&self, filename: &str) -> String {
+        format!("{}{}", self.settings.prefix, filename)
+    }
+
+    fn hash_key_for(&self, filename: &str) -> String {
+        format!("{}{}{}", self.settings.prefix, HASH_DIR, filename)
+    }
+
+    /// Drop cached content of `filename` once the object changed.
+    async fn forget(&self, filename: &str) {
+        let mut window = self.read_window.lock().await;
+        if window.as_ref().is_some_and(|w| w.filename == filename) {
+            *window = None;
+        }
+    }
+
+    /// Send one signed request. The HTTP exchange runs on its own task so the
+    /// storage futures stay `Sync`, which reqwest's aren't.
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+        body: Vec<u8>,
+    ) -> Result<S3Response, StorageError> {
+        let endpoint = self.settings.endpoint.trim_end_matches('/');
+        let path = format!(
+            "/{}/{}",
+            uri_encode(&self.settings.bucket, false),
+            uri_encode(key, true)
+        );
+        let mut pairs: Vec<(String, String)> = query
+            .iter()
+            .map(|(k, v)| (uri_encode(k, false), uri_encode(v, false)))
+            .collect();
+        pairs.sort();
+        let query = pairs
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+        let url = if query.is_empty() {
+            format!("{}{}", endpoint, path)
+        } else {
+            format!("{}{}?{}", endpoint, path, query)
+        };
+        let url = reqwest::Url::parse(&url)
+            .map_err(|e| StorageError::Unknown(format!("Invalid S3 URL {}: {}", url, e)))?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(StorageError::Unknown("S3 endpoint has no host".into())),
+        };
+
+        let payload_sha256 = hex::encode(Sha256::digest(&body));
+        let signature = sign(
+            &CanonicalRequest {
+                method: method.as_str(),
+                host: &host,
+                path: &path,
+                query: &query,
+                payload_sha256: &payload_sha256,
+            },
+            &self.settings.region,
+            &self.settings.credentials,
+            SystemTime::now(),
+        );
+
+        let mut request = self
+            .client
+            .request(method.clone(), url)
+            .header("x-amz-date", signature.amz_date)
+            .header("x-amz-content-sha256", payload_sha256)
+            .header("authorization", signature.authorization)
+            .body(body);
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+
+        let outcome = tokio::spawn(async move {
+            let response = request.send().await?;
+            let status = response.status().as_u16();
+            let etag = response
+                .headers()
+                .get("etag")
+                .and_then(|v| v.to_str().ok())
+                .map(String::from);
+            // Read from the header: HEAD responses carry the object's length but no body.
+            let content_length = response
+                .headers()
+                .get("content-length")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok());
+            let body = response.bytes().await?.to_vec();
+            Ok::<_, reqwest::Error>(S3Response {
+                status,
+                etag,
+                content_length,
+                body,
+            })
+        })
+        .await
+        .map_err(|e| StorageError::Unknown(e.to_string()))?;
+
+        outcome.map_err(|e| StorageError::Unknown(format!("S3 {} {}: {}", method, key, e)))
+    }
+
+    /// Map anything but a 2xx reply to a `StorageError`.
+    fn check(method: &str, key: &str, response: S3Response) -> Result<S3Response, StorageError> {
+        match response.status {
+            200..=299 => Ok(response),
+            403 => Err(StorageError::PermissionDenied),
+            404 => Err(StorageError::FileNotFound),
+            status => Err(StorageError::Unknown(format!(
+                "S3 {} {} failed with {}: {}",
+                method,
+                key,
+                status,
+                xml_value(&response.text(), "Code").unwrap_or_default()
+            ))),
+        }
+    }
+
+    async fn put_object(&self, key: &str, body: Vec<u8>) -> Result<(), StorageError> {
+        let response = self
+            .request(reqwest::Method::PUT, key, &[], &[], body)
+            .await?;
+        Self::check("PUT", key, response).map(|_| ())
+    }
+
+    async fn create_multipart(&self, key: &str) -> Result<String, StorageError> {
+        let response = self
+            .request(
+                reqwest::Method::POST,
+                key,
+                &[("uploads", "")],
+                &[],
+                Vec::new(),
+            )
+            .await?;
+        let response = Self::check("CreateMultipartUpload", key, response)?;
+        xml_value(&response.text(), "UploadId")
+            .ok_or_else(|| StorageError::Unknown(format!("No UploadId for {}", key)))
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        body: Vec<u8>,
+    ) -> Result<String, StorageError> {
+        let part_number = part_number.to_string();
+        let query = [
+            ("partNumber", part_number.as_str()),
+            ("uploadId", upload_id),
+        ];
+        let response = self
+            .request(reqwest::Method::PUT, key, &query, &[], body)
+            .await?;
+        let response = Self::check("UploadPart", key, response)?;
+        response
+            .etag
+            .ok_or_else(|| StorageError::Unknown(format!("No ETag for part of {}", key)))
+    }
+
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[(u32, String)],
+    ) -> Result<(), StorageError> {
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (number, etag) in parts {
+            body += &format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                number, etag
+            );
+        }
+        body += "</CompleteMultipartUpload>";
+        let response = self
+            .request(
+                reqwest::Method::POST,
+                key,
+                &[("uploadId", upload_id)],
+                &[],
+                body.into_bytes(),
+            )
+            .await?;
+        let response = Self::check("CompleteMultipartUpload", key, response)?;
+        // S3 may report a failed completion in a 200 reply.
+        let text = response.text();
+        if text.contains("<Error>") {
+            return Err(StorageError::Unknown(format!(
+                "S3 CompleteMultipartUpload {} failed: {}",
+                key,
+                xml_value(&text, "Code").unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+
+    async fn abort_multipart(&self, key: &str, upload_id: &str) {
+        let result = self
+            .request(
+                reqwest::Method::DELETE,
+                key,
+                &[("uploadId", upload_id)],
+                &[],
+                Vec::new(),
+            )
+            .await;
+        if let Err(e) = result.and_then(|r| Self::check("AbortMultipartUpload", key, r)) {
+            eprintln!("Failed to abort upload of {}: {:?}", key, e);
+        }
+    }
+
+    /// Accept `data` at `offset` of `filename`, uploading a part once enough is buffered.
+    async fn append(
+        &self,
+        filename: &str,
+        upload: &mut Upload,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        if offset != upload.written {
+            return Err(StorageError::Unknown(format!(
+                "S3 uploads need blocks in order: expected offset {}, got {}",
+                upload.written, offset
+            )));
+        }
+        upload.hasher.update(data);
+        upload.buffer.extend_from_slice(data);
+        upload.written += data.len() as u64;
+
+        if upload.buffer.len() >= PART_SIZE {
+            let key = self.key_for(filename);
+            let upload_id = match &upload.upload_id {
+                Some(upload_id) => upload_id.clone(),
+                None => {
+                    let upload_id = self.create_multipart(&key).await?;
+                    upload.upload_id = Some(upload_id.clone());
+                    upload_id
+                }
+            };
+            let part_number = upload.parts.len() as u32 + 1;
+            let part = std::mem::take(&mut upload.buffer);
+            let etag = self
+                .upload_part(&key, &upload_id, part_number, part)
+                .await?;
+            upload.parts.push((part_number, etag));
+        }
+        Ok(())
+    }
+
+    async fn finish(&self, filename: &str, upload: Upload) -> Result<(), StorageError> {
+        let key = self.key_for(filename);
+        let sha256 = hex::encode(upload.hasher.finalize());
+        match upload.upload_id {
+            None => self.put_object(&key, upload.buffer).await?,
+            Some(upload_id) => {
+                let mut parts = upload.parts;
+                let result = async {
+                    if !upload.buffer.is_empty() {
+                        let part_number = parts.len() as u32 + 1;
+                        let etag = self
+                            .upload_part(&key, &upload_id, part_number, upload.buffer)
+                            .await?;
+                        parts.push((part_number, etag));
+                    }
+                    self.complete_multipart(&key, &upload_id, &parts).await
+                }
+                .await;
+                if let Err(e) = result {
+                    self.abort_multipart(&key, &upload_id).await;
+                    return Err(e);
+                }
+            }
+        }
+        self.forget(filename).await;
+        self.put_object(&self.hash_key_for(filename), sha256.into_bytes())
+            .await
+    }
+
+    /// Keys and listing data of every object under the prefix, following pagination.
+    async fn list_objects(&self) -> Result<Vec<(String, u64, u64)>, StorageError> {
+        let mut objects = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("list-type", "2"),
+                ("prefix", self.settings.prefix.as_str()),
+            ];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            // The bucket itself is the resource: an empty key.
+            let response = self
+                .request(reqwest::Method::GET, "", &query, &[], Vec::new())
+                .await?;
+            let text = Self::check("ListObjectsV2", "", response)?.text();
+
+            for contents in xml_blocks(&text, "Contents") {
+                let (Some(key), Some(size)) = (
+                    xml_value(contents, "Key"),
+                    xml_value(contents, "Size").and_then(|s| s.parse().ok()),
+                ) else {
+                    continue;
+                };
+                let mtime = xml_value(contents, "LastModified")
+                    .and_then(|t| parse_timestamp(&t))
+                    .unwrap_or(0);
+                objects.push((key, size, mtime));
+            }
+
+            match xml_value(&text, "NextContinuationToken") {
+                Some(next) if xml_value(&text, "IsTruncated").as_deref() == Some("true") => {
+                    token = Some(next)
+                }
+                _ => break,
+            }
+        }
+        Ok(objects)
+    }
+}
+
+impl StorageRepository for S3StorageRepository {
+    type Session = S3Session;
+
+    async fn open_file(
+        &self,
+        filename: &str,
+        _size: Option<u64>,
+    ) -> Result<S3Session, StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        Ok(S3Session {
+            repository: self.clone(),
+            filename: filename.to_string(),
+            upload: Some(Upload::new()),
+        })
+    }
+
+    /// The parts of an interrupted upload were aborted with it.
+    async fn resume_file(
+        &self,
+        _filename: &str,
+        _size: Option<u64>,
+        _offset: u64,
+    ) -> Result<S3Session, StorageError> {
+        Err(StorageError::Unknown(
+            "S3 uploads can't go on from an earlier transfer".to_string(),
+        ))
+    }
+
+    async fn list_files(&self) -> Result<Vec<ManifestEntry>, StorageError> {
+        let hash_dir = format!("{}{}", self.settings.prefix, HASH_DIR);
+        let mut entries = Vec::new();
+        for (key, size, mtime) in self.list_objects().await? {
+            if key.starts_with(&hash_dir) {
+                continue;
+            }
+            let Some(path) = key.strip_prefix(&self.settings.prefix) else {
+                continue;
+            };
+            // Objects not uploaded by ferrisshare have no recorded hash; an
+            // empty one makes sync send them again.
+            let sha256 = match self
+                .request(
+                    reqwest::Method::GET,
+                    &self.hash_key_for(path),
+                    &[],
+                    &[],
+                    Vec::new(),
+                )
+                .await?
+            {
+                response if response.status == 200 => response.text().trim().to_string(),
+                _ => String::new(),
+            };
+            entries.push(ManifestEntry {
+                path: path.to_string(),
+                size,
+                mtime,
+                sha256,
+            });
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+
+    async fn delete_file(&self, filename: &str) -> Result<(), StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        let key = self.key_for(filename);
+        // DELETE succeeds on missing keys; HEAD tells whether there was a file.
+        let response = self
+            .request(reqwest::Method::HEAD, &key, &[], &[], Vec::new())
+            .await?;
+        Self::check("HEAD", &key, response)?;
+
+        let response = self
+            .request(reqwest::Method::DELETE, &key, &[], &[], Vec::new())
+            .await?;
+        Self::check("DELETE", &key, response)?;
+        self.forget(filename).await;
+        let hash_key = self.hash_key_for(filename);
+        let response = self
+            .request(reqwest::Method::DELETE, &hash_key, &[], &[], Vec::new())
+            .await?;
+        Self::check("DELETE", &hash_key, response).map(|_| ())
+    }
+
+    async fn read_range(
+        &self,
+        filename: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let mut window = self.read_window.lock().await;
+        if let Some(data) = window.as_ref().and_then(|w| w.slice(filename, offset, len)) {
+            return Ok(data);
+        }
+
+        let key = self.key_for(filename);
+        let fetch = len.max(READ_WINDOW);
+        let last = offset.checked_add(fetch - 1).ok_or_else(|| {
+            StorageError::Unknown(format!(
+                "Range {}+{} of {} is out of bounds",
+                offset, len, filename
+            ))
+        })?;
+        let range = format!("bytes={}-{}", offset, last);
+        let response = self
+            .request(
+                reqwest::Method::GET,
+                &key,
+                &[],
+                &[("range", range)],
+                Vec::new(),
+            )
+            .await?;
+        let data = match response.status {
+            // Range starts past the end of the object.
+            416 => Vec::new(),
+            _ => Self::check("GET", &key, response)?.body,
+        };
+        let fetched = ReadWindow {
+            filename: filename.to_string(),
+            start: offset,
+            eof: (data.len() as u64) < fetch,
+            data,
+        };
+        let data = fetched.slice(filename, offset, len).unwrap_or_default();
+        *window = Some(fetched);
+        Ok(data)
+    }
+
+    async fn file_size(&self, filename: &str) -> Result<u64, StorageError> {
+        FSStorageRepository::sanitize_filename(filename)?;
+        let key = self.key_for(filename);
+        let response = self
+            .request(reqwest::Method::HEAD, &key, &[], &[], Vec::new())
+            .await?;
+        Self::check("HEAD", &key, response)?
+            .content_length
+            .ok_or_else(|| StorageError::Unknown(format!("S3 HEAD {} gave no length", key)))
+    }
+
+    fn location(&self, filename: &str) -> String {
+        format!("s3://{}/{}", self.settings.bucket, self.key_for(filename))
+    }
+}
+
+/// One object being uploaded. Dropping a session that was not finalized
+/// aborts its multipart upload, so the bucket keeps no orphaned parts.
+pub struct S3Session {
+    repository: S3StorageRepository,
+    filename: String,
+    // taken by `finalize`
+    upload: Option<Upload>,
+}
+
+impl S3Session {
+    fn upload(&mut self) -> Result<&mut Upload, StorageError> {
+        self.upload
+            .as_mut()
+            .ok_or_else(|| StorageError::Unknown(format!("{} is already finalized", self.filename)))
+    }
+}
+
+impl StorageSession for S3Session {
+    async fn write_block(
+        &mut self,
+        offset: u64,
+        _block: &YeetBlock,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        let repository = self.repository.clone();
+        let filename = self.filename.clone();
+        repository
+            .append(&filename, self.upload()?, offset, data)
+            .await
+    }
+
+    /// Objects can't be sparse: the zeros are uploaded.
+    async fn write_hole(&mut self, offset: u64, len: u64) -> Result<(), StorageError> {
+        let repository = self.repository.clone();
+        let filename = self.filename.clone();
+        let mut done = 0;
+        while done < len {
+            let n = (len - done).min(ZEROS.len() as u64);
+            repository
+                .append(
+                    &filename,
+                    self.upload()?,
+                    offset + done,
+                    &ZEROS[..n as usize],
+                )
+                .await?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    async fn copy_range(
+        &mut self,
+        offset: u64,
+        len: u64,
+        dst_offset: u64,
+    ) -> Result<(), StorageError> {
+        let repository = self.repository.clone();
+        let filename = self.filename.clone();
+        let data = repository.read_range(&filename, offset, len).await?;
+        if data.len() as u64 != len {
+            return Err(StorageError::Unknown(format!(
+                "Range {}+{} is past the end of {}",
+                offset, len, filename
+            )));
+        }
+        repository
+            .append(&filename, self.upload()?, dst_offset, &data)
+            .await
+    }
+
+    /// Objects have no permissions, and their time is the upload's.
+    fn set_metadata(&mut self, _metadata: FileMetadata) {}
+
+    /// Uploaded parts are already durable; the buffer can't be, as S3 only
+    /// accepts parts of at least 5 MiB.
+    async fn sync(&mut self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    async fn finalize(mut self) -> Result<(), StorageError> {
+        self.upload()?;
+        let upload = self.upload.take().unwrap_or_else(Upload::new);
+        self.repository.finish(&self.filename, upload).await
+    }
+
+    /// Uploaded parts can't be resumed from, so dropping the session aborts
+    /// the multipart upload and frees them.
+    async fn abandon(self) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+impl Drop for S3Session {
+    fn drop(&mut self) {
+        let Some(upload_id) = self.upload.take().and_then(|u| u.upload_id) else {
+            return;
+        };
+        let repository = self.repository.clone();
+        let key = repository.key_for(&self.filename);
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move { repository.abort_multipart(&key, &upload_id).await });
+        }
+    }
+}
+
+/// Text of the first `<tag>` element, unescaped.
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    xml_blocks(xml, tag).first().map(|value| unescape(value))
+}
+
+/// Inner text of every `<tag>...</tag>` element, in order.
+fn xml_blocks<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut blocks = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        let Some(end) = after.find(&close) else {
+            break;
+        };
+        blocks.push(&after[..end]);
+        rest = &after[end + close.len()..];
+    }
+    blocks
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,560 @@
+use std::collections::{HashMap, HashSet, hash_map::Entry};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::core::domain::{
+    command::entities::IncomingTransfer,
+    journal::{
+        entities::{JournalError, TransferId, TransferOutcome, TransferRecord, TransferStatus},
+        ports::TransferJournal,
+    },
+    queue::entities::unix_now,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS transfers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    peer TEXT,
+    filename TEXT NOT NULL,
+    size INTEGER,
+    block_size INTEGER,
+    started_at INTEGER NOT NULL,
+    ended_at INTEGER,
+    status TEXT NOT NULL DEFAULT 'in_progress',
+    error TEXT,
+    final_path TEXT,
+    blocks_received INTEGER NOT NULL DEFAULT 0,
+    sha256 TEXT
+);
+CREATE TABLE IF NOT EXISTS blocks (
+    transfer_id INTEGER NOT NULL REFERENCES transfers(id) ON DELETE CASCADE,
+    block_index INTEGER NOT NULL,
+    PRIMARY KEY (transfer_id, block_index)
+) WITHOUT ROWID;
+";
+
+/// Keeps the transfer journal in a SQLite database.
+///
+/// Every transfer gets a row in `transfers`; the blocks of transfers that did
+/// not complete stay in `blocks` so they can be resumed. Recorded blocks are
+/// kept in memory and written in one transaction per `flush`, so a block costs
+/// no database round trip. The connection runs in WAL mode without syncing
+/// every commit: a crash may lose the last flushes, whose blocks are then
+/// received again.
+#[derive(Clone)]
+pub struct SqliteTransferJournal {
+    conn: Arc<Mutex<Connection>>,
+    // blocks of the transfers begun or resumed since the journal was opened,
+    // until they finish: a transfer in here is being received
+    active: Arc<Mutex<HashMap<TransferId, Blocks>>>,
+}
+
+/// What the journal knows of a transfer's blocks, flushed or not.
+struct Blocks {
+    received: HashSet<u64>,
+    // recorded since the last flush
+    unflushed: Vec<u64>,
+    block_size: Option<u64>,
+}
+
+impl SqliteTransferJournal {
+    /// Open the journal at `path`, creating the file and its parent directory if needed.
+    pub fn open(path: &Path) -> Result<Self, JournalError> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| JournalError::Database(format!("{}: {}", parent.display(), e)))?;
+        }
+        let conn = Connection::open(path).map_err(database_error)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL; PRAGMA foreign_keys = ON;",
+        )
+        .map_err(database_error)?;
+        conn.execute_batch(SCHEMA).map_err(database_error)?;
+        // Journals created before content hashes were recorded lack the column.
+        if conn
+            .prepare("SELECT sha256 FROM transfers LIMIT 0")
+            .is_err()
+        {
+            conn.execute_batch("ALTER TABLE transfers ADD COLUMN sha256 TEXT")
+                .map_err(database_error)?;
+        }
+        Ok(SqliteTransferJournal {
+            conn: Arc::new(Mutex::new(conn)),
+            active: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    fn active(&self) -> std::sync::MutexGuard<'_, HashMap<TransferId, Blocks>> {
+        self.active.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run `f` on the blocks of transfer `id`, loading them from the database
+    /// the first time.
+    async fn with_blocks<T>(
+        &self,
+        id: TransferId,
+        f: impl FnOnce(&mut Blocks) -> T,
+    ) -> Result<T, JournalError> {
+        if let Some(blocks) = self.active().get_mut(&id) {
+            return Ok(f(blocks));
+        }
+        let loaded = self
+            .with_conn(move |conn| load_blocks(conn, id))
+            .await?
+            .ok_or(JournalError::UnknownTransfer(id))?;
+        Ok(f(self.active().entry(id).or_insert(loaded)))
+    }
+
+    /// Run `f` on the connection without blocking the runtime.
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, JournalError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut conn).map_err(database_error)
+        })
+        .await
+        .map_err(|e| JournalError::Database(e.to_string()))?
+    }
+}
+
+/// The flushed blocks of transfer `id`, None if there is no such transfer.
+fn load_blocks(conn: &Connection, id: TransferId) -> rusqlite::Result<Option<Blocks>> {
+    let Some(block_size) = conn
+        .query_row(
+            "SELECT block_size FROM transfers WHERE id = ?1",
+            params![id.0],
+            |row| row.get::<_, Option<i64>>(0),
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let mut stmt = conn.prepare("SELECT block_index FROM blocks WHERE transfer_id = ?1")?;
+    let received = stmt
+        .query_map(params![id.0], |row| row.get::<_, i64>(0))?
+        .map(|index| index.map(|i| i as u64))
+        .collect::<rusqlite::Result<HashSet<_>>>()?;
+    Ok(Some(Blocks {
+        received,
+        unflushed: Vec::new(),
+        block_size: block_size.map(|s| s as u64),
+    }))
+}
+
+fn database_error(err: rusqlite::Error) -> JournalError {
+    JournalError::Database(err.to_string())
+}
+
+fn require(id: TransferId, found: Option<()>) -> Result<(), JournalError> {
+    found.ok_or(JournalError::UnknownTransfer(id))
+}
+
+impl TransferJournal for SqliteTransferJournal {
+    async fn begin(
+        &self,
+        transfer: &IncomingTransfer,
+        block_size: Option<u64>,
+    ) -> Result<TransferId, JournalError> {
+        let peer = transfer.peer.map(|p| p.to_string());
+        let filename = transfer.filename.clone();
+        let size = transfer.filesize.map(|s| s as i64);
+        let sha256 = transfer.sha256.clone();
+        let size_of_blocks = block_size.map(|s| s as i64);
+        let id = self
+            .with_conn(move |conn| {
+                conn.execute(
+                    "INSERT INTO transfers (peer, filename, size, block_size, started_at, sha256)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        peer,
+                        filename,
+                        size,
+                        size_of_blocks,
+                        unix_now() as i64,
+                        sha256
+                    ],
+                )?;
+                Ok(TransferId(conn.last_insert_rowid()))
+            })
+            .await?;
+        self.active().insert(
+            id,
+            Blocks {
+                received: HashSet::new(),
+                unflushed: Vec::new(),
+                block_size,
+            },
+        );
+        Ok(id)
+    }
+
+    async fn resume(
+        &self,
+        transfer: &IncomingTransfer,
+    ) -> Result<Option<TransferId>, JournalError> {
+        let Some(sha256) = transfer.sha256.clone() else {
+            return Ok(None);
+        };
+        let peer = transfer.peer.map(|p| p.to_string());
+        let filename = transfer.filename.clone();
+        let size = transfer.filesize.map(|s| s as i64);
+        let candidate = self
+            .with_conn(move |conn| {
+                let latest = conn
+                    .query_row(
+                        "SELECT id, size, sha256, status FROM transfers
+                         WHERE filename = ?1 AND status != ?2 ORDER BY id DESC LIMIT 1",
+                        params![filename, <&str>::from(TransferStatus::Rejected)],
+                        |row| {
+                            Ok((
+                                TransferId(row.get::<_, i64>(0)?),
+                                row.get::<_, Option<i64>>(1)?,
+                                row.get::<_, Option<String>>(2)?,
+                                row.get::<_, String>(3)?,
+                            ))
+                        },
+                    )
+                    .optional()?;
+                let Some((id, latest_size, latest_sha256, status)) = latest else {
+                    return Ok(None);
+                };
+                let interrupted = status == <&str>::from(TransferStatus::Failed)
+                    || status == <&str>::from(TransferStatus::InProgress);
+                if !interrupted || latest_size != size || latest_sha256.as_ref() != Some(&sha256) {
+                    return Ok(None);
+                }
+                Ok(load_blocks(conn, id)?.map(|blocks| (id, blocks)))
+            })
+            .await?;
+        let Some((id, blocks)) = candidate else {
+            return Ok(None);
+        };
+        // In progress and in `active` means another connection is receiving
+        // it; in progress but not in `active`, that an earlier run stopped.
+        match self.active().entry(id) {
+            Entry::Occupied(_) => return Err(JournalError::InProgress(id)),
+            Entry::Vacant(entry) => {
+                entry.insert(blocks);
+            }
+        }
+        let reopened = self
+            .with_conn(move |conn| {
+                conn.execute(
+                    "UPDATE transfers SET peer = ?2, status = ?3, error = NULL, ended_at = NULL
+                     WHERE id = ?1",
+                    params![id.0, peer, <&str>::from(TransferStatus::InProgress)],
+                )
+            })
+            .await;
+        if let Err(e) = reopened {
+            self.active().remove(&id);
+            return Err(e);
+        }
+        Ok(Some(id))
+    }
+
+    async fn record_block(
+        &self,
+        id: TransferId,
+        index: u64,
+        size: u64,
+    ) -> Result<(), JournalError> {
+        self.with_blocks(id, |blocks| {
+            if blocks.received.insert(index) {
+                blocks.unflushed.push(index);
+            }
+            blocks.block_size.get_or_insert(size);
+        })
+        .await
+    }
+
+    async fn block_size(&self, id: TransferId) -> Result<Option<u64>, JournalError> {
+        self.with_blocks(id, |blocks| blocks.block_size).await
+    }
+
+    async fn has_block(&self, id: TransferId, index: u64) -> Result<bool, JournalError> {
+        self.with_blocks(id, |blocks| blocks.received.contains(&index))
+            .await
+    }
+
+    async fn block_count(&self, id: TransferId) -> Result<u64, JournalError> {
+        self.with_blocks(id, |blocks| blocks.received.len() as u64)
+            .await
+    }
+
+    async fn received_blocks(&self, id: TransferId) -> Result<Vec<u64>, JournalError> {
+        self.with_blocks(id, |blocks| {
+            let mut indices: Vec<u64> = blocks.received.iter().copied().collect();
+            indices.sort_unstable();
+            indices
+        })
+        .await
+    }
+
+    async fn flush(&self, id: TransferId) -> Result<(), JournalError> {
+        let (indices, block_size) = self
+            .with_blocks(id, |blocks| {
+                (std::mem::take(&mut blocks.unflushed), blocks.block_size)
+            })
+            .await?;
+        if indices.is_empty() {
+            return Ok(());
+        }
+        let pending = indices.clone();
+        let written = self
+            .with_conn(move |conn| {
+                let tx = conn.transaction()?;
+                let mut inserted = 0;
+                {
+                    let mut insert = tx.prepare(
+                        "INSERT OR IGNORE INTO blocks (transfer_id, block_index) VALUES (?1, ?2)",
+                    )?;
+                    for index in &pending {
+                        inserted += insert.execute(params![id.0, *index as i64])?;
+                    }
+                }
+                tx.execute(
+                    "UPDATE transfers SET block_size = COALESCE(block_size, ?2),
+                     blocks_received = blocks_received + ?3 WHERE id = ?1",
+                    params![id.0, block_size.map(|s| s as i64), inserted as i64],
+                )?;
+                tx.commit()
+            })
+            .await;
+        if written.is_err() {
+            // Keep them for the next flush.
+            self.with_blocks(id, |blocks| blocks.unflushed.extend(indices))
+                .await?;
+        }
+        written
+    }
+
+    async fn finish(&self, id: TransferId, outcome: &TransferOutcome) -> Result<(), JournalError> {
+        match self.flush(id).await {
+            Ok(()) => {}
+            Err(JournalError::UnknownTransfer(id)) => {
+                return Err(JournalError::UnknownTransfer(id));
+            }
+            // The transfer still has to be closed; its last blocks are received again.
+            Err(e) => eprintln!(
+                "Failed to journal the last blocks of transfer {}: {}",
+                id.0,
+                String::from(e)
+            ),
+        }
+        let outcome = outcome.clone();
+        let found = self
+            .with_conn(move |conn| {
+                let now = unix_now() as i64;
+                let tx = conn.transaction()?;
+                let exists = tx
+                    .query_row(
+                        "SELECT 1 FROM transfers WHERE id = ?1",
+                        params![id.0],
+                        |_| Ok(()),
+                    )
+                    .optional()?;
+                if exists.is_none() {
+                    return Ok(None);
+                }
+                match &outcome {
+                    TransferOutcome::Completed { final_path } => {
+                        tx.execute(
+                            "UPDATE transfers SET status = ?2, error = NULL, final_path = ?3,
+                             ended_at = ?4 WHERE id = ?1",
+                            params![
+                                id.0,
+                                <&str>::from(TransferStatus::Completed),
+                                final_path,
+                                now
+                            ],
+                        )?;
+                        // Nothing left to resume.
+                        tx.execute("DELETE FROM blocks WHERE transfer_id = ?1", params![id.0])?;
+                    }
+                    TransferOutcome::Failed(error) | TransferOutcome::Rejected(error) => {
+                        let status = match outcome {
+                            TransferOutcome::Rejected(_) => TransferStatus::Rejected,
+                            _ => TransferStatus::Failed,
+                        };
+                        tx.execute(
+                            "UPDATE transfers SET status = ?2, error = ?3, ended_at = ?4
+                             WHERE id = ?1 AND status = ?5",
+                            params![
+                                id.0,
+                                <&str>::from(status),
+                                error,
+                                now,
+                                <&str>::from(TransferStatus::InProgress)
+                            ],
+                        )?;
+                    }
+                }
+                tx.commit()?;
+                Ok(Some(()))
+            })
+            .await?;
+        self.active().remove(&id);
+        require(id, found)
+    }
+
+    async fn history(&self, limit: usize) -> Result<Vec<TransferRecord>, JournalError> {
+        let rows = self
+            .with_conn(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, peer, filename, size, block_size, started_at, ended_at, status,
+                            error, final_path, blocks_received
+                     FROM transfers ORDER BY id DESC LIMIT ?1",
+                )?;
+                let rows = stmt
+                    .query_map(params![limit as i64], |row| {
+                        Ok((
+                            TransferRecord {
+                                id: TransferId(row.get(0)?),
+                                peer: row.get(1)?,
+                                filename: row.get(2)?,
+                                size: row.get::<_, Option<i64>>(3)?.map(|s| s as u64),
+                                block_size: row.get::<_, Option<i64>>(4)?.map(|s| s as u64),
+                                started_at: row.get::<_, i64>(5)? as u64,
+                                ended_at: row.get::<_, Option<i64>>(6)?.map(|t| t as u64),
+                                status: TransferStatus::InProgress,
+                                error: row.get(8)?,
+                                final_path: row.get(9)?,
+                                blocks_received: row.get::<_, i64>(10)? as u64,
+                            },
+                            row.get::<_, String>(7)?,
+                        ))
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(rows)
+            })
+            .await?;
+
+        rows.into_iter()
+            .map(|(record, status)| {
+                Ok(TransferRecord {
+                    status: TransferStatus::try_from(status.as_str())?,
+                    ..record
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::core::domain::command::entities::TransferKind;
+
+    /// A fresh directory under the system temp dir, removed on drop.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("ferrisshare-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Scratch(path)
+        }
+
+        fn journal(&self) -> SqliteTransferJournal {
+            SqliteTransferJournal::open(&self.0.join("journal.db")).unwrap()
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn announced(filesize: u64, sha256: &str) -> IncomingTransfer {
+        IncomingTransfer {
+            kind: TransferKind::Receive,
+            peer: None,
+            filename: "a.bin".to_string(),
+            filesize: Some(filesize),
+            sha256: Some(sha256.repeat(32)),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_flushed_blocks_survive_a_restart() {
+        let dir = Scratch::new("journal-restart");
+        let journal = dir.journal();
+        let id = journal.begin(&announced(4096, "ab"), None).await.unwrap();
+        journal.record_block(id, 0, 1024).await.unwrap();
+        journal.record_block(id, 1, 1024).await.unwrap();
+        journal.flush(id).await.unwrap();
+        journal.record_block(id, 2, 1024).await.unwrap();
+        // Stopped without finishing: the transfer stays in progress.
+        drop(journal);
+
+        let journal = dir.journal();
+        assert_eq!(
+            journal.resume(&announced(4096, "ab")).await.unwrap(),
+            Some(id)
+        );
+        assert_eq!(journal.received_blocks(id).await.unwrap(), vec![0, 1]);
+        assert_eq!(journal.block_size(id).await.unwrap(), Some(1024));
+
+        // Finishing flushes what is left.
+        journal.record_block(id, 2, 1024).await.unwrap();
+        journal
+            .finish(id, &TransferOutcome::Failed("cut".to_string()))
+            .await
+            .unwrap();
+        drop(journal);
+
+        let journal = dir.journal();
+        assert_eq!(
+            journal.resume(&announced(4096, "ab")).await.unwrap(),
+            Some(id)
+        );
+        assert_eq!(journal.received_blocks(id).await.unwrap(), vec![0, 1, 2]);
+        let history = journal.history(1).await.unwrap();
+        assert_eq!(history[0].blocks_received, 3);
+        assert_eq!(history[0].status, TransferStatus::InProgress);
+    }
+
+    #[tokio::test]
+    async fn only_the_same_interrupted_transfer_is_resumed() {
+        let dir = Scratch::new("journal-resume");
+        let journal = dir.journal();
+        let id = journal.begin(&announced(4096, "ab"), None).await.unwrap();
+        journal.record_block(id, 0, 1024).await.unwrap();
+        assert!(matches!(
+            journal.resume(&announced(4096, "ab")).await,
+            Err(JournalError::InProgress(_))
+        ));
+        journal
+            .finish(id, &TransferOutcome::Failed("cut".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(journal.resume(&announced(4096, "cd")).await.unwrap(), None);
+        assert_eq!(journal.resume(&announced(8192, "ab")).await.unwrap(), None);
+        assert_eq!(
+            journal.resume(&announced(4096, "ab")).await.unwrap(),
+            Some(id)
+        );
+
+        let done = TransferOutcome::Completed {
+            final_path: "a.bin".to_string(),
+        };
+        journal.finish(id, &done).await.unwrap();
+        assert_eq!(journal.resume(&announced(4096, "ab")).await.unwrap(), None);
+        assert!(journal.received_blocks(id).await.unwrap().is_empty());
+    }
+}
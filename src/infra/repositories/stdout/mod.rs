@@ -0,0 +1 @@
+pub mod stdout_storage_repository;
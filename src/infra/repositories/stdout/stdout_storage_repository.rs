@@ -0,0 +1,152 @@
+use tokio::io::{AsyncWriteExt, BufWriter, Stdout};
+
+use crate::core::domain::storage::{
+    entities::{FileMetadata, ManifestEntry, StorageError, YeetBlock},
+    ports::{StorageRepository, StorageSession},
+    services::ZEROS,
+};
+
+/// Writes the incoming transfer to the process stdout instead of a file.
+///
+/// Stdout cannot seek, so blocks must arrive in order: a block whose offset
+/// is not exactly the number of bytes already written is rejected.
+#[derive(Clone, Default)]
+pub struct StdoutStorageRepository;
+
+impl StdoutStorageRepository {
+    pub fn new() -> Self {
+        StdoutStorageRepository
+    }
+}
+
+impl StorageRepository for StdoutStorageRepository {
+    type Session = StdoutSession;
+
+    async fn open_file(
+        &self,
+        _filename: &str,
+        _size: Option<u64>,
+    ) -> Result<StdoutSession, StorageError> {
+        Ok(StdoutSession {
+            out: BufWriter::new(tokio::io::stdout()),
+            written: 0,
+        })
+    }
+
+    /// What was written went down the pipe.
+    async fn resume_file(
+        &self,
+        _filename: &str,
+        _size: Option<u64>,
+        _offset: u64,
+    ) -> Result<StdoutSession, StorageError> {
+        Err(StorageError::Unknown(
+            "stdout can't go on with an earlier transfer".to_string(),
+        ))
+    }
+
+    /// Nothing is kept, so there is nothing to list.
+    async fn list_files(&self) -> Result<Vec<ManifestEntry>, StorageError> {
+        Ok(Vec::new())
+    }
+
+    async fn delete_file(&self, _filename: &str) -> Result<(), StorageError> {
+        Err(StorageError::FileNotFound)
+    }
+
+    async fn read_range(
+        &self,
+        _filename: &str,
+        _offset: u64,
+        _len: u64,
+    ) -> Result<Vec<u8>, StorageError> {
+        Err(StorageError::FileNotFound)
+    }
+
+    async fn file_size(&self, _filename: &str) -> Result<u64, StorageError> {
+        Err(StorageError::FileNotFound)
+    }
+
+    fn location(&self, _filename: &str) -> String {
+        "stdout".to_string()
+    }
+}
+
+/// One transfer streamed to stdout; each transfer starts from an empty stream.
+pub struct StdoutSession {
+    out: BufWriter<Stdout>,
+    // Bytes of this transfer written so far. Lives and dies with the session,
+    // so a transfer abandoned midway doesn't shift the offsets of the next.
+    written: u64,
+}
+
+impl StorageSession for StdoutSession {
+    async fn write_block(
+        &mut self,
+        offset: u64,
+        _block: &YeetBlock,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        if offset != self.written {
+            return Err(StorageError::Unknown(format!(
+                "Out of order block at offset {} (expected {})",
+                offset, self.written
+            )));
+        }
+
+        self.out
+            .write_all(data)
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    async fn write_hole(&mut self, offset: u64, len: u64) -> Result<(), StorageError> {
+        if offset != self.written {
+            return Err(StorageError::Unknown(format!(
+                "Out of order hole at offset {} (expected {})",
+                offset, self.written
+            )));
+        }
+
+        let mut left = len;
+        while left > 0 {
+            let n = left.min(ZEROS.len() as u64);
+            self.out
+                .write_all(&ZEROS[..n as usize])
+                .await
+                .map_err(|e| StorageError::Unknown(e.to_string()))?;
+            left -= n;
+        }
+        self.written += len;
+        Ok(())
+    }
+
+    async fn copy_range(
+        &mut self,
+        _offset: u64,
+        _len: u64,
+        _dst_offset: u64,
+    ) -> Result<(), StorageError> {
+        Err(StorageError::FileNotFound)
+    }
+
+    /// There is no file to apply it to.
+    fn set_metadata(&mut self, _metadata: FileMetadata) {}
+
+    async fn sync(&mut self) -> Result<(), StorageError> {
+        self.out
+            .flush()
+            .await
+            .map_err(|e| StorageError::Unknown(e.to_string()))
+    }
+
+    async fn finalize(mut self) -> Result<(), StorageError> {
+        self.sync().await
+    }
+
+    async fn abandon(mut self) -> Result<(), StorageError> {
+        self.sync().await
+    }
+}
@@ -1,27 +1,265 @@
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use tokio::{net::TcpStream, sync::mpsc};
 
 use ferrisshare::{
-    application::config::Config,
+    application::config::{Config, OutputMode},
     core::domain::{
         command::services::CommandServiceImpl,
+        discovery::{
+            entities::PeerAnnouncement, ports::DiscoveryService as _,
+            services::MulticastDiscoveryService,
+        },
+        encryption::{
+            entities::NodeKey,
+            services::{EncryptingStorageRepository, decrypt, load_or_create_key},
+        },
+        forward::{
+            entities::RetryPolicy,
+            ports::ForwardService as _,
+            services::{ForwardServiceImpl, ForwardingStorageRepository},
+        },
+        hook::{
+            entities::{HookJob, HookSettings},
+            ports::HookService as _,
+            services::HookServiceImpl,
+        },
+        journal::{entities::TransferStatus, ports::TransferJournal as _},
         network::{ports::NetworkService as _, services::NetworkServiceImpl},
+        queue::entities::unix_now,
+        relay::services::RelayClientImpl,
+        sender::services::TcpSenderService,
+        storage::{
+            ports::{ContentLookup, StorageRepository},
+            services::NoContentLookup,
+        },
+    },
+    infra::repositories::{
+        cas::cas_storage_repository::CasStorageRepository,
+        fs::fs_storage_repository::FSStorageRepository,
+        json::json_queue_repository::JsonQueueRepository,
+        memory::in_memory_storage_repository::InMemoryStorageRepository,
+        sqlite::sqlite_transfer_journal::SqliteTransferJournal,
+        stdout::stdout_storage_repository::StdoutStorageRepository,
     },
-    infra::repositories::fs::fs_storage_repository::FSStorageRepository,
 };
 
+/// Finalized files waiting for the forwarder before SUCCESS is held back.
+const FORWARD_QUEUE: usize = 1024;
+/// Finalized files waiting for a hook worker before SUCCESS is held back.
+const HOOK_QUEUE: usize = 1024;
+
+#[derive(Parser)]
+#[command(name = "ferrisshare")]
+#[command(about = "FerrisShare receiving node, configured with FERRIS_* environment variables", long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show recorded transfers, newest first
+    History {
+        /// number of transfers to show
+        #[arg(short = 'n', long, default_value_t = 20usize)]
+        limit: usize,
+        /// print one JSON object per transfer
+        #[arg(long)]
+        json: bool,
+    },
+    /// Decrypt a file stored while FERRIS_ENCRYPTION_KEY was set
+    Decrypt {
+        /// encrypted file, as found in the storage
+        file: PathBuf,
+        /// where to write the decrypted file (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// key file (defaults to FERRIS_ENCRYPTION_KEY)
+        #[arg(short, long)]
+        key: Option<PathBuf>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
+    let args = Args::parse();
     dotenv().ok();
     let cfg: Config = Config::from_env();
 
+    match args.command {
+        Some(Command::History { limit, json }) => return history(&cfg, limit, json).await,
+        Some(Command::Decrypt { file, output, key }) => {
+            return decrypt_file(&cfg, &file, output, key).await;
+        }
+        None => {}
+    }
+
+    match cfg.ferris_output {
+        OutputMode::Fs => {
+            let storage_repo = FSStorageRepository::new(cfg.ferris_base_path.clone())
+                .with_durability(cfg.ferris_durability);
+            match &cfg.ferris_forward_to {
+                Some(downstream) => {
+                    let (jobs_tx, jobs_rx) = mpsc::channel::<String>(FORWARD_QUEUE);
+                    let forwarder = ForwardServiceImpl::new(
+                        TcpSenderService::default(),
+                        JsonQueueRepository::new(cfg.ferris_forward_queue.clone()),
+                        downstream,
+                        &cfg.ferris_base_path,
+                    )
+                    .with_policy(RetryPolicy {
+                        max_attempts: cfg.ferris_forward_max_attempts,
+                        ..RetryPolicy::default()
+                    })
+                    .with_delete_after(cfg.ferris_forward_delete);
+                    eprintln!(
+                        "Forwarding finalized files to {}, pending in {}",
+                        downstream,
+                        cfg.ferris_forward_queue.display()
+                    );
+                    tokio::spawn(async move { forwarder.run(jobs_rx).await });
+                    if cfg.ferris_hook.is_some() {
+                        // Files are forwarded once their hook kept them.
+                        serve(&cfg, storage_repo, NoContentLookup, Some(jobs_tx)).await
+                    } else {
+                        let storage_repo = ForwardingStorageRepository::new(storage_repo, jobs_tx);
+                        serve(&cfg, storage_repo, NoContentLookup, None).await
+                    }
+                }
+                None => serve(&cfg, storage_repo, NoContentLookup, None).await,
+            }
+        }
+        OutputMode::Stdout => {
+            serve(&cfg, StdoutStorageRepository::new(), NoContentLookup, None).await
+        }
+        OutputMode::Memory => {
+            serve(
+                &cfg,
+                InMemoryStorageRepository::new(),
+                NoContentLookup,
+                None,
+            )
+            .await
+        }
+        OutputMode::Cas => {
+            let storage_repo = CasStorageRepository::new(cfg.ferris_base_path.clone())
+                .with_durability(cfg.ferris_durability);
+            serve(&cfg, storage_repo.clone(), storage_repo, None).await
+        }
+        #[cfg(feature = "s3")]
+        OutputMode::S3 => {
+            use ferrisshare::infra::repositories::s3::{
+                s3_storage_repository::{S3Settings, S3StorageRepository},
+                sigv4::Credentials,
+            };
+            let storage_repo = S3StorageRepository::new(S3Settings {
+                endpoint: cfg.ferris_s3_endpoint.clone(),
+                region: cfg.ferris_s3_region.clone(),
+                bucket: cfg.ferris_s3_bucket.clone().unwrap_or_default(),
+                prefix: cfg.ferris_s3_prefix.clone(),
+                credentials: Credentials {
+                    access_key: cfg.ferris_s3_access_key.clone().unwrap_or_default(),
+                    secret_key: cfg.ferris_s3_secret_key.clone().unwrap_or_default(),
+                },
+            });
+            eprintln!(
+                "Storing received files in s3://{}/{}",
+                cfg.ferris_s3_bucket.as_deref().unwrap_or_default(),
+                cfg.ferris_s3_prefix
+            );
+            serve(&cfg, storage_repo, NoContentLookup, None).await
+        }
+        #[cfg(not(feature = "s3"))]
+        OutputMode::S3 => {
+            eprintln!(
+                "FERRIS_OUTPUT=s3 needs a build with the s3 feature: cargo build --features s3"
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Run the node on `storage_repo`, encrypted when FERRIS_ENCRYPTION_KEY is set.
+/// Files the post-receive hook keeps are passed on to `kept`.
+async fn serve<S, L>(
+    cfg: &Config,
+    storage_repo: S,
+    lookup: L,
+    kept: Option<mpsc::Sender<String>>,
+) -> tokio::io::Result<()>
+where
+    S: StorageRepository + Clone + Send + Sync + 'static,
+    L: ContentLookup + Clone + Send + Sync + 'static,
+{
+    let Some(key_path) = &cfg.ferris_encryption_key else {
+        return run(cfg, storage_repo, lookup, kept).await;
+    };
+    let key = load_or_create_key(key_path).map_err(|e| tokio::io::Error::other(String::from(e)))?;
+    eprintln!(
+        "Encrypting received files with the key in {}",
+        key_path.display()
+    );
+    // Stored content is ciphertext, so there is nothing to deduplicate against.
+    run(
+        cfg,
+        EncryptingStorageRepository::new(storage_repo, &key),
+        NoContentLookup,
+        kept,
+    )
+    .await
+}
+
+async fn run<S, L>(
+    cfg: &Config,
+    storage_repo: S,
+    lookup: L,
+    kept: Option<mpsc::Sender<String>>,
+) -> tokio::io::Result<()>
+where
+    S: StorageRepository + Clone + Send + Sync + 'static,
+    L: ContentLookup + Clone + Send + Sync + 'static,
+{
     let (tx, rx) = mpsc::channel::<TcpStream>(1);
 
-    let storage_repo = FSStorageRepository::new(cfg.ferris_base_path);
+    let journal = SqliteTransferJournal::open(&cfg.ferris_journal)
+        .map_err(|e| tokio::io::Error::other(String::from(e)))?;
+    eprintln!("Recording transfers in {}", cfg.ferris_journal.display());
 
-    let command_service = CommandServiceImpl::new(storage_repo);
+    let mut command_service = CommandServiceImpl::new(storage_repo.clone())
+        .with_content_lookup(lookup)
+        .with_journal(journal)
+        .with_metadata_policy(cfg.ferris_metadata)
+        .with_sync(cfg.ferris_sync);
+    if let Some(hook) = &cfg.ferris_hook {
+        let (jobs_tx, jobs_rx) = mpsc::channel::<HookJob>(HOOK_QUEUE);
+        // Only unencrypted fs storage keeps the received bytes in a file
+        // the hook can read; the others get a plaintext copy.
+        let readable = cfg.ferris_output == OutputMode::Fs && cfg.ferris_encryption_key.is_none();
+        let mut hooks = HookServiceImpl::new(
+            storage_repo,
+            HookSettings {
+                command: hook.clone(),
+                workers: cfg.ferris_hook_workers,
+                timeout: cfg.ferris_hook_timeout,
+                quarantine_dir: cfg.ferris_quarantine_dir.clone(),
+                staging_dir: (!readable).then(|| cfg.ferris_quarantine_dir.join(".staging")),
+            },
+        );
+        if let Some(kept) = kept {
+            hooks = hooks.with_kept(kept);
+        }
+        eprintln!(
+            "Running {} on finalized files with {} workers",
+            hook.display(),
+            cfg.ferris_hook_workers
+        );
+        tokio::spawn(async move { hooks.run(jobs_rx).await });
+        command_service = command_service.with_hooks(jobs_tx);
+    }
     let network_service = NetworkServiceImpl::new(command_service);
 
     let ferrisshare_state = Arc::new(
@@ -33,6 +271,33 @@ async fn main() -> tokio::io::Result<()> {
 
     let ferrisshare_state_clone = ferrisshare_state.clone();
 
+    if cfg.ferris_discovery {
+        let announcement = PeerAnnouncement::new(
+            &cfg.ferris_node_name,
+            cfg.ferris_port,
+            &PeerAnnouncement::CAPABILITIES,
+        );
+        tokio::spawn(async move {
+            if let Err(e) = MulticastDiscoveryService::default()
+                .announce(announcement)
+                .await
+            {
+                eprintln!("Discovery error: {}", String::from(e));
+            }
+        });
+    }
+
+    if let (Some(relay), Some(session)) = (&cfg.ferris_relay, &cfg.ferris_relay_session) {
+        let relay = RelayClientImpl::new(relay);
+        let session = session.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = relay.listen(&session, tx).await {
+                eprintln!("Relay error: {}", String::from(e));
+            }
+        });
+    }
+
     tokio::spawn(async move {
         if let Err(e) = ferrisshare_state_clone.network_service.handler(rx).await {
             eprintln!("Handler error: {}", e);
@@ -51,3 +316,95 @@ async fn main() -> tokio::io::Result<()> {
     }
     Ok(())
 }
+
+async fn history(cfg: &Config, limit: usize, json: bool) -> tokio::io::Result<()> {
+    let journal = SqliteTransferJournal::open(&cfg.ferris_journal)
+        .map_err(|e| tokio::io::Error::other(String::from(e)))?;
+    let records = journal
+        .history(limit)
+        .await
+        .map_err(|e| tokio::io::Error::other(String::from(e)))?;
+
+    if json {
+        for record in records {
+            println!("{}", serde_json::to_string(&record)?);
+        }
+        return Ok(());
+    }
+    if records.is_empty() {
+        eprintln!("No transfers recorded in {}.", cfg.ferris_journal.display());
+        return Ok(());
+    }
+
+    let now = unix_now();
+    for record in records {
+        let status: &str = record.status.into();
+        let took = match record.ended_at {
+            Some(ended_at) => format_secs(ended_at.saturating_sub(record.started_at)),
+            None => "-".to_string(),
+        };
+        let size = record
+            .size
+            .map(|size| size.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let detail = match record.status {
+            TransferStatus::Completed => record.final_path.unwrap_or_default(),
+            _ => record.error.unwrap_or_default(),
+        };
+        let row = format!(
+            "{:>4}  {:<11} {:>4} ago {:>5}  {:<21} {:<24} {:>10}  {}",
+            record.id.0,
+            status,
+            format_secs(now.saturating_sub(record.started_at)),
+            took,
+            record.peer.as_deref().unwrap_or("-"),
+            record.filename,
+            size,
+            detail
+        );
+        println!("{}", row.trim_end());
+    }
+    Ok(())
+}
+
+async fn decrypt_file(
+    cfg: &Config,
+    file: &std::path::Path,
+    output: Option<PathBuf>,
+    key: Option<PathBuf>,
+) -> tokio::io::Result<()> {
+    let key_path = key
+        .or_else(|| cfg.ferris_encryption_key.clone())
+        .ok_or_else(|| {
+            tokio::io::Error::other("No key: pass --key or set FERRIS_ENCRYPTION_KEY")
+        })?;
+    let key = std::fs::read_to_string(&key_path)
+        .map_err(|e| tokio::io::Error::other(format!("{}: {}", key_path.display(), e)))
+        .and_then(|text| {
+            NodeKey::try_from(text.as_str()).map_err(|e| tokio::io::Error::other(String::from(e)))
+        })?;
+    let input = tokio::fs::File::open(file).await?;
+
+    let result = match &output {
+        Some(path) => decrypt(&key, input, tokio::fs::File::create(path).await?).await,
+        None => decrypt(&key, input, tokio::io::stdout()).await,
+    };
+    if let Err(e) = result {
+        // Don't leave plaintext around that failed authentication.
+        if let Some(path) = &output {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        return Err(tokio::io::Error::other(String::from(e)));
+    }
+    Ok(())
+}
+
+/// Compact duration for the history table: 42s, 7m, 3h, 12d.
+fn format_secs(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
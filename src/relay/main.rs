@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, oneshot};
+
+use ferrisshare::core::domain::relay::{entities::RelayMessage, services::read_line_unbuffered};
+
+/// Size of the chunks forwarded between the two peers.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(Parser)]
+#[command(name = "ferrisshare-relay")]
+#[command(about = "Relay piping bytes between two ferrisshare peers that can't reach each other", long_about = None)]
+struct Args {
+    /// address to listen on
+    #[arg(short, long, default_value = "0.0.0.0:9020")]
+    listen: String,
+
+    /// bytes a session may forward, both directions combined (0 = unlimited)
+    #[arg(long, default_value_t = 4 * 1024 * 1024 * 1024)]
+    max_bytes: u64,
+
+    /// bytes per second a session may forward, both directions combined (0 = unlimited)
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    max_rate: u64,
+
+    /// seconds a peer may wait for the other side of its session
+    #[arg(long, default_value_t = 300u64)]
+    pair_timeout: u64,
+}
+
+/// Caps shared by both directions of one session.
+struct SessionBudget {
+    max_bytes: u64,
+    max_rate: u64,
+    started: Instant,
+    forwarded: AtomicU64,
+}
+
+impl SessionBudget {
+    fn new(max_bytes: u64, max_rate: u64) -> Self {
+        SessionBudget {
+            max_bytes,
+            max_rate,
+            started: Instant::now(),
+            forwarded: AtomicU64::new(0),
+        }
+    }
+
+    /// Account for `n` more bytes, failing once the byte cap is exceeded and
+    /// sleeping as long as the session is ahead of its rate.
+    async fn spend(&self, n: u64) -> std::io::Result<()> {
+        let forwarded = self.forwarded.fetch_add(n, Ordering::SeqCst) + n;
+        if self.max_bytes > 0 && forwarded > self.max_bytes {
+            return Err(std::io::Error::other("session byte cap exceeded"));
+        }
+        if self.max_rate > 0 {
+            let due = Duration::from_secs_f64(forwarded as f64 / self.max_rate as f64);
+            let elapsed = self.started.elapsed();
+            if due > elapsed {
+                tokio::time::sleep(due - elapsed).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A peer waiting for the other side of its session.
+struct Waiting {
+    // Distinguishes successive waiters on the same session ID.
+    id: u64,
+    pair: oneshot::Sender<TcpStream>,
+}
+
+#[derive(Default)]
+struct Sessions {
+    waiting: HashMap<String, Waiting>,
+    next_id: u64,
+}
+
+type SharedSessions = Arc<Mutex<Sessions>>;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Arc::new(Args::parse());
+    let listener = TcpListener::bind(&args.listen).await?;
+    println!("Relay listening on {}", args.listen);
+
+    let sessions: SharedSessions = Arc::new(Mutex::new(Sessions::default()));
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let sessions = sessions.clone();
+        let args = args.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, sessions, &args).await {
+                eprintln!("Client {} error: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_client(
+    mut stream: TcpStream,
+    sessions: SharedSessions,
+    args: &Args,
+) -> anyhow::Result<()> {
+    let line = read_line_unbuffered(&mut stream)
+        .await
+        .map_err(|e| anyhow::anyhow!(String::from(e)))?;
+    let session_id = match RelayMessage::try_from(line.as_str()) {
+        Ok(RelayMessage::Relay(session_id)) => session_id,
+        Ok(other) => {
+            let reason = format!("expected RELAY, got {:?}", other);
+            return send(&mut stream, RelayMessage::Nope(reason)).await;
+        }
+        Err(e) => return send(&mut stream, RelayMessage::Nope(String::from(e))).await,
+    };
+
+    // Join the peer already waiting on this session, if any.
+    let waiting = sessions.lock().await.waiting.remove(&session_id);
+    let stream = match waiting {
+        Some(waiting) => match waiting.pair.send(stream) {
+            Ok(()) => return Ok(()),
+            // The waiter left in the meantime: wait in its place.
+            Err(stream) => stream,
+        },
+        None => stream,
+    };
+
+    let (pair, paired) = oneshot::channel();
+    let id = {
+        let mut guard = sessions.lock().await;
+        guard.next_id += 1;
+        let id = guard.next_id;
+        guard
+            .waiting
+            .insert(session_id.clone(), Waiting { id, pair });
+        id
+    };
+
+    let mut stream = stream;
+    let mut peek = [0u8; 1];
+    let outcome = tokio::select! {
+        peer = paired => peer.ok(),
+        // Peers stay silent until PAIRED, so anything readable means they hung up.
+        _ = stream.peek(&mut peek) => None,
+        _ = tokio::time::sleep(Duration::from_secs(args.pair_timeout)) => {
+            let _ = send(&mut stream, RelayMessage::Nope("no peer joined".to_string())).await;
+            None
+        }
+    };
+
+    let Some(mut peer) = outcome else {
+        let mut guard = sessions.lock().await;
+        if guard.waiting.get(&session_id).is_some_and(|w| w.id == id) {
+            guard.waiting.remove(&session_id);
+        }
+        return Ok(());
+    };
+
+    send(&mut stream, RelayMessage::Paired).await?;
+    send(&mut peer, RelayMessage::Paired).await?;
+    println!("Session {} paired", session_id);
+
+    let budget = SessionBudget::new(args.max_bytes, args.max_rate);
+    let (mut a_read, mut a_write) = stream.into_split();
+    let (mut b_read, mut b_write) = peer.into_split();
+    let result = tokio::try_join!(
+        pipe(&mut a_read, &mut b_write, &budget),
+        pipe(&mut b_read, &mut a_write, &budget),
+    );
+    println!(
+        "Session {} closed after {} bytes",
+        session_id,
+        budget.forwarded.load(Ordering::SeqCst)
+    );
+    result?;
+    Ok(())
+}
+
+/// Copy `reader` to `writer` until EOF, charging every chunk to the session.
+///
+/// The relay never looks at the bytes: encrypted transports stay encrypted.
+async fn pipe<R, W>(reader: &mut R, writer: &mut W, budget: &SessionBudget) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            // Half-close so the other side sees EOF but can still answer.
+            writer.shutdown().await?;
+            return Ok(());
+        }
+        budget.spend(n as u64).await?;
+        writer.write_all(&buf[..n]).await?;
+    }
+}
+
+async fn send(stream: &mut TcpStream, msg: RelayMessage) -> anyhow::Result<()> {
+    let line = String::from(msg) + "\n";
+    stream.write_all(line.as_bytes()).await?;
+    Ok(())
+}
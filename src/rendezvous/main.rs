@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use clap::Parser;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, mpsc};
+
+use ferrisshare::core::domain::wormhole::entities::RendezvousMessage;
+
+/// Longest line accepted from a client; PAKE messages are well below this.
+const MAX_LINE: u64 = 4096;
+/// Messages buffered for a side that hasn't joined (or read) yet.
+const MAILBOX_SIZE: usize = 16;
+
+#[derive(Parser)]
+#[command(name = "ferrisshare-rendezvous")]
+#[command(about = "Rendezvous server pairing wormhole senders and receivers", long_about = None)]
+struct Args {
+    /// address to listen on
+    #[arg(short, long, default_value = "0.0.0.0:9010")]
+    listen: String,
+}
+
+/// The half of a nameplate waiting for the receiver to claim it.
+struct Pending {
+    // Distinguishes successive allocations of the same nameplate number.
+    id: u64,
+    to_allocator: mpsc::Sender<String>,
+    from_allocator: mpsc::Receiver<String>,
+}
+
+#[derive(Default)]
+struct Nameplates {
+    pending: HashMap<u32, Pending>,
+    next_id: u64,
+}
+
+type SharedNameplates = Arc<Mutex<Nameplates>>;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let listener = TcpListener::bind(&args.listen).await?;
+    println!("Rendezvous listening on {}", args.listen);
+
+    let nameplates: SharedNameplates = Arc::new(Mutex::new(Nameplates::default()));
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let nameplates = nameplates.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, nameplates).await {
+                eprintln!("Client {} error: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_client(stream: TcpStream, nameplates: SharedNameplates) -> anyhow::Result<()> {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+
+    let Some(first) = read_message(&mut reader).await? else {
+        return Ok(());
+    };
+
+    match first {
+        RendezvousMessage::Allocate => {
+            let (to_claimer, from_allocator) = mpsc::channel(MAILBOX_SIZE);
+            let (to_allocator, from_claimer) = mpsc::channel(MAILBOX_SIZE);
+
+            let (nameplate, id) = {
+                let mut guard = nameplates.lock().await;
+                let nameplate = (1..)
+                    .find(|n| !guard.pending.contains_key(n))
+                    .expect("nameplate space exhausted");
+                guard.next_id += 1;
+                let id = guard.next_id;
+                guard.pending.insert(
+                    nameplate,
+                    Pending {
+                        id,
+                        to_allocator,
+                        from_allocator,
+                    },
+                );
+                (nameplate, id)
+            };
+            println!("Allocated nameplate {}", nameplate);
+            send(&mut write_half, RendezvousMessage::Nameplate(nameplate)).await?;
+
+            let result =
+                relay_messages(&mut reader, &mut write_half, to_claimer, from_claimer).await;
+
+            // Release the nameplate if nobody claimed it before we left.
+            let mut guard = nameplates.lock().await;
+            if guard.pending.get(&nameplate).is_some_and(|p| p.id == id) {
+                guard.pending.remove(&nameplate);
+            }
+            result
+        }
+        RendezvousMessage::Claim(nameplate) => {
+            let pending = nameplates.lock().await.pending.remove(&nameplate);
+            let Some(pending) = pending else {
+                send(
+                    &mut write_half,
+                    RendezvousMessage::Nope("unknown nameplate".to_string()),
+                )
+                .await?;
+                return Ok(());
+            };
+            println!("Nameplate {} claimed", nameplate);
+            send(&mut write_half, RendezvousMessage::Ok).await?;
+
+            relay_messages(
+                &mut reader,
+                &mut write_half,
+                pending.to_allocator,
+                pending.from_allocator,
+            )
+            .await
+        }
+        other => {
+            send(
+                &mut write_half,
+                RendezvousMessage::Nope(format!("expected ALLOCATE or CLAIM, got {:?}", other)),
+            )
+            .await?;
+            Ok(())
+        }
+    }
+}
+
+/// Forward `MSG` lines between this client and the other side of its nameplate
+/// until either of them goes away.
+async fn relay_messages<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    to_peer: mpsc::Sender<String>,
+    mut from_peer: mpsc::Receiver<String>,
+) -> anyhow::Result<()>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    loop {
+        tokio::select! {
+            msg = read_message(reader) => match msg? {
+                Some(RendezvousMessage::Msg(payload)) => {
+                    if to_peer.send(payload).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                Some(other) => {
+                    send(writer, RendezvousMessage::Nope(format!("unexpected {:?}", other))).await?;
+                }
+                None => return Ok(()),
+            },
+            payload = from_peer.recv() => match payload {
+                Some(payload) => send(writer, RendezvousMessage::Msg(payload)).await?,
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+async fn read_message<R>(reader: &mut R) -> anyhow::Result<Option<RendezvousMessage>>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+{
+    let mut line = String::new();
+    let n = (&mut *reader).take(MAX_LINE).read_line(&mut line).await?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        anyhow::bail!("line too long");
+    }
+    RendezvousMessage::try_from(line.as_str())
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!(String::from(e)))
+}
+
+async fn send<W>(writer: &mut W, msg: RendezvousMessage) -> anyhow::Result<()>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let line = String::from(msg) + "\n";
+    writer.write_all(line.as_bytes()).await?;
+    Ok(())
+}
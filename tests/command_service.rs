@@ -0,0 +1,716 @@
+//! Protocol-level tests of `CommandServiceImpl`, driven the way the network
+//! layer drives it, over the in-memory storage and journal.
+
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use ferrisshare::core::domain::{
+    command::{
+        entities::{IncomingTransfer, TransferDecision},
+        ports::{CommandService, TransferGate},
+        services::CommandServiceImpl,
+    },
+    delta::entities::BlockSignature,
+    journal::{
+        entities::TransferStatus, ports::TransferJournal, services::InMemoryTransferJournal,
+    },
+    network::entities::{ProtocolMessage, TransferState},
+};
+use ferrisshare::infra::repositories::memory::in_memory_storage_repository::InMemoryStorageRepository;
+
+type State = Arc<Mutex<TransferState>>;
+
+fn idle() -> State {
+    Arc::new(Mutex::new(TransferState::Idle))
+}
+
+/// Parse `line` and run it, as the network layer does with every command.
+async fn send<S: CommandService>(
+    service: &S,
+    state: &State,
+    line: &str,
+) -> Result<ProtocolMessage, String> {
+    let msg = ProtocolMessage::try_from(line).map_err(String::from)?;
+    service
+        .execute_protocol_command(Arc::clone(state), None, &msg)
+        .await
+        .map_err(String::from)
+}
+
+/// Send block `index` with its data, as a YEET line followed by the bytes.
+async fn yeet<S: CommandService>(
+    service: &S,
+    state: &State,
+    index: u64,
+    data: &[u8],
+) -> Result<ProtocolMessage, String> {
+    let header = send(service, state, &format!("YEET {} {} 0", index, data.len())).await?;
+    assert!(matches!(header, ProtocolMessage::Yeet(_)), "{:?}", header);
+    service
+        .process_binary_data(Arc::clone(state), data)
+        .await
+        .map_err(String::from)
+}
+
+fn content(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+fn service() -> (
+    CommandServiceImpl<InMemoryStorageRepository>,
+    InMemoryStorageRepository,
+) {
+    let storage = InMemoryStorageRepository::new();
+    (CommandServiceImpl::new(storage.clone()), storage)
+}
+
+/// Deltas read stored files back, so they need sync like MANIFEST does.
+fn sync_service() -> (
+    CommandServiceImpl<InMemoryStorageRepository>,
+    InMemoryStorageRepository,
+) {
+    let storage = InMemoryStorageRepository::new();
+    (
+        CommandServiceImpl::new(storage.clone()).with_sync(true),
+        storage,
+    )
+}
+
+#[derive(Clone)]
+struct Refuse;
+
+impl TransferGate for Refuse {
+    async fn review(&self, _transfer: &IncomingTransfer) -> TransferDecision {
+        TransferDecision::Reject("not today".to_string())
+    }
+}
+
+#[tokio::test]
+async fn hello_yeet_stores_the_file_on_mission_accomplished() {
+    let (service, storage) = service();
+    let state = idle();
+    let data = content(2000);
+
+    assert_eq!(
+        send(&service, &state, "HELLO a.bin 2000").await,
+        Ok(ProtocolMessage::Ok)
+    );
+    assert_eq!(
+        yeet(&service, &state, 0, &data[..1024]).await,
+        Ok(ProtocolMessage::OkHousten(0))
+    );
+    assert_eq!(storage.partial("a.bin").as_deref(), Some(&data[..1024]));
+    assert_eq!(storage.file("a.bin"), None);
+    assert_eq!(
+        yeet(&service, &state, 1, &data[1024..]).await,
+        Ok(ProtocolMessage::OkHousten(1))
+    );
+
+    assert_eq!(
+        send(&service, &state, "MISSION-ACCOMPLISHED").await,
+        Ok(ProtocolMessage::Success)
+    );
+    assert_eq!(storage.file("a.bin"), Some(data));
+    assert!(matches!(*state.lock().await, TransferState::Finished));
+    assert_eq!(
+        send(&service, &state, "BYE-RIS").await,
+        Ok(ProtocolMessage::ByeRis)
+    );
+    assert!(matches!(*state.lock().await, TransferState::Closed));
+}
+
+#[tokio::test]
+async fn a_block_sent_twice_is_stored_once() {
+    let (service, storage) = service();
+    let state = idle();
+    let data = content(1024);
+
+    send(&service, &state, "HELLO a.bin 2048").await.unwrap();
+    yeet(&service, &state, 0, &data).await.unwrap();
+    assert_eq!(
+        yeet(&service, &state, 0, &data).await,
+        Ok(ProtocolMessage::Ok)
+    );
+    assert_eq!(storage.partial("a.bin"), Some(data));
+}
+
+#[tokio::test]
+async fn blocks_past_the_announced_size_are_refused() {
+    let (service, storage) = service();
+    let state = idle();
+
+    // HELLO announces no block size: the first block sets it.
+    send(&service, &state, "HELLO a.bin 10").await.unwrap();
+    for (index, block) in [b"abcd", b"efgh"].into_iter().enumerate() {
+        yeet(&service, &state, index as u64, block).await.unwrap();
+    }
+    yeet(&service, &state, 2, b"ij").await.unwrap();
+    assert!(send(&service, &state, "YEET 3 1 0").await.is_err());
+    assert_eq!(
+        send(&service, &state, "MISSION-ACCOMPLISHED").await,
+        Ok(ProtocolMessage::Success)
+    );
+    assert_eq!(storage.file("a.bin"), Some(b"abcdefghij".to_vec()));
+}
+
+#[tokio::test]
+async fn resume_counts_blocks_of_the_announced_size() {
+    let (service, storage) = service();
+    let state = idle();
+    let data = content(2000);
+
+    let resume = format!("RESUME r.bin 2000 {} 512", "ab".repeat(32));
+    assert_eq!(
+        send(&service, &state, &resume).await,
+        Ok(ProtocolMessage::OkResume(0))
+    );
+    for (index, block) in data.chunks(512).enumerate() {
+        assert_eq!(
+            yeet(&service, &state, index as u64, block).await,
+            Ok(ProtocolMessage::OkHousten(index as u64))
+        );
+    }
+    assert_eq!(
+        send(&service, &state, "MISSION-ACCOMPLISHED").await,
+        Ok(ProtocolMessage::Success)
+    );
+    assert_eq!(storage.file("r.bin"), Some(data));
+
+    assert!(matches!(
+        send(&service, &idle(), &resume.replace(" 512", " 0")).await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+}
+
+#[tokio::test]
+async fn stream_ends_with_eos_of_the_received_length() {
+    let (service, storage) = service();
+    let state = idle();
+    let data = content(1500);
+
+    assert_eq!(
+        send(&service, &state, "STREAM s.bin").await,
+        Ok(ProtocolMessage::Ok)
+    );
+    yeet(&service, &state, 0, &data[..1024]).await.unwrap();
+    yeet(&service, &state, 1, &data[1024..]).await.unwrap();
+
+    assert!(
+        send(&service, &state, "MISSION-ACCOMPLISHED")
+            .await
+            .is_err()
+    );
+    assert!(send(&service, &state, "EOS 1499").await.is_err());
+    assert_eq!(storage.file("s.bin"), None);
+    assert_eq!(
+        send(&service, &state, "EOS 1500").await,
+        Ok(ProtocolMessage::Success)
+    );
+    assert_eq!(storage.file("s.bin"), Some(data));
+}
+
+#[tokio::test]
+async fn eos_does_not_end_a_hello_transfer() {
+    let (service, storage) = service();
+    let state = idle();
+
+    send(&service, &state, "HELLO a.bin 4").await.unwrap();
+    yeet(&service, &state, 0, b"abcd").await.unwrap();
+    assert!(send(&service, &state, "EOS 4").await.is_err());
+    assert_eq!(storage.file("a.bin"), None);
+}
+
+#[tokio::test]
+async fn delta_rebuilds_from_copies_and_literals() {
+    let (service, storage) = sync_service();
+    let state = idle();
+    let old = content(4096);
+    storage.insert_file("d.bin", &old);
+
+    let reply = send(&service, &state, "DELTA d.bin 3072 1024")
+        .await
+        .unwrap();
+    assert!(
+        matches!(reply, ProtocolMessage::Delta { .. }),
+        "{:?}",
+        reply
+    );
+    assert_eq!(service.signature_count("d.bin", 1024).await.unwrap(), 4);
+    assert_eq!(
+        service.signature("d.bin", 1024, 3).await.unwrap(),
+        BlockSignature::of(&old[3072..])
+    );
+
+    let literal = vec![9u8; 1024];
+    assert_eq!(
+        send(&service, &state, "COPY 1024 1024").await,
+        Ok(ProtocolMessage::Ok)
+    );
+    assert_eq!(
+        yeet(&service, &state, 0, &literal).await,
+        Ok(ProtocolMessage::OkHousten(0))
+    );
+    assert_eq!(
+        send(&service, &state, "COPY 0 1024").await,
+        Ok(ProtocolMessage::Ok)
+    );
+    assert_eq!(
+        send(&service, &state, "EOS 3072").await,
+        Ok(ProtocolMessage::Success)
+    );
+
+    let mut expected = old[1024..2048].to_vec();
+    expected.extend_from_slice(&literal);
+    expected.extend_from_slice(&old[..1024]);
+    assert_eq!(storage.file("d.bin"), Some(expected));
+}
+
+#[tokio::test]
+async fn copy_is_bounded_by_the_block_size_and_the_existing_copy() {
+    let (service, storage) = sync_service();
+    let state = idle();
+
+    send(&service, &state, "DELTA d.bin 4096 1024")
+        .await
+        .unwrap();
+    // Nothing to copy from.
+    assert!(matches!(
+        send(&service, &state, "COPY 0 1024").await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+
+    storage.insert_file("e.bin", &content(8192));
+    let state = idle();
+    send(&service, &state, "DELTA e.bin 8192 1024")
+        .await
+        .unwrap();
+    assert!(matches!(
+        send(&service, &state, "COPY 0 4096").await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+    assert!(matches!(
+        send(&service, &state, "COPY 7680 1024").await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+    assert_eq!(
+        send(&service, &state, "COPY 0 1024").await,
+        Ok(ProtocolMessage::Ok)
+    );
+}
+
+#[tokio::test]
+async fn delta_block_sizes_are_bounded() {
+    let (service, storage) = sync_service();
+    storage.insert_file("d.bin", &content(4096));
+
+    for line in [
+        "DELTA d.bin 4096 0",
+        "DELTA d.bin 4096 512",
+        "DELTA d.bin 4096 33554432",
+    ] {
+        assert!(
+            matches!(
+                send(&service, &idle(), line).await,
+                Ok(ProtocolMessage::Nope(_))
+            ),
+            "{}",
+            line
+        );
+    }
+    assert!(matches!(
+        send(&service, &idle(), "DELTA d.bin 4096 16777216").await,
+        Ok(ProtocolMessage::Delta { .. })
+    ));
+}
+
+#[tokio::test]
+async fn delta_stays_within_the_announced_size() {
+    let (service, storage) = sync_service();
+    storage.insert_file("d.bin", &content(4096));
+
+    let state = idle();
+    send(&service, &state, "DELTA d.bin 2048 1024")
+        .await
+        .unwrap();
+    send(&service, &state, "COPY 0 1024").await.unwrap();
+    send(&service, &state, "COPY 1024 1024").await.unwrap();
+    assert!(matches!(
+        send(&service, &state, "COPY 2048 1024").await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+    assert!(yeet(&service, &state, 0, b"x").await.is_err());
+
+    // EOS must come once the announced size is reached.
+    let state = idle();
+    send(&service, &state, "DELTA d.bin 3072 1024")
+        .await
+        .unwrap();
+    send(&service, &state, "COPY 0 1024").await.unwrap();
+    assert!(send(&service, &state, "EOS 1024").await.is_err());
+    assert_eq!(storage.file("d.bin"), Some(content(4096)));
+}
+
+#[tokio::test]
+async fn copy_outside_a_delta_is_an_error() {
+    let (service, storage) = service();
+    let state = idle();
+    storage.insert_file("a.bin", &content(2048));
+
+    send(&service, &state, "HELLO a.bin 2048").await.unwrap();
+    assert!(matches!(
+        send(&service, &state, "COPY 0 1024").await,
+        Ok(ProtocolMessage::Error(_))
+    ));
+}
+
+#[tokio::test]
+async fn hole_stores_zeros_without_data() {
+    let (service, storage) = service();
+    let state = idle();
+    let data = content(1024);
+
+    send(&service, &state, "HELLO z.bin 3072").await.unwrap();
+    yeet(&service, &state, 0, &data).await.unwrap();
+    assert_eq!(
+        send(&service, &state, "HOLE 1 1024").await,
+        Ok(ProtocolMessage::OkHousten(1))
+    );
+    // Already received.
+    assert_eq!(
+        send(&service, &state, "HOLE 1 1024").await,
+        Ok(ProtocolMessage::Ok)
+    );
+    yeet(&service, &state, 2, &data).await.unwrap();
+    send(&service, &state, "MISSION-ACCOMPLISHED")
+        .await
+        .unwrap();
+
+    let mut expected = data.clone();
+    expected.extend_from_slice(&[0u8; 1024]);
+    expected.extend_from_slice(&data);
+    assert_eq!(storage.file("z.bin"), Some(expected));
+}
+
+#[tokio::test]
+async fn holes_are_bounded_like_blocks() {
+    let (service, storage) = service();
+    let state = idle();
+
+    send(&service, &state, "HELLO z.bin 2500").await.unwrap();
+    yeet(&service, &state, 0, &content(1024)).await.unwrap();
+    for line in [
+        // Larger than a block, empty, not the next block.
+        "HOLE 1 4096",
+        "HOLE 1 0",
+        "HOLE 2 1024",
+        "HOLE 7 1024",
+    ] {
+        assert!(send(&service, &state, line).await.is_err(), "{}", line);
+    }
+    send(&service, &state, "HOLE 1 1024").await.unwrap();
+    // Past the announced size.
+    assert!(send(&service, &state, "HOLE 2 1024").await.is_err());
+    assert_eq!(
+        send(&service, &state, "HOLE 2 452").await,
+        Ok(ProtocolMessage::OkHousten(2))
+    );
+    send(&service, &state, "MISSION-ACCOMPLISHED")
+        .await
+        .unwrap();
+
+    let mut expected = content(1024);
+    expected.resize(2500, 0);
+    assert_eq!(storage.file("z.bin"), Some(expected));
+}
+
+#[tokio::test]
+async fn rejected_transfers_are_refused_and_journaled() {
+    let storage = InMemoryStorageRepository::new();
+    let journal = InMemoryTransferJournal::new();
+    let service = CommandServiceImpl::new(storage.clone())
+        .with_gate(Refuse)
+        .with_journal(journal.clone());
+    let state = idle();
+
+    assert_eq!(
+        send(&service, &state, "HELLO a.bin 10").await,
+        Ok(ProtocolMessage::Nope("not today".to_string()))
+    );
+    assert_eq!(
+        send(&service, &state, "STREAM s.bin").await,
+        Ok(ProtocolMessage::Nope("not today".to_string()))
+    );
+    assert!(matches!(*state.lock().await, TransferState::Idle));
+    assert_eq!(storage.partial("a.bin"), None);
+
+    let history = journal.history(10).await.unwrap();
+    assert_eq!(history.len(), 2);
+    assert!(history.iter().all(|r| r.status == TransferStatus::Rejected));
+}
+
+#[tokio::test]
+async fn unsafe_filenames_are_refused() {
+    let (service, _) = service();
+    let state = idle();
+
+    for line in [
+        "HELLO ../escape 10",
+        "HELLO /etc/passwd 10",
+        "STREAM ../escape",
+    ] {
+        assert!(
+            matches!(
+                send(&service, &state, line).await,
+                Ok(ProtocolMessage::Nope(_))
+            ),
+            "{}",
+            line
+        );
+        assert!(matches!(*state.lock().await, TransferState::Idle));
+    }
+}
+
+#[tokio::test]
+async fn blocks_need_an_accepted_transfer() {
+    let (service, _) = service();
+    let state = idle();
+
+    assert!(send(&service, &state, "YEET 0 4 0").await.is_err());
+    assert!(
+        send(&service, &state, "MISSION-ACCOMPLISHED")
+            .await
+            .is_err()
+    );
+    assert!(send(&service, &state, "HOLE 0 4").await.is_err());
+}
+
+#[tokio::test]
+async fn compression_is_negotiated_before_the_first_block() {
+    let (service, _) = service();
+    let state = idle();
+
+    send(&service, &state, "HELLO a.bin 2048").await.unwrap();
+    assert!(matches!(
+        send(&service, &state, "COMPRESS brotli").await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+    // Not agreed yet.
+    assert!(
+        send(&service, &state, "YEET 0 4 0 zstd 1024")
+            .await
+            .is_err()
+    );
+    yeet(&service, &state, 0, &content(1024)).await.unwrap();
+    assert!(matches!(
+        send(&service, &state, "COMPRESS zstd").await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+}
+
+#[tokio::test]
+async fn block_sizes_are_checked_before_the_data_is_read() {
+    let (service, _) = service();
+    let state = idle();
+
+    send(&service, &state, "HELLO a.bin 3000").await.unwrap();
+    send(&service, &state, "COMPRESS zstd").await.unwrap();
+    // Past the announced size, and larger than any block may be.
+    for line in ["YEET 0 4 0 zstd 4000", "YEET 0 4 0 zstd 4294967295"] {
+        assert!(send(&service, &state, line).await.is_err(), "{}", line);
+    }
+    yeet(&service, &state, 0, &content(1024)).await.unwrap();
+    for line in [
+        // Larger than the first block.
+        "YEET 1 4 0 zstd 1025",
+        // More on the wire than decoded.
+        "YEET 1 1024 0 zstd 1000",
+        "YEET 1 1000 0 none 1024",
+    ] {
+        assert!(send(&service, &state, line).await.is_err(), "{}", line);
+    }
+    assert!(matches!(
+        send(&service, &state, "YEET 1 4 0 zstd 1024").await,
+        Ok(ProtocolMessage::Yeet(_))
+    ));
+}
+
+#[tokio::test]
+async fn abandoned_transfers_fail_and_keep_their_partial_copy() {
+    let storage = InMemoryStorageRepository::new();
+    let journal = InMemoryTransferJournal::new();
+    let service = CommandServiceImpl::new(storage.clone()).with_journal(journal.clone());
+    let state = idle();
+    let data = content(1024);
+
+    send(&service, &state, "HELLO a.bin 2048").await.unwrap();
+    yeet(&service, &state, 0, &data).await.unwrap();
+    service.abandon(Arc::clone(&state), "connection lost").await;
+
+    let history = journal.history(1).await.unwrap();
+    assert_eq!(history[0].status, TransferStatus::Failed);
+    assert_eq!(history[0].error.as_deref(), Some("connection lost"));
+    assert_eq!(storage.partial("a.bin"), Some(data));
+    assert_eq!(storage.file("a.bin"), None);
+}
+
+#[tokio::test]
+async fn resume_goes_on_from_the_blocks_received() {
+    let (service, storage) = service();
+    let data = content(3000);
+    let sha256 = "ab".repeat(32);
+    let resume = format!("RESUME r.bin 3000 {} 1024", sha256);
+
+    let state = idle();
+    assert_eq!(
+        send(&service, &state, &resume).await,
+        Ok(ProtocolMessage::OkResume(0))
+    );
+    yeet(&service, &state, 0, &data[..1024]).await.unwrap();
+    yeet(&service, &state, 1, &data[1024..2048]).await.unwrap();
+    service.abandon(Arc::clone(&state), "connection lost").await;
+
+    // Other content under the same name starts over.
+    let state = idle();
+    let other = format!("RESUME r.bin 3000 {} 1024", "cd".repeat(32));
+    assert_eq!(
+        send(&service, &state, &other).await,
+        Ok(ProtocolMessage::OkResume(0))
+    );
+    yeet(&service, &state, 0, &data[..1024]).await.unwrap();
+    yeet(&service, &state, 1, &data[1024..2048]).await.unwrap();
+    service.abandon(Arc::clone(&state), "connection lost").await;
+
+    // A different block size can't reuse them.
+    let state = idle();
+    let other = format!("RESUME r.bin 3000 {} 2048", "cd".repeat(32));
+    assert_eq!(
+        send(&service, &state, &other).await,
+        Ok(ProtocolMessage::OkResume(0))
+    );
+    yeet(&service, &state, 0, &data[..2048]).await.unwrap();
+    service.abandon(Arc::clone(&state), "connection lost").await;
+
+    let state = idle();
+    let other = format!("RESUME r.bin 3000 {} 2048", "cd".repeat(32));
+    assert_eq!(
+        send(&service, &state, &other).await,
+        Ok(ProtocolMessage::OkResume(1))
+    );
+    assert_eq!(storage.partial("r.bin").as_deref(), Some(&data[..2048]));
+    yeet(&service, &state, 1, &data[2048..]).await.unwrap();
+    assert_eq!(
+        send(&service, &state, "MISSION-ACCOMPLISHED").await,
+        Ok(ProtocolMessage::Success)
+    );
+    assert_eq!(storage.file("r.bin"), Some(data));
+
+    // Completed transfers are not resumed.
+    let state = idle();
+    assert_eq!(
+        send(&service, &state, &resume).await,
+        Ok(ProtocolMessage::OkResume(0))
+    );
+}
+
+#[tokio::test]
+async fn sync_commands_are_refused_unless_sync_is_enabled() {
+    let (service, storage) = service();
+    let state = idle();
+    storage.insert_file("a.bin", b"keep");
+
+    assert!(matches!(
+        send(&service, &state, "MANIFEST").await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+    assert!(matches!(
+        send(&service, &state, "DELETE a.bin").await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+    assert!(matches!(
+        send(&service, &state, "DELTA a.bin 4 1024").await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+    assert_eq!(storage.file("a.bin"), Some(b"keep".to_vec()));
+}
+
+#[tokio::test]
+async fn sync_commands_go_through_the_gate() {
+    let storage = InMemoryStorageRepository::new();
+    storage.insert_file("a.bin", b"keep");
+    let refused = CommandServiceImpl::new(storage.clone())
+        .with_gate(Refuse)
+        .with_sync(true);
+    let state = idle();
+
+    assert_eq!(
+        send(&refused, &state, "MANIFEST").await,
+        Ok(ProtocolMessage::Nope("not today".to_string()))
+    );
+    assert_eq!(
+        send(&refused, &state, "DELETE a.bin").await,
+        Ok(ProtocolMessage::Nope("not today".to_string()))
+    );
+    assert_eq!(
+        send(&refused, &state, "DELTA a.bin 4 1024").await,
+        Ok(ProtocolMessage::Nope("not today".to_string()))
+    );
+    assert_eq!(storage.file("a.bin"), Some(b"keep".to_vec()));
+
+    let accepted = CommandServiceImpl::new(storage.clone()).with_sync(true);
+    assert_eq!(
+        send(&accepted, &state, "MANIFEST").await,
+        Ok(ProtocolMessage::ManifestRequest)
+    );
+    assert_eq!(
+        send(&accepted, &state, "DELETE a.bin").await,
+        Ok(ProtocolMessage::Ok)
+    );
+    assert_eq!(storage.file("a.bin"), None);
+}
+
+#[tokio::test]
+async fn resume_leaves_a_transfer_in_progress_alone() {
+    let (service, storage) = service();
+    let data = content(3072);
+    let resume = format!("RESUME o.bin 3072 {} 1024", "ab".repeat(32));
+
+    let first = idle();
+    assert_eq!(
+        send(&service, &first, &resume).await,
+        Ok(ProtocolMessage::OkResume(0))
+    );
+    yeet(&service, &first, 0, &data[..1024]).await.unwrap();
+
+    // A second connection can't take over the transfer or cut its file back.
+    let second = idle();
+    assert!(matches!(
+        send(&service, &second, &resume).await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+    assert!(matches!(*second.lock().await, TransferState::Idle));
+    service
+        .abandon(Arc::clone(&second), "connection lost")
+        .await;
+
+    yeet(&service, &first, 1, &data[1024..2048]).await.unwrap();
+    service.abandon(Arc::clone(&first), "connection lost").await;
+
+    // Once the first one failed, one connection resumes it, not two.
+    let third = idle();
+    assert_eq!(
+        send(&service, &third, &resume).await,
+        Ok(ProtocolMessage::OkResume(2))
+    );
+    let fourth = idle();
+    assert!(matches!(
+        send(&service, &fourth, &resume).await,
+        Ok(ProtocolMessage::Nope(_))
+    ));
+    yeet(&service, &third, 2, &data[2048..]).await.unwrap();
+    assert_eq!(
+        send(&service, &third, "MISSION-ACCOMPLISHED").await,
+        Ok(ProtocolMessage::Success)
+    );
+    assert_eq!(storage.file("o.bin"), Some(data));
+}
@@ -0,0 +1,468 @@
+//! `S3StorageRepository` against an in-process S3 stand-in: a minimal HTTP
+//! server keeping objects and multipart uploads in memory, answering the
+//! requests the backend makes the way MinIO does. Signatures aren't checked.
+#![cfg(feature = "s3")]
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use ferrisshare::core::domain::storage::{
+    entities::{StorageError, YeetBlock},
+    ports::{StorageRepository, StorageSession},
+};
+use ferrisshare::infra::repositories::s3::{
+    s3_storage_repository::{S3Settings, S3StorageRepository},
+    sigv4::Credentials,
+};
+
+const BUCKET: &str = "test";
+const MIB: usize = 1024 * 1024;
+/// Keys per ListObjectsV2 page, small so listings span several pages.
+const PAGE: usize = 2;
+
+#[derive(Default)]
+struct Bucket {
+    objects: BTreeMap<String, Vec<u8>>,
+    uploads: HashMap<String, BTreeMap<u32, Vec<u8>>>,
+    next_upload: u64,
+    // keys stored with a single PUT
+    puts: Vec<String>,
+    // sizes of the uploaded parts, in order
+    parts: Vec<usize>,
+    aborted: Vec<String>,
+    lists: usize,
+    gets: usize,
+}
+
+struct Reply {
+    status: u16,
+    etag: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Reply {
+            status,
+            etag: None,
+            body: body.into(),
+        }
+    }
+}
+
+struct StandIn {
+    addr: SocketAddr,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl StandIn {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bucket = Arc::new(Mutex::new(Bucket::default()));
+        let shared = Arc::clone(&bucket);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let bucket = Arc::clone(&shared);
+                tokio::spawn(async move {
+                    let _ = serve(stream, bucket).await;
+                });
+            }
+        });
+        StandIn { addr, bucket }
+    }
+
+    fn repository(&self) -> S3StorageRepository {
+        S3StorageRepository::new(S3Settings {
+            endpoint: format!("http://{}", self.addr),
+            region: "us-east-1".to_string(),
+            bucket: BUCKET.to_string(),
+            prefix: "in/".to_string(),
+            credentials: Credentials {
+                access_key: "minioadmin".to_string(),
+                secret_key: "minioadmin".to_string(),
+            },
+        })
+    }
+
+    fn bucket(&self) -> std::sync::MutexGuard<'_, Bucket> {
+        self.bucket.lock().unwrap()
+    }
+}
+
+/// Answer one request, then close the connection.
+async fn serve(stream: TcpStream, bucket: Arc<Mutex<Bucket>>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut request = line.split_whitespace();
+    let (Some(method), Some(target)) = (request.next(), request.next()) else {
+        return Ok(());
+    };
+    let (method, target) = (method.to_string(), target.to_string());
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    let length = headers
+        .get("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+
+    let reply = handle(
+        &mut bucket.lock().unwrap(),
+        &method,
+        &target,
+        &headers,
+        body,
+    );
+    let mut head = format!(
+        "HTTP/1.1 {} X\r\ncontent-length: {}\r\nconnection: close\r\n",
+        reply.status,
+        reply.body.len()
+    );
+    if let Some(etag) = &reply.etag {
+        head += &format!("etag: {}\r\n", etag);
+    }
+    head += "\r\n";
+    let mut stream = reader.into_inner();
+    stream.write_all(head.as_bytes()).await?;
+    if method != "HEAD" {
+        stream.write_all(&reply.body).await?;
+    }
+    stream.shutdown().await
+}
+
+fn handle(
+    bucket: &mut Bucket,
+    method: &str,
+    target: &str,
+    headers: &HashMap<String, String>,
+    body: Vec<u8>,
+) -> Reply {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let Some(key) = path.strip_prefix(&format!("/{}/", BUCKET)) else {
+        return Reply::new(404, "<Error><Code>NoSuchBucket</Code></Error>");
+    };
+    let key = decode(key);
+    let query: HashMap<String, String> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(k), decode(v))
+        })
+        .collect();
+    let upload_id = query.get("uploadId").cloned();
+
+    match (method, upload_id) {
+        ("GET", None) if query.contains_key("list-type") => list(bucket, &query),
+        ("POST", None) if query.contains_key("uploads") => {
+            bucket.next_upload += 1;
+            let upload_id = format!("upload-{}", bucket.next_upload);
+            bucket.uploads.insert(upload_id.clone(), BTreeMap::new());
+            Reply::new(
+                200,
+                format!(
+                    "<InitiateMultipartUploadResult><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                    upload_id
+                ),
+            )
+        }
+        ("PUT", Some(upload_id)) => {
+            let Some(number) = query.get("partNumber").and_then(|n| n.parse().ok()) else {
+                return Reply::new(400, "<Error><Code>InvalidArgument</Code></Error>");
+            };
+            let Some(upload) = bucket.uploads.get_mut(&upload_id) else {
+                return Reply::new(404, "<Error><Code>NoSuchUpload</Code></Error>");
+            };
+            let size = body.len();
+            upload.insert(number, body);
+            bucket.parts.push(size);
+            Reply {
+                etag: Some(format!("\"etag-{}\"", number)),
+                ..Reply::new(200, "")
+            }
+        }
+        ("POST", Some(upload_id)) => {
+            let Some(parts) = bucket.uploads.remove(&upload_id) else {
+                return Reply::new(404, "<Error><Code>NoSuchUpload</Code></Error>");
+            };
+            let listed = String::from_utf8_lossy(&body).into_owned();
+            let mut object = Vec::new();
+            for number in values(&listed, "PartNumber") {
+                let part = number.parse().ok().and_then(|n: u32| parts.get(&n));
+                let Some(part) = part else {
+                    return Reply::new(400, "<Error><Code>InvalidPart</Code></Error>");
+                };
+                object.extend_from_slice(part);
+            }
+            bucket.objects.insert(key, object);
+            Reply::new(200, "<CompleteMultipartUploadResult/>")
+        }
+        ("DELETE", Some(upload_id)) => {
+            bucket.uploads.remove(&upload_id);
+            bucket.aborted.push(upload_id);
+            Reply::new(204, "")
+        }
+        ("PUT", None) => {
+            bucket.objects.insert(key.clone(), body);
+            bucket.puts.push(key);
+            Reply::new(200, "")
+        }
+        ("DELETE", None) => {
+            bucket.objects.remove(&key);
+            Reply::new(204, "")
+        }
+        ("HEAD", None) => match bucket.objects.get(&key) {
+            // Only the length of the body goes out for a HEAD.
+            Some(object) => Reply::new(200, object.clone()),
+            None => Reply::new(404, ""),
+        },
+        ("GET", None) => {
+            bucket.gets += 1;
+            let Some(object) = bucket.objects.get(&key) else {
+                return Reply::new(404, "<Error><Code>NoSuchKey</Code></Error>");
+            };
+            let range = headers
+                .get("range")
+                .and_then(|r| r.strip_prefix("bytes="))
+                .and_then(|r| r.split_once('-'))
+                .and_then(|(a, b)| Some((a.parse::<usize>().ok()?, b.parse::<usize>().ok()?)));
+            match range {
+                None => Reply::new(200, object.clone()),
+                Some((first, _)) if first >= object.len() => {
+                    Reply::new(416, "<Error><Code>InvalidRange</Code></Error>")
+                }
+                Some((first, last)) => Reply::new(206, &object[first..=last.min(object.len() - 1)]),
+            }
+        }
+        _ => Reply::new(405, "<Error><Code>MethodNotAllowed</Code></Error>"),
+    }
+}
+
+/// One ListObjectsV2 page of `PAGE` keys; the continuation token is the last key.
+fn list(bucket: &mut Bucket, query: &HashMap<String, String>) -> Reply {
+    bucket.lists += 1;
+    let prefix = query.get("prefix").cloned().unwrap_or_default();
+    let after = query.get("continuation-token");
+    let keys: Vec<(&String, &Vec<u8>)> = bucket
+        .objects
+        .iter()
+        .filter(|(key, _)| key.starts_with(&prefix))
+        .filter(|(key, _)| after.is_none_or(|after| *key > after))
+        .collect();
+    let page = &keys[..keys.len().min(PAGE)];
+    let mut xml = String::from("<ListBucketResult>");
+    for (key, data) in page {
+        xml += &format!(
+            "<Contents><Key>{}</Key><LastModified>2026-10-18T12:00:00.000Z</LastModified><Size>{}</Size></Contents>",
+            key,
+            data.len()
+        );
+    }
+    if keys.len() > PAGE {
+        xml += &format!(
+            "<IsTruncated>true</IsTruncated><NextContinuationToken>{}</NextContinuationToken>",
+            page[PAGE - 1].0
+        );
+    } else {
+        xml += "<IsTruncated>false</IsTruncated>";
+    }
+    xml += "</ListBucketResult>";
+    Reply::new(200, xml)
+}
+
+fn values<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    xml.split(&open)
+        .skip(1)
+        .filter_map(|rest| rest.split_once(&close).map(|(value, _)| value))
+        .collect()
+}
+
+fn decode(encoded: &str) -> String {
+    let bytes = encoded.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = encoded
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).unwrap()
+}
+
+fn content(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+/// Write `data` into `session` in blocks of `block` bytes.
+async fn write_all<T: StorageSession>(session: &mut T, data: &[u8], block: usize) {
+    for (index, chunk) in data.chunks(block).enumerate() {
+        session
+            .write_block(
+                (index * block) as u64,
+                &YeetBlock::new(index as u64, chunk.len() as u32, 0),
+                chunk,
+            )
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn small_files_go_out_with_a_single_put() {
+    let s3 = StandIn::start().await;
+    let repository = s3.repository();
+    let data = content(3000);
+
+    let mut session = repository.open_file("small.bin", Some(3000)).await.unwrap();
+    write_all(&mut session, &data, 1024).await;
+    session.finalize().await.unwrap();
+
+    let bucket = s3.bucket();
+    assert_eq!(bucket.objects.get("in/small.bin"), Some(&data));
+    assert_eq!(
+        bucket.objects.get("in/.ferrisshare/sha256/small.bin"),
+        Some(&hex::encode(Sha256::digest(&data)).into_bytes())
+    );
+    assert!(bucket.puts.contains(&"in/small.bin".to_string()));
+    assert!(bucket.parts.is_empty());
+    assert_eq!(bucket.next_upload, 0);
+}
+
+#[tokio::test]
+async fn large_files_are_uploaded_in_8_mib_parts() {
+    let s3 = StandIn::start().await;
+    let repository = s3.repository();
+    let data = content(20 * MIB);
+
+    let mut session = repository
+        .open_file("large.bin", Some(data.len() as u64))
+        .await
+        .unwrap();
+    write_all(&mut session, &data, MIB).await;
+    session.finalize().await.unwrap();
+
+    {
+        let bucket = s3.bucket();
+        assert_eq!(bucket.parts, vec![8 * MIB, 8 * MIB, 4 * MIB]);
+        assert!(bucket.uploads.is_empty());
+        assert!(bucket.aborted.is_empty());
+        assert!(!bucket.puts.contains(&"in/large.bin".to_string()));
+        assert!(bucket.objects.get("in/large.bin") == Some(&data));
+    }
+
+    // Reads across a part boundary come back whole.
+    let at = 8 * MIB - 10;
+    let read = repository
+        .read_range("large.bin", at as u64, 20)
+        .await
+        .unwrap();
+    assert_eq!(read, &data[at..at + 20]);
+    let tail = repository
+        .read_range("large.bin", (data.len() - 5) as u64, 20)
+        .await
+        .unwrap();
+    assert_eq!(tail, &data[data.len() - 5..]);
+    assert_eq!(
+        repository.file_size("large.bin").await.unwrap(),
+        data.len() as u64
+    );
+    assert!(matches!(
+        repository.file_size("missing.bin").await,
+        Err(StorageError::FileNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn dropping_an_unfinished_session_aborts_its_upload() {
+    let s3 = StandIn::start().await;
+    let repository = s3.repository();
+    let data = content(9 * MIB);
+
+    let mut session = repository.open_file("cut.bin", None).await.unwrap();
+    write_all(&mut session, &data, MIB).await;
+    assert_eq!(s3.bucket().parts, vec![8 * MIB]);
+    drop(session);
+
+    // The abort runs on its own task.
+    for _ in 0..100 {
+        if !s3.bucket().aborted.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let bucket = s3.bucket();
+    assert_eq!(bucket.aborted, vec!["upload-1".to_string()]);
+    assert!(bucket.uploads.is_empty());
+    assert!(!bucket.objects.contains_key("in/cut.bin"));
+}
+
+#[tokio::test]
+async fn listing_follows_every_page() {
+    let s3 = StandIn::start().await;
+    let repository = s3.repository();
+    let names = ["a.bin", "b.bin", "c.bin", "d.bin", "e.bin"];
+    for (i, name) in names.iter().enumerate() {
+        let data = content(100 + i);
+        let mut session = repository.open_file(name, None).await.unwrap();
+        write_all(&mut session, &data, 1024).await;
+        session.finalize().await.unwrap();
+    }
+    s3.bucket()
+        .objects
+        .insert("elsewhere/x.bin".to_string(), vec![1]);
+
+    let entries = repository.list_files().await.unwrap();
+    let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+    assert_eq!(paths, names);
+    for (i, entry) in entries.iter().enumerate() {
+        let data = content(100 + i);
+        assert_eq!(entry.size, data.len() as u64);
+        assert_eq!(entry.sha256, hex::encode(Sha256::digest(&data)));
+    }
+    // Five files and their five hashes, two keys a page.
+    assert_eq!(s3.bucket().lists, 5);
+}
+
+#[tokio::test]
+async fn ranges_past_the_end_of_the_offsets_are_refused() {
+    let s3 = StandIn::start().await;
+    let repository = s3.repository();
+
+    assert!(
+        repository
+            .read_range("a.bin", u64::MAX - 100, 10)
+            .await
+            .is_err()
+    );
+    assert_eq!(s3.bucket().gets, 0);
+}